@@ -0,0 +1,67 @@
+//! Starting a server and speaking just enough RESP to benchmark it.
+
+use std::{
+    io::{BufRead, BufReader, Read},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+pub struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts appledore on a free port, without persistence so runs do not
+/// leave files behind, and waits until it accepts connections.
+pub fn start_server() -> (Server, u16) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let child = Command::new(env!("CARGO_BIN_EXE_appledore"))
+        .args(["--bind", "127.0.0.1", "--port", &port.to_string()])
+        .args(["--save", ""])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("failed to start appledore");
+    let server = Server(child);
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return (server, port);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("appledore did not start listening on port {}", port);
+}
+
+pub fn encode(args: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend(format!("${}\r\n", arg.len()).into_bytes());
+        out.extend(*arg);
+        out.extend(b"\r\n");
+    }
+    out
+}
+
+/// Reads one reply, which in these benchmarks is a status, error, integer
+/// or bulk. Returns the bulk's contents when it is one.
+pub fn read_reply(reader: &mut BufReader<TcpStream>, line: &mut String) -> Option<Vec<u8>> {
+    line.clear();
+    reader.read_line(line).unwrap();
+    let len: i64 = line.strip_prefix('$')?.trim_end().parse().unwrap();
+    if len < 0 {
+        return None;
+    }
+    let mut body = vec![0; len as usize + 2];
+    reader.read_exact(&mut body).unwrap();
+    body.truncate(len as usize);
+    Some(body)
+}
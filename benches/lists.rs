@@ -0,0 +1,121 @@
+//! Pushes and pops at both ends of lists and queues of increasing length.
+//! The rate should not depend on the length: every operation is constant
+//! time, however many items the key already holds.
+//!
+//! Run with `cargo bench --bench lists`.
+
+mod common;
+
+use std::{
+    io::{BufReader, Write},
+    net::TcpStream,
+    time::Instant,
+};
+
+use common::{encode, read_reply, start_server};
+
+const SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+/// Operations timed per command and size.
+const OPS: usize = 20_000;
+const PIPELINE: usize = 100;
+/// Items per command while filling a key up to size.
+const FILL_BATCH: usize = 1_000;
+
+struct Client {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    line: String,
+}
+
+impl Client {
+    fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_nodelay(true).unwrap();
+        Client {
+            writer: stream.try_clone().unwrap(),
+            reader: BufReader::new(stream),
+            line: String::new(),
+        }
+    }
+
+    /// Sends `commands` in pipelines of `PIPELINE` and reads the replies.
+    fn run(&mut self, commands: &[Vec<u8>]) {
+        for batch in commands.chunks(PIPELINE) {
+            self.writer.write_all(&batch.concat()).unwrap();
+            for _ in batch {
+                read_reply(&mut self.reader, &mut self.line);
+                assert!(!self.line.starts_with('-'), "error reply: {}", self.line);
+            }
+        }
+    }
+
+    /// Fills `key` with `len` items using `push`, RPUSH or QADD.
+    fn fill(&mut self, push: &[u8], key: &[u8], len: usize) {
+        let item = b"item".as_slice();
+        let commands: Vec<Vec<u8>> = (0..len)
+            .step_by(FILL_BATCH)
+            .map(|start| {
+                let mut args = vec![push, key];
+                args.resize(2 + FILL_BATCH.min(len - start), item);
+                encode(&args)
+            })
+            .collect();
+        self.run(&commands);
+    }
+
+    /// Checks that LPOP and RPOP take from the head and the tail, so their
+    /// columns time a pop at each end. The list holds nothing but "item".
+    fn check_ends(&mut self) {
+        let ends: [(&[u8], &[u8]); 2] = [(b"LPUSH", b"LPOP"), (b"RPUSH", b"RPOP")];
+        for (push, pop) in ends {
+            self.run(&[encode(&[push, b"list", b"end"])]);
+            self.writer.write_all(&encode(&[pop, b"list"])).unwrap();
+            let popped = read_reply(&mut self.reader, &mut self.line);
+            assert_eq!(
+                popped.as_deref(),
+                Some(&b"end"[..]),
+                "{} popped from the wrong end",
+                String::from_utf8_lossy(pop)
+            );
+        }
+    }
+
+    /// Runs `OPS` of `args` and returns how many ran per second.
+    fn rate(&mut self, args: &[&[u8]]) -> f64 {
+        let commands = vec![encode(args); OPS];
+        let started = Instant::now();
+        self.run(&commands);
+        OPS as f64 / started.elapsed().as_secs_f64()
+    }
+}
+
+fn main() {
+    let (_server, port) = start_server();
+    let mut client = Client::connect(port);
+    let columns: [&[&[u8]]; 6] = [
+        &[b"LPUSH", b"list", b"item"],
+        &[b"RPUSH", b"list", b"item"],
+        &[b"LPOP", b"list"],
+        &[b"RPOP", b"list"],
+        &[b"QADD", b"queue", b"item"],
+        &[b"QREAD", b"queue"],
+    ];
+    print!("{:>10}", "length");
+    for args in &columns {
+        let name = String::from_utf8_lossy(args[0]);
+        print!(" {:>12}", format!("{} ops/s", name));
+    }
+    println!();
+    for len in SIZES {
+        client.run(&[encode(&[b"FLUSHALL"])]);
+        client.fill(b"RPUSH", b"list", len);
+        client.fill(b"QADD", b"queue", len);
+        client.check_ends();
+        print!("{:>10}", len);
+        // The keys stay within a few `OPS` items of `len` throughout.
+        for args in &columns {
+            print!(" {:>12.0}", client.rate(args));
+        }
+        println!();
+    }
+}
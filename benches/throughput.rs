@@ -0,0 +1,58 @@
+//! Pipelined SET/GET throughput against a freshly started server, with an
+//! increasing number of concurrent clients.
+//!
+//! Run with `cargo bench --bench throughput`.
+
+mod common;
+
+use std::{
+    io::{BufReader, Write},
+    net::TcpStream,
+    thread,
+    time::Instant,
+};
+
+use common::{encode, read_reply, start_server};
+
+const PIPELINE: usize = 100;
+const ROUNDS: usize = 200;
+
+/// Runs `ROUNDS` pipelines of SETs followed by GETs on the client's own keys.
+fn client(port: u16, id: usize) -> usize {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let value = vec![b'x'; 64];
+    let mut line = String::new();
+    for round in 0..ROUNDS {
+        let mut batch = vec![];
+        for i in 0..PIPELINE {
+            let key = format!("bench:{}:{}", id, (round * PIPELINE + i) % 1000);
+            if i % 2 == 0 {
+                batch.extend(encode(&[b"SET", key.as_bytes(), &value]));
+            } else {
+                batch.extend(encode(&[b"GET", key.as_bytes()]));
+            }
+        }
+        writer.write_all(&batch).unwrap();
+        for _ in 0..PIPELINE {
+            read_reply(&mut reader, &mut line);
+        }
+    }
+    ROUNDS * PIPELINE
+}
+
+fn main() {
+    let (_server, port) = start_server();
+    println!("{:>8} {:>12} {:>14}", "clients", "ops", "ops/sec");
+    for clients in [1, 2, 4, 8, 16, 32] {
+        let started = Instant::now();
+        let handles: Vec<_> = (0..clients)
+            .map(|id| thread::spawn(move || client(port, id)))
+            .collect();
+        let ops: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        let elapsed = started.elapsed().as_secs_f64();
+        println!("{:>8} {:>12} {:>14.0}", clients, ops, ops as f64 / elapsed);
+    }
+}
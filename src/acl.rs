@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, HashSet};
+
+use bytes::Bytes;
+use ring::digest::{digest, SHA256};
+
+use crate::{
+    commands::registry::{self, CommandSpec},
+    error::CommandError,
+    glob::glob_match,
+};
+
+pub const DEFAULT_USER: &str = "default";
+
+const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "string",
+    "list",
+    "hash",
+    "queue",
+    "fast",
+    "slow",
+    "admin",
+    "dangerous",
+    "connection",
+];
+
+fn commands_in_category(category: &str) -> Vec<&'static str> {
+    registry::commands()
+        .iter()
+        .filter(|c| category == "all" || c.acl_categories.contains(&category))
+        .map(|c| c.name)
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    enabled: bool,
+    nopass: bool,
+    /// SHA-256 of each accepted password, hex encoded.
+    passwords: Vec<String>,
+    allowed: HashSet<&'static str>,
+    /// The command rules as given, reset by `+@all`, `-@all` and `reset`.
+    command_rules: Vec<String>,
+    key_patterns: Vec<Vec<u8>>,
+}
+
+fn modifier_error(rule: &str, reason: &str) -> CommandError {
+    CommandError::Other(format!(
+        "Error in ACL SETUSER modifier '{}': {}",
+        rule, reason
+    ))
+}
+
+fn hash_password(password: &[u8]) -> String {
+    digest(&SHA256, password)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl User {
+    fn new(name: &str) -> Self {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            allowed: HashSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            key_patterns: vec![],
+        }
+    }
+
+    /// Applies one ACL SETUSER rule.
+    pub fn apply_rule(&mut self, rule: &[u8]) -> Result<(), CommandError> {
+        let text = String::from_utf8_lossy(rule).into_owned();
+        let lower = text.to_ascii_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.key_patterns = vec![b"*".to_vec()],
+            "resetkeys" => self.key_patterns.clear(),
+            "allcommands" => self.apply_rule(b"+@all")?,
+            "nocommands" => self.apply_rule(b"-@all")?,
+            "reset" => {
+                let name = std::mem::take(&mut self.name);
+                *self = User::new(&name);
+            }
+            _ => match rule.first() {
+                Some(b'>') => {
+                    let hash = hash_password(&rule[1..]);
+                    if !self.passwords.contains(&hash) {
+                        self.passwords.push(hash);
+                    }
+                    self.nopass = false;
+                }
+                Some(b'<') => {
+                    let hash = hash_password(&rule[1..]);
+                    self.passwords.retain(|p| p != &hash);
+                }
+                Some(b'#') => {
+                    let hash = lower[1..].to_string();
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(modifier_error(
+                            &text,
+                            "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters",
+                        ));
+                    }
+                    if !self.passwords.contains(&hash) {
+                        self.passwords.push(hash);
+                    }
+                    self.nopass = false;
+                }
+                Some(b'!') => {
+                    let hash = lower[1..].to_string();
+                    self.passwords.retain(|p| p != &hash);
+                }
+                Some(b'~') => {
+                    let pattern = rule[1..].to_vec();
+                    if self.key_patterns.iter().any(|p| p == b"*") {
+                        return Ok(());
+                    }
+                    if pattern == b"*" {
+                        self.key_patterns = vec![pattern];
+                    } else {
+                        self.key_patterns.push(pattern);
+                    }
+                }
+                Some(b'+') | Some(b'-') => {
+                    let allow = rule[0] == b'+';
+                    let commands = match lower[1..].strip_prefix('@') {
+                        Some(category) => {
+                            if category != "all" && !CATEGORIES.contains(&category) {
+                                return Err(modifier_error(
+                                    &text,
+                                    "Unknown command or category name in ACL",
+                                ));
+                            }
+                            commands_in_category(category)
+                        }
+                        None => match registry::lookup(&lower.as_bytes()[1..]) {
+                            Some(spec) => vec![spec.name],
+                            None => {
+                                return Err(modifier_error(
+                                    &text,
+                                    "Unknown command or category name in ACL",
+                                ))
+                            }
+                        },
+                    };
+                    for command in commands {
+                        if allow {
+                            self.allowed.insert(command);
+                        } else {
+                            self.allowed.remove(command);
+                        }
+                    }
+                    if &lower[1..] == "@all" {
+                        self.command_rules.clear();
+                    }
+                    self.command_rules.push(lower);
+                }
+                _ => return Err(modifier_error(&text, "Syntax error")),
+            },
+        }
+        Ok(())
+    }
+
+    fn check_password(&self, password: &[u8]) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&hash_password(password)))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    pub fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    pub fn commands_description(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    pub fn keys_description(&self) -> String {
+        self.key_patterns
+            .iter()
+            .map(|p| format!("~{}", String::from_utf8_lossy(p)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The user as a line of ACL LIST.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("user {}", self.name)];
+        parts.extend(self.flags().iter().map(|f| f.to_string()));
+        parts.extend(self.passwords.iter().map(|p| format!("#{}", p)));
+        if !self.key_patterns.is_empty() {
+            parts.push(self.keys_description());
+        }
+        parts.push(self.commands_description());
+        parts.join(" ")
+    }
+
+    /// Checks that the user may run `command` against the keys in `args`.
+    pub fn check_command(&self, command: &CommandSpec, args: &[Bytes]) -> Result<(), CommandError> {
+        if !self.allowed.contains(command.name) {
+            return Err(CommandError::NoPerm {
+                user: self.name.clone(),
+                command: command.name.to_string(),
+            });
+        }
+        if self.key_patterns.iter().any(|p| p == b"*") {
+            return Ok(());
+        }
+        for key in command.keys(args) {
+            if !self
+                .key_patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, key, false))
+            {
+                return Err(CommandError::NoPermKey);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The user table. The `default` user always exists; connections start out
+/// authenticated as it unless it has a password.
+pub struct Acl {
+    users: BTreeMap<String, User>,
+}
+
+impl Acl {
+    pub fn new(requirepass: Option<&str>) -> Self {
+        let mut default = User::new(DEFAULT_USER);
+        for rule in ["on", "allkeys", "+@all"] {
+            default.apply_rule(rule.as_bytes()).unwrap();
+        }
+        match requirepass {
+            Some(pass) => default.apply_rule(format!(">{}", pass).as_bytes()).unwrap(),
+            None => default.apply_rule(b"nopass").unwrap(),
+        }
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), default);
+        Acl { users }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Whether new connections are logged in as `default` without AUTH.
+    pub fn default_needs_auth(&self) -> bool {
+        match self.users.get(DEFAULT_USER) {
+            Some(user) => !(user.enabled && user.nopass),
+            None => true,
+        }
+    }
+
+    pub fn authenticate(&self, username: &str, password: &[u8]) -> Result<(), CommandError> {
+        match self.users.get(username) {
+            Some(user) if user.check_password(password) => Ok(()),
+            _ => Err(CommandError::WrongPass),
+        }
+    }
+
+    /// `AUTH <password>`, which only makes sense once `default` has one.
+    pub fn authenticate_default(&self, password: &[u8]) -> Result<(), CommandError> {
+        if !self.default_needs_auth() {
+            return Err(CommandError::Other(
+                "AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string(),
+            ));
+        }
+        self.authenticate(DEFAULT_USER, password)
+    }
+
+    /// Creates the user if needed and applies `rules` in order. Nothing
+    /// changes if any rule is invalid.
+    pub fn set_user(&mut self, name: &str, rules: &[Bytes]) -> Result<(), CommandError> {
+        let mut user = match self.users.get(name) {
+            Some(u) => u.clone(),
+            None => User::new(name),
+        };
+        for rule in rules {
+            user.apply_rule(rule)?;
+        }
+        self.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    pub fn delete_user(&mut self, name: &str) -> bool {
+        self.users.remove(name).is_some()
+    }
+}
+
+pub fn categories() -> &'static [&'static str] {
+    CATEGORIES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(items: &[&str]) -> Vec<Bytes> {
+        items
+            .iter()
+            .map(|r| Bytes::copy_from_slice(r.as_bytes()))
+            .collect()
+    }
+
+    /// The reply `user` gets for running `argv`, as text.
+    fn check(user: &User, argv: &[&str]) -> Result<(), String> {
+        let command = registry::lookup(argv[0].as_bytes()).unwrap();
+        user.check_command(command, &rules(argv))
+            .map_err(|e| e.to_string())
+    }
+
+    fn acl_err(acl: &mut Acl, r: &[&str]) -> String {
+        acl.set_user("u", &rules(r)).unwrap_err().to_string()
+    }
+
+    fn user(acl: &Acl, name: &str) -> User {
+        acl.get(name).unwrap().clone()
+    }
+
+    #[test]
+    fn new_users_are_off_and_may_run_nothing() {
+        let mut acl = Acl::new(None);
+        acl.set_user("u", &[]).unwrap();
+        let u = user(&acl, "u");
+        assert_eq!(u.describe(), "user u off -@all");
+        assert_eq!(
+            check(&u, &["ping"]),
+            Err("NOPERM User u has no permissions to run the 'ping' command".to_string())
+        );
+    }
+
+    #[test]
+    fn command_rules_apply_in_order() {
+        let mut acl = Acl::new(None);
+        acl.set_user("u", &rules(&["on", "allkeys", "+@read", "-get"]))
+            .unwrap();
+        let u = user(&acl, "u");
+        assert_eq!(u.commands_description(), "-@all +@read -get");
+        assert_eq!(check(&u, &["mget", "a", "b"]), Ok(()));
+        assert_eq!(check(&u, &["lrange", "l", "0", "-1"]), Ok(()));
+        assert!(check(&u, &["get", "a"]).unwrap_err().starts_with("NOPERM"));
+        assert!(check(&u, &["set", "a", "1"])
+            .unwrap_err()
+            .starts_with("NOPERM"));
+
+        acl.set_user("u", &rules(&["+@all", "-set"])).unwrap();
+        let u = user(&acl, "u");
+        assert_eq!(u.commands_description(), "+@all -set");
+        assert_eq!(check(&u, &["get", "a"]), Ok(()));
+        assert_eq!(
+            check(&u, &["set", "a", "1"]),
+            Err("NOPERM User u has no permissions to run the 'set' command".to_string())
+        );
+    }
+
+    #[test]
+    fn key_patterns_limit_the_keys_commands_touch() {
+        let mut acl = Acl::new(None);
+        acl.set_user("u", &rules(&["on", "+@all", "~pat*", "~other"]))
+            .unwrap();
+        let u = user(&acl, "u");
+        assert_eq!(u.keys_description(), "~pat* ~other");
+        assert_eq!(check(&u, &["get", "pattern"]), Ok(()));
+        assert_eq!(check(&u, &["mget", "pat", "other"]), Ok(()));
+        assert_eq!(
+            check(&u, &["mget", "pat", "secret"]),
+            Err("NOPERM No permissions to access a key".to_string())
+        );
+        assert_eq!(
+            check(&u, &["set", "Pattern", "x"]),
+            Err("NOPERM No permissions to access a key".to_string())
+        );
+        // Commands without keys don't need a pattern.
+        assert_eq!(check(&u, &["ping"]), Ok(()));
+
+        acl.set_user("u", &rules(&["allkeys", "~ignored"])).unwrap();
+        assert_eq!(user(&acl, "u").keys_description(), "~*");
+        acl.set_user("u", &rules(&["resetkeys"])).unwrap();
+        assert_eq!(
+            check(&user(&acl, "u"), &["get", "pattern"]),
+            Err("NOPERM No permissions to access a key".to_string())
+        );
+    }
+
+    #[test]
+    fn passwords() {
+        let mut acl = Acl::new(None);
+        acl.set_user("u", &rules(&["on", ">secret", ">other"]))
+            .unwrap();
+        assert_eq!(user(&acl, "u").passwords().len(), 2);
+        assert!(acl.authenticate("u", b"secret").is_ok());
+        assert!(acl.authenticate("u", b"other").is_ok());
+        assert_eq!(
+            acl.authenticate("u", b"wrong").unwrap_err().to_string(),
+            "WRONGPASS invalid username-password pair or user is disabled."
+        );
+        assert!(acl.authenticate("nobody", b"secret").is_err());
+
+        acl.set_user("u", &rules(&["<other"])).unwrap();
+        assert!(acl.authenticate("u", b"other").is_err());
+        let hash = hash_password(b"hashed");
+        acl.set_user("u", &rules(&[&format!("#{}", hash)])).unwrap();
+        assert!(acl.authenticate("u", b"hashed").is_ok());
+
+        acl.set_user("u", &rules(&["nopass"])).unwrap();
+        assert!(user(&acl, "u").passwords().is_empty());
+        assert!(acl.authenticate("u", b"anything").is_ok());
+        acl.set_user("u", &rules(&["resetpass"])).unwrap();
+        assert!(acl.authenticate("u", b"anything").is_err());
+
+        acl.set_user("u", &rules(&["nopass", "off"])).unwrap();
+        assert!(acl.authenticate("u", b"anything").is_err());
+    }
+
+    #[test]
+    fn reset_starts_the_user_over() {
+        let mut acl = Acl::new(None);
+        acl.set_user("u", &rules(&["on", ">p", "allkeys", "+@all"]))
+            .unwrap();
+        acl.set_user("u", &rules(&["reset"])).unwrap();
+        let u = user(&acl, "u");
+        assert_eq!(u.describe(), "user u off -@all");
+        assert!(!u.enabled());
+        assert!(u.passwords().is_empty());
+    }
+
+    #[test]
+    fn invalid_rules_leave_the_user_unchanged() {
+        let mut acl = Acl::new(None);
+        acl.set_user("u", &rules(&["on", "+get"])).unwrap();
+        assert_eq!(
+            acl_err(&mut acl, &["off", "+@nosuch"]),
+            "ERR Error in ACL SETUSER modifier '+@nosuch': Unknown command or category name in ACL"
+        );
+        assert_eq!(
+            acl_err(&mut acl, &["-nosuch"]),
+            "ERR Error in ACL SETUSER modifier '-nosuch': Unknown command or category name in ACL"
+        );
+        assert_eq!(
+            acl_err(&mut acl, &["bogus"]),
+            "ERR Error in ACL SETUSER modifier 'bogus': Syntax error"
+        );
+        assert!(acl_err(&mut acl, &["#short"]).contains("64 characters"));
+        let u = user(&acl, "u");
+        assert!(u.enabled());
+        assert_eq!(u.commands_description(), "-@all +get");
+    }
+
+    #[test]
+    fn the_default_user() {
+        let acl = Acl::new(None);
+        assert!(!acl.default_needs_auth());
+        assert_eq!(
+            user(&acl, DEFAULT_USER).describe(),
+            "user default on nopass ~* +@all"
+        );
+        assert!(acl.authenticate_default(b"x").is_err());
+
+        let acl = Acl::new(Some("pw"));
+        assert!(acl.default_needs_auth());
+        assert!(acl.authenticate_default(b"pw").is_ok());
+        assert!(acl.authenticate_default(b"wrong").is_err());
+    }
+}
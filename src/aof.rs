@@ -0,0 +1,431 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    client::Client,
+    commands::registry::{self, CommandSpec},
+    config::AppendFsync,
+    decoder::RequestDecoder,
+    encoder::{encode_resp_array_header, ReplyBuffer},
+    rdb::Snapshot,
+    server::Server,
+    storage::Value,
+};
+
+/// Elements per command when a rewrite turns a collection into commands,
+/// as Redis does.
+const ITEMS_PER_COMMAND: usize = 64;
+/// Bytes read from the log at a time while replaying it.
+const READ_CHUNK: usize = 64 * 1024;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Appends `args` as a RESP command, preceded by a SELECT when `db` is not
+/// the database `current` says the previous command ran in.
+fn encode_command(out: &mut Vec<u8>, current: &mut Option<usize>, db: usize, args: &[Bytes]) {
+    if *current != Some(db) {
+        let index = Bytes::from(db.to_string());
+        encode_args(out, &[Bytes::from_static(b"select"), index]);
+        *current = Some(db);
+    }
+    encode_args(out, args);
+}
+
+fn encode_args(out: &mut Vec<u8>, args: &[Bytes]) {
+    out.extend(encode_resp_array_header(args.len()));
+    for arg in args {
+        out.push(b'$');
+        out.extend_from_slice(arg.len().to_string().as_bytes());
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// The append only file: every write command, in the order the writes
+/// reached storage.
+///
+/// Each shard, numbered across databases, has a lock a write holds from
+/// before it runs until it is logged. Writes to the same shard are logged
+/// in the order they changed it, while writes to different shards run side
+/// by side and only take the log's own lock to append.
+pub struct Aof {
+    state: Mutex<AofState>,
+    order: Vec<Mutex<()>>,
+    rewriting: AtomicBool,
+    last_rewrite_ok: AtomicBool,
+}
+
+/// Commands logged while a rewrite runs, for the end of the new file.
+struct RewriteBuffer {
+    bytes: Vec<u8>,
+    db: Option<usize>,
+}
+
+/// The open log, locked only to append to it or swap it for a rewritten
+/// one.
+pub struct AofState {
+    file: File,
+    fsync: AppendFsync,
+    /// The database the last command in the file ran in.
+    db: Option<usize>,
+    rewrite: Option<RewriteBuffer>,
+}
+
+impl Aof {
+    /// Opens the log for appending. A missing log is created holding the
+    /// data already loaded, say from an RDB snapshot, so turning the AOF on
+    /// does not lose it.
+    pub fn open(server: &Server) -> io::Result<Aof> {
+        let path = server.config.aof_path();
+        let mut db = None;
+        let file = if path.exists() {
+            OpenOptions::new().append(true).open(&path)?
+        } else {
+            let mut out = BufWriter::new(File::create(&path)?);
+            write_snapshot(&Snapshot::take(server), &mut out, &mut db)?;
+            let file = out.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            file
+        };
+        Ok(Aof {
+            state: Mutex::new(AofState {
+                file,
+                fsync: server.config.appendfsync,
+                db,
+                rewrite: None,
+            }),
+            order: (0..server.dbs().len() * server.db(0).shard_count())
+                .map(|_| Mutex::new(()))
+                .collect(),
+            rewriting: AtomicBool::new(false),
+            last_rewrite_ok: AtomicBool::new(true),
+        })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, AofState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the order of the shards numbered `shards`, in index order so
+    /// that two writes cannot deadlock. Taken before any shard of the data
+    /// is locked.
+    pub fn lock_shards(&self, mut shards: Vec<usize>) -> Vec<MutexGuard<'_, ()>> {
+        shards.sort_unstable();
+        shards.dedup();
+        shards
+            .into_iter()
+            .map(|i| self.order[i].lock().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+
+    /// Locks the order of every shard `command` may change when run with
+    /// `args` in database `db`.
+    pub fn lock_written(
+        &self,
+        server: &Server,
+        command: &CommandSpec,
+        args: &[Bytes],
+        db: usize,
+    ) -> Vec<MutexGuard<'_, ()>> {
+        let per_db = server.db(0).shard_count();
+        let keys = command.keys(args);
+        // Commands without keys, like FLUSHALL and SWAPDB, may change any
+        // shard. MOVE and COPY also change their keys in another database.
+        let dbs = match command.name {
+            _ if keys.is_empty() => return self.lock_shards((0..self.order.len()).collect()),
+            "move" | "copy" => 0..server.dbs().len(),
+            _ => db..db + 1,
+        };
+        let shards = dbs
+            .flat_map(|db| {
+                let keyspace = server.db(db);
+                keys.iter()
+                    .map(move |key| db * per_db + keyspace.index(key))
+            })
+            .collect();
+        self.lock_shards(shards)
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewriting.load(Ordering::Acquire)
+    }
+
+    pub fn last_rewrite_ok(&self) -> bool {
+        self.last_rewrite_ok.load(Ordering::Relaxed)
+    }
+}
+
+impl AofState {
+    /// Logs `args` as run in database `db`.
+    pub fn log(&mut self, db: usize, args: &[Bytes]) -> io::Result<()> {
+        let mut encoded = Vec::new();
+        encode_command(&mut encoded, &mut self.db, db, args);
+        if let Some(buffer) = &mut self.rewrite {
+            encode_command(&mut buffer.bytes, &mut buffer.db, db, args);
+        }
+        self.file.write_all(&encoded)?;
+        if self.fsync == AppendFsync::Always {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Logs a write command that just ran. Relative expiries are logged as
+    /// the time they resolved to, so replaying the log later does not
+    /// extend them.
+    pub fn log_write(&mut self, server: &Server, db: usize, args: &[Bytes]) {
+        let result = match args[0].to_ascii_lowercase().as_slice() {
+            b"expire" | b"pexpire" | b"expireat" | b"pexpireat" => {
+                self.log_expiry(server, db, &args[1])
+            }
+            b"set" if args.len() > 3 => self
+                .log(db, &args[..3])
+                .and_then(|_| self.log_expiry(server, db, &args[1])),
+            _ => self.log(db, args),
+        };
+        if let Err(e) = result {
+            eprintln!("Error writing to the AOF file: {}", e);
+        }
+    }
+
+    /// Logs the expiry `key` has now, or its deletion if the expiry was
+    /// already in the past.
+    fn log_expiry(&mut self, server: &Server, db: usize, key: &Bytes) -> io::Result<()> {
+        let expireat = server.storage(db, key).expire_time(key);
+        match expireat {
+            Ok(Some(at)) => self.log(
+                db,
+                &[
+                    Bytes::from_static(b"pexpireat"),
+                    key.clone(),
+                    Bytes::from(at.to_string()),
+                ],
+            ),
+            // A condition like NX left the key without an expiry.
+            Ok(None) => Ok(()),
+            Err(_) => self.log(db, &[Bytes::from_static(b"del"), key.clone()]),
+        }
+    }
+
+    /// Hands the file to the fsync thread.
+    fn file(&self) -> io::Result<File> {
+        self.file.try_clone()
+    }
+}
+
+/// Writes commands that recreate `snapshot`.
+fn write_snapshot<W: Write>(
+    snapshot: &Snapshot,
+    out: &mut W,
+    current: &mut Option<usize>,
+) -> io::Result<()> {
+    let mut encoded = Vec::new();
+    for (db, keys) in snapshot.dbs().iter().enumerate() {
+        for (key, unit) in keys {
+            if unit.value().is_empty() {
+                continue;
+            }
+            let mut command = |name: &'static [u8], items: &[Bytes]| {
+                let mut args = vec![Bytes::from_static(name), key.clone()];
+                args.extend_from_slice(items);
+                encode_command(&mut encoded, current, db, &args);
+            };
+            match unit.value() {
+                Value::String(s) => command(b"set", std::slice::from_ref(s)),
+                Value::Vector(items) => {
+                    let items: Vec<Bytes> = items.iter().cloned().collect();
+                    for chunk in items.chunks(ITEMS_PER_COMMAND) {
+                        command(b"rpush", chunk);
+                    }
+                }
+                Value::Hash(map) => {
+                    let pairs: Vec<Bytes> = map
+                        .iter()
+                        .flat_map(|(f, v)| [f.clone(), v.clone()])
+                        .collect();
+                    for chunk in pairs.chunks(ITEMS_PER_COMMAND * 2) {
+                        command(b"hset", chunk);
+                    }
+                }
+                Value::Queue(queue) => {
+                    let items: Vec<Bytes> = queue.items().iter().cloned().collect();
+                    for chunk in items.chunks(ITEMS_PER_COMMAND) {
+                        command(b"qadd", chunk);
+                    }
+                }
+            }
+            if let Some(at) = unit.expireat() {
+                command(b"pexpireat", &[Bytes::from(at.to_string())]);
+            }
+            out.write_all(&encoded)?;
+            encoded.clear();
+        }
+    }
+    Ok(())
+}
+
+/// Runs one logged command. Replies, errors included, go nowhere: the
+/// command already ran once.
+fn replay(server: &Server, client: &mut Client, args: Vec<Bytes>) -> io::Result<()> {
+    let command = registry::lookup(&args[0].to_ascii_lowercase())
+        .filter(|c| c.check_arity(args.len()))
+        .ok_or_else(|| {
+            invalid(format!(
+                "unknown command '{}' reading the append only file",
+                String::from_utf8_lossy(&args[0])
+            ))
+        })?;
+    let _ = (command.handler)(&mut ReplyBuffer::new(), args, client, server);
+    Ok(())
+}
+
+/// Replays the log at the configured path. Returns the number of commands
+/// run, or `None` when there is no log yet.
+///
+/// A log whose last command was cut short, as a crash mid-write leaves it,
+/// is truncated after the last complete command when `aof-load-truncated`
+/// is on.
+pub fn load(server: &Server) -> io::Result<Option<usize>> {
+    let path = server.config.aof_path();
+    let mut file = match OpenOptions::new().read(true).write(true).open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut decoder = RequestDecoder::new();
+    let mut client = Client::new(true);
+    let mut chunk = vec![0; READ_CHUNK];
+    let mut read = 0;
+    let mut complete = 0;
+    let mut commands = 0;
+    loop {
+        let n = file.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        read += n as u64;
+        decoder.buffer().extend_from_slice(&chunk[..n]);
+        loop {
+            let args = decoder.next_command().map_err(|e| {
+                invalid(format!(
+                    "bad file format reading the append only file: {}",
+                    e
+                ))
+            })?;
+            match args {
+                Some(args) => {
+                    replay(server, &mut client, args)?;
+                    commands += 1;
+                    complete = read - decoder.buffer().len() as u64;
+                }
+                None => break,
+            }
+        }
+    }
+    if complete < read {
+        if !server.config.aof_load_truncated {
+            return Err(invalid(
+                "the append only file ends with a truncated command; \
+                 set aof-load-truncated yes to load it anyway",
+            ));
+        }
+        eprintln!(
+            "!!! Warning: short read while loading the AOF file {}, truncating it from {} to {} bytes",
+            path.display(),
+            read,
+            complete
+        );
+        file.set_len(complete)?;
+    }
+    Ok(Some(commands))
+}
+
+/// Starts rewriting the log from the current data in the background.
+/// Returns `None` when a rewrite is already running, and the error when
+/// the rewrite thread could not be started. Writes that happen meanwhile
+/// are logged to both files and end up after the rewritten data.
+pub fn bgrewrite(server: &Server, aof: &Arc<Aof>) -> Option<io::Result<()>> {
+    if aof.rewriting.swap(true, Ordering::AcqRel) {
+        return None;
+    }
+    let snapshot = {
+        // No write may be between changing the data and logging it, or it
+        // would be in both the snapshot and the buffer.
+        let _order = aof.lock_shards((0..aof.order.len()).collect());
+        let mut state = aof.lock();
+        state.rewrite = Some(RewriteBuffer {
+            bytes: Vec::new(),
+            db: None,
+        });
+        Snapshot::take(server)
+    };
+    let path = server.config.aof_path();
+    let shared = Arc::clone(aof);
+    let spawned = thread::Builder::new()
+        .name("aof-rewrite".to_string())
+        .spawn(move || {
+            let aof = shared;
+            let temp = path.with_file_name(format!("temp-rewriteaof-{}.aof", process::id()));
+            let result = File::create(&temp).and_then(|file| {
+                let mut out = BufWriter::new(file);
+                write_snapshot(&snapshot, &mut out, &mut None)?;
+                let mut file = out.into_inner().map_err(|e| e.into_error())?;
+                file.sync_data()?;
+                let mut state = aof.lock();
+                let buffer = state.rewrite.take().expect("rewrite buffer missing");
+                file.write_all(&buffer.bytes)?;
+                file.sync_data()?;
+                fs::rename(&temp, &path)?;
+                state.file = file;
+                state.db = buffer.db;
+                Ok(())
+            });
+            match &result {
+                Ok(()) => println!("Background AOF rewrite finished successfully"),
+                Err(e) => {
+                    eprintln!("Background AOF rewrite failed: {}", e);
+                    let _ = fs::remove_file(&temp);
+                    aof.lock().rewrite = None;
+                }
+            }
+            aof.last_rewrite_ok.store(result.is_ok(), Ordering::Relaxed);
+            aof.rewriting.store(false, Ordering::Release);
+        });
+    if let Err(e) = &spawned {
+        eprintln!("Can't rewrite the append only file in background: {}", e);
+        aof.lock().rewrite = None;
+        aof.last_rewrite_ok.store(false, Ordering::Relaxed);
+        aof.rewriting.store(false, Ordering::Release);
+    }
+    Some(spawned.map(|_| ()))
+}
+
+/// Starts the thread that flushes the log to disk once a second, for
+/// `appendfsync everysec`. The lock is only held to duplicate the file
+/// handle, so writes never wait for the disk.
+pub fn spawn_fsync(aof: Arc<Aof>) {
+    thread::Builder::new()
+        .name("aof-fsync".to_string())
+        .spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            let file = aof.lock().file();
+            if let Err(e) = file.and_then(|f| f.sync_data()) {
+                eprintln!("Error syncing the AOF file: {}", e);
+            }
+        })
+        .expect("failed to spawn the AOF fsync thread");
+}
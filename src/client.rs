@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{acl::DEFAULT_USER, encoder::Protocol};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Per-connection state that outlives a single command.
+pub struct Client {
+    pub id: u64,
+    pub protocol: Protocol,
+    pub name: Option<String>,
+    /// The ACL user commands run as.
+    pub user: String,
+    pub authenticated: bool,
+    /// The selected database.
+    pub db: usize,
+    /// Changes the running command made to the data. Write commands that
+    /// leave it at 0 are neither logged nor counted towards the save rules.
+    pub dirty: u64,
+}
+
+impl Client {
+    pub fn new(authenticated: bool) -> Self {
+        Client {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Resp2,
+            name: None,
+            user: DEFAULT_USER.to_string(),
+            authenticated,
+            db: 0,
+            dirty: 0,
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use bytes::Bytes;
+
+use crate::{
+    acl::{self, Acl},
+    client::Client,
+    encoder::*,
+    error::{CommandError, CommandResult},
+    server::Server,
+};
+
+/// Logs the connection in as `username`.
+pub fn login(
+    client: &mut Client,
+    acl: &Acl,
+    username: &str,
+    password: &[u8],
+) -> Result<(), CommandError> {
+    acl.authenticate(username, password)?;
+    client.user = username.to_string();
+    client.authenticated = true;
+    Ok(())
+}
+
+pub fn auth(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let acl = server.acl();
+    let result = match pure_cmd.len() {
+        2 => acl
+            .authenticate_default(&pure_cmd[1])
+            .and_then(|_| login(client, &acl, acl::DEFAULT_USER, &pure_cmd[1])),
+        3 => login(
+            client,
+            &acl,
+            &String::from_utf8_lossy(&pure_cmd[1]),
+            &pure_cmd[2],
+        ),
+        _ => {
+            return Err(CommandError::Syntax);
+        }
+    };
+    result?;
+    reply.extend(encode_resp_simple_string("OK"));
+    Ok(())
+}
+
+pub fn acl(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let subcommand = pure_cmd[1].to_ascii_lowercase();
+    match (subcommand.as_slice(), pure_cmd.len()) {
+        (b"setuser", n) if n >= 3 => {
+            let name = String::from_utf8_lossy(&pure_cmd[2]).into_owned();
+            server.acl_mut().set_user(&name, &pure_cmd[3..])?;
+            reply.extend(encode_resp_simple_string("OK"));
+        }
+        (b"getuser", 3) => {
+            let acl = server.acl();
+            let user = match acl.get(&String::from_utf8_lossy(&pure_cmd[2])) {
+                Some(u) => u,
+                None => {
+                    reply.extend(encode_resp_null(client.protocol));
+                    return Ok(());
+                }
+            };
+            let bulk = |s: &str| encode_resp_bulk_string(s.as_bytes());
+            reply.extend(encode_resp_map(
+                vec![
+                    (
+                        bulk("flags"),
+                        encode_resp_array(user.flags().iter().map(|f| bulk(f)).collect()),
+                    ),
+                    (
+                        bulk("passwords"),
+                        encode_resp_array(user.passwords().iter().map(|p| bulk(p)).collect()),
+                    ),
+                    (bulk("commands"), bulk(&user.commands_description())),
+                    (bulk("keys"), bulk(&user.keys_description())),
+                ],
+                client.protocol,
+            ));
+        }
+        (b"deluser", n) if n >= 3 => {
+            let mut acl = server.acl_mut();
+            if pure_cmd[2..].iter().any(|name| name == acl::DEFAULT_USER) {
+                return Err(CommandError::Other(
+                    "The 'default' user cannot be removed".to_string(),
+                ));
+            }
+            let deleted = pure_cmd[2..]
+                .iter()
+                .filter(|name| acl.delete_user(&String::from_utf8_lossy(name)))
+                .count();
+            reply.extend(encode_resp_integer(&deleted.to_string()));
+        }
+        (b"list", 2) => {
+            let lines: Vec<Bytes> = server
+                .acl()
+                .users()
+                .map(|u| Bytes::from(u.describe()))
+                .collect();
+            reply.push_arrays(lines);
+        }
+        (b"whoami", 2) => reply.push_bulk_string(Bytes::from(client.user.clone())),
+        (b"cat", 2) => {
+            let categories = acl::categories()
+                .iter()
+                .map(|c| Bytes::from_static(c.as_bytes()))
+                .collect();
+            reply.push_arrays(categories);
+        }
+        (b"setuser", _)
+        | (b"getuser", _)
+        | (b"deluser", _)
+        | (b"list", _)
+        | (b"whoami", _)
+        | (b"cat", _) => {
+            return Err(CommandError::WrongArity(format!(
+                "acl|{}",
+                String::from_utf8_lossy(&subcommand)
+            )));
+        }
+        _ => {
+            return Err(CommandError::UnknownSubcommand {
+                command: "acl",
+                subcommand: String::from_utf8_lossy(&pure_cmd[1]).into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
@@ -1,316 +1,290 @@
-use std::sync::{Arc, Mutex};
-
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use bytes::Bytes;
 
 use crate::{
+    client::Client,
     decoder::*,
     encoder::*,
-    storage::{Storage, StorageError},
+    error::{CommandError, CommandResult},
+    server::Server,
+    storage::{ListEnd, PopReply, StorageError},
 };
 
-pub async fn push(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() < 3 {
-        stream
-            .write(&encode_resp_error_string(
-                format!("Invalid args for {}", pure_cmd[0]).trim(),
-            ))
-            .await
-            .unwrap();
-    } else {
-        let items = pure_cmd[2..pure_cmd.len()].to_vec();
-        let clock = client_store.lock().unwrap().set_array(
-            pure_cmd[1].to_owned(),
-            items,
-            pure_cmd[0].trim(),
-        );
-        match clock {
-            Ok(len) => {
-                let str_len = len.to_string();
-                stream
-                    .write(&encode_resp_integer(str_len.trim()))
-                    .await
-                    .unwrap();
-            }
-            Err(_) => {
-                stream
-                    .write(&encode_resp_error_string(
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ))
-                    .await
-                    .unwrap();
-            }
-        }
-    }
+pub fn lpush(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    push(reply, pure_cmd, client, server, ListEnd::Head, false)
 }
 
-pub async fn lrange(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() < 4 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for lrange"))
-            .await
-            .unwrap();
-    } else {
-        let key = pure_cmd[1].to_owned();
-        let len_clock = client_store.lock().unwrap().get_array_len(&key);
-        let mut len: usize = 0;
-        match len_clock {
-            Ok(v) => len = v,
-            Err(e) => match e {
-                StorageError::BadType => {
-                    stream
-                        .write(&encode_resp_error_string(
-                            "WRONGTYPE Operation against a key holding the wrong kind of value",
-                        ))
-                        .await
-                        .unwrap();
-                }
-                _ => {
-                    stream.write(&encode_resp_empty_array()).await.unwrap();
-                }
-            },
-        }
-        if len > 0 {
-            match decode_array_indices(pure_cmd[2].trim(), pure_cmd[3].trim(), len) {
-                Ok(bound) => {
-                    let array_clock = client_store.lock().unwrap().get_array(&key, bound);
-                    match array_clock {
-                        Ok(array) => {
-                            stream.write(&encode_resp_arrays(array)).await.unwrap();
-                        }
-                        Err(e) => {
-                            match e {
-                                StorageError::BadCommand => {
-                                    stream
-                                        .write(&encode_resp_error_string("Invalid range"))
-                                        .await
-                                        .unwrap();
-                                }
-                                _ => {
-                                    stream.write(&encode_resp_error_string("WRONGTYPE Operation against a key holding the wrong kind of value")).await.unwrap();
-                                }
-                            };
-                        }
-                    }
-                }
-                Err(_) => {
-                    stream
-                        .write(&encode_resp_error_string("Invalid range"))
-                        .await
-                        .unwrap();
-                }
-            }
-        }
+pub fn rpush(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    push(reply, pure_cmd, client, server, ListEnd::Tail, false)
+}
+
+pub fn lpushx(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    push(reply, pure_cmd, client, server, ListEnd::Head, true)
+}
+
+pub fn rpushx(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    push(reply, pure_cmd, client, server, ListEnd::Tail, true)
+}
+
+fn push(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+    end: ListEnd,
+    existing_only: bool,
+) -> CommandResult {
+    let items = pure_cmd[2..pure_cmd.len()].to_vec();
+    let pushed = items.len() as u64;
+    let len = server.storage(client.db, &pure_cmd[1]).set_array(
+        pure_cmd[1].to_owned(),
+        items,
+        end,
+        existing_only,
+    )?;
+    // Only a missing list with `existing_only` is left empty.
+    if len > 0 {
+        client.dirty += pushed;
     }
+    reply.extend(encode_resp_integer(&len.to_string()));
+    Ok(())
 }
 
-pub async fn llen(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() != 2 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for 'llen'"))
-            .await
-            .unwrap();
-        return;
+pub fn lrange(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let (start, stop) = match (parse_i64(&pure_cmd[2]), parse_i64(&pure_cmd[3])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        _ => return Err(CommandError::NotInteger),
+    };
+    match server
+        .storage(client.db, &pure_cmd[1])
+        .get_array(&pure_cmd[1], start, stop)
+    {
+        Ok(array) => reply.push_arrays(array),
+        Err(StorageError::NotFound) => reply.extend(encode_resp_empty_array()),
+        Err(e) => return Err(e.into()),
     }
-    let key = pure_cmd[1].as_str();
-    let clock = client_store.lock().unwrap().get_array_len(key);
+    Ok(())
+}
+
+pub fn llen(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let key = &pure_cmd[1][..];
+    let clock = server.storage(client.db, &pure_cmd[1]).get_array_len(key);
     match clock {
         Ok(len) => {
-            stream
-                .write(&encode_resp_integer(len.to_string().as_str()))
-                .await
-                .unwrap();
+            reply.extend(encode_resp_integer(len.to_string().as_str()));
         }
+        Err(StorageError::BadType) => return Err(CommandError::WrongType),
         Err(_) => {
-            stream.write(&encode_resp_integer("0")).await.unwrap();
+            reply.extend(encode_resp_integer("0"));
         }
     }
+    Ok(())
+}
+
+pub fn lpop(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    pop(reply, pure_cmd, client, server, ListEnd::Head)
+}
+
+pub fn rpop(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    pop(reply, pure_cmd, client, server, ListEnd::Tail)
 }
 
-pub async fn lpop(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() < 2 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for lpop"))
-            .await
-            .unwrap();
-        return;
+fn pop(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+    end: ListEnd,
+) -> CommandResult {
+    if pure_cmd.len() > 3 {
+        return Err(CommandError::WrongArity(
+            String::from_utf8_lossy(&pure_cmd[0]).to_ascii_lowercase(),
+        ));
     }
-    let clock = client_store.lock().unwrap().pop_array(pure_cmd);
-    match clock {
-        Ok(reply) => match reply {
-            crate::storage::PopReply::String(s) => {
-                stream.write(&encode_resp_bulk_string(s)).await.unwrap();
-            }
-            crate::storage::PopReply::Vector(v) => {
-                stream.write(&encode_resp_arrays(v)).await.unwrap();
-            }
-        },
-        Err(e) => match e {
-            StorageError::BadType => {
-                stream
-                    .write(&encode_resp_error_string(
-                        "WRONGTYPE operation against a key holding the wrong kind of value",
-                    ))
-                    .await
-                    .unwrap();
-            }
+    let count = match pure_cmd.get(2) {
+        Some(arg) => match parse_i64(arg) {
+            Ok(n) if n >= 0 => Some(n as usize),
             _ => {
-                stream.write(&empty_bulk_string()).await.unwrap();
+                return Err(CommandError::Other(
+                    "value is out of range, must be positive".to_string(),
+                ))
             }
         },
+        None => None,
     };
-}
-
-pub async fn lindex(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() != 3 {
-        stream
-            .write(&encode_resp_error_string("Invalid arguments for linex"))
-            .await
-            .unwrap();
-        return;
+    let clock = server
+        .storage(client.db, &pure_cmd[1])
+        .pop_array(&pure_cmd[1], end, count);
+    match clock {
+        Ok(PopReply::String(s)) => {
+            client.dirty += 1;
+            reply.push_bulk_string(s);
+        }
+        Ok(PopReply::Vector(v)) => {
+            client.dirty += v.len() as u64;
+            reply.push_arrays(v);
+        }
+        Err(StorageError::BadType) => return Err(CommandError::WrongType),
+        // With a count the reply is an array, so a missing list is a null
+        // array rather than a null string.
+        Err(_) if count.is_some() => reply.extend(encode_resp_null_array(client.protocol)),
+        Err(_) => reply.extend(encode_resp_null(client.protocol)),
     }
+    Ok(())
+}
 
-    let index: i64 = match pure_cmd[2].parse::<i64>() {
+pub fn lindex(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let index: i64 = match parse_i64(&pure_cmd[2]) {
         Ok(i) => i,
+        // Redis looks the key up before it reads the index.
         _ => {
-            stream
-                .write(&encode_resp_error_string("Invalid arguments for linex"))
-                .await
-                .unwrap();
-            return;
+            return match server
+                .storage(client.db, &pure_cmd[1])
+                .get_array_len(&pure_cmd[1])
+            {
+                Ok(_) => Err(CommandError::NotInteger),
+                Err(StorageError::BadType) => Err(CommandError::WrongType),
+                Err(_) => {
+                    reply.extend(encode_resp_null(client.protocol));
+                    Ok(())
+                }
+            };
         }
     };
-    let clock = client_store
-        .lock()
-        .unwrap()
-        .array_get(pure_cmd[1].trim(), index);
+    let clock = server
+        .storage(client.db, &pure_cmd[1])
+        .array_get(&pure_cmd[1], index);
     match clock {
         Ok(s) => {
-            stream.write(&encode_resp_bulk_string(s)).await.unwrap();
+            reply.push_bulk_string(s);
         }
         Err(e) => match e {
             StorageError::BadType => {
-                stream
-                    .write(&encode_resp_error_string(
-                        "WRONGTYPE operation against a key holding the wrong kind of value",
-                    ))
-                    .await
-                    .unwrap();
+                return Err(CommandError::WrongType);
             }
             _ => {
-                stream.write(&empty_bulk_string()).await.unwrap();
+                reply.extend(encode_resp_null(client.protocol));
             }
         },
     }
+    Ok(())
 }
 
-pub async fn lrem(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() != 4 {
-        stream
-            .write(&encode_resp_error_string("Invalid arguments for 'lrem'"))
-            .await
-            .unwrap();
-    }
-    if let Ok(n) = pure_cmd[2].parse::<i64>() {
-        let clock = client_store.lock().unwrap().remove_array(
-            pure_cmd[1].as_str(),
+pub fn lrem(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    if let Ok(n) = parse_i64(&pure_cmd[2]) {
+        let clock = server.storage(client.db, &pure_cmd[1]).remove_array(
+            &pure_cmd[1],
             n,
             pure_cmd[3].to_owned(),
         );
         match clock {
             Ok(count) => {
-                stream
-                    .write(&encode_resp_integer(count.to_string().as_str()))
-                    .await
-                    .unwrap();
+                client.dirty += count as u64;
+                reply.extend(encode_resp_integer(count.to_string().as_str()));
             }
-            Err(e) => {
-                match e {
-                    StorageError::BadType => {
-                        stream.write(&encode_resp_error_string("WRONGTYPE operation against the key holding the wrong kind of value")).await.unwrap();
-                    }
-                    _ => {
-                        stream.write(&empty_bulk_string()).await.unwrap();
-                    }
+            Err(e) => match e {
+                StorageError::BadType => {
+                    return Err(CommandError::WrongType);
                 }
-            }
+                _ => {
+                    reply.extend(encode_resp_integer("0"));
+                }
+            },
         }
     } else {
-        stream
-            .write(&encode_resp_error_string("Invalid arguments for 'lrem'"))
-            .await
-            .unwrap();
+        return Err(CommandError::NotInteger);
     }
+    Ok(())
 }
 
-pub async fn lset(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    if pure_cmd.len() < 4 {
-        stream
-            .write(&encode_resp_error_string("Invalid arguments for 'lset'"))
-            .await
-            .unwrap();
-    }
-    if let Ok(n) = pure_cmd[2].parse::<i64>() {
-        let clock =
-            client_store
-                .lock()
-                .unwrap()
-                .array_set(pure_cmd[1].trim(), n, pure_cmd[3].to_owned());
+pub fn lset(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    if let Ok(n) = parse_i64(&pure_cmd[2]) {
+        let clock = server.storage(client.db, &pure_cmd[1]).array_set(
+            &pure_cmd[1],
+            n,
+            pure_cmd[3].to_owned(),
+        );
         match clock {
             Ok(()) => {
-                stream
-                    .write(&encode_resp_simple_string("OK"))
-                    .await
-                    .unwrap();
+                client.dirty += 1;
+                reply.extend(encode_resp_simple_string("OK"));
             }
-            Err(e) => {
-                match e {
-                    StorageError::BadType => {
-                        stream.write(&encode_resp_error_string("WRONGTYPE operation against the key holding the wrong kind of value")).await.unwrap();
-                    }
-                    StorageError::OutOfRange => {
-                        stream
-                            .write(&encode_resp_error_string("index out of range"))
-                            .await
-                            .unwrap();
-                    }
-                    _ => {
-                        stream.write(&encode_resp_empty_array()).await.unwrap();
-                    }
+            Err(e) => match e {
+                StorageError::BadType => {
+                    return Err(CommandError::WrongType);
                 }
-            }
+                StorageError::OutOfRange => {
+                    return Err(CommandError::OutOfRange);
+                }
+                _ => {
+                    return Err(CommandError::NoSuchKey);
+                }
+            },
         }
     } else {
-        stream
-            .write(&encode_resp_error_string("Invalid arguments for 'lset'"))
-            .await
-            .unwrap();
+        // Redis looks the key up before it reads the index.
+        return match server
+            .storage(client.db, &pure_cmd[1])
+            .get_array_len(&pure_cmd[1])
+        {
+            Ok(_) => Err(CommandError::NotInteger),
+            Err(StorageError::BadType) => Err(CommandError::WrongType),
+            Err(_) => Err(CommandError::NoSuchKey),
+        };
     }
+    Ok(())
 }
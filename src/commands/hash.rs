@@ -1,40 +1,60 @@
-use std::sync::{Arc, Mutex};
-
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use bytes::Bytes;
 
 use crate::{
+    client::Client,
     encoder::*,
-    storage::{Storage, StorageError},
+    error::{CommandError, CommandResult},
+    server::Server,
+    storage::StorageError,
 };
 
-pub async fn hash_set(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    let clock = client_store.lock().unwrap().hash_set(pure_cmd);
+pub fn hash_set(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    // Overwritten fields are changes too, though HSET only counts new ones.
+    let pairs = (pure_cmd.len() - 2) / 2;
+    let clock = server.storage(client.db, &pure_cmd[1]).hash_set(pure_cmd);
     match clock {
-        Ok(size) => {
-            stream
-                .write(&encode_resp_integer(size.to_string().as_str()))
-                .await
-                .unwrap();
+        Ok(added) => {
+            client.dirty += pairs as u64;
+            reply.extend(encode_resp_integer(added.to_string().as_str()));
         }
         Err(StorageError::BadType) => {
-            stream
-                .write(&encode_resp_error_string(
-                    "WRONGTYPE Operation against a key holding the wrong kind of value",
-                ))
-                .await
-                .unwrap();
+            return Err(CommandError::WrongType);
         }
         _ => {
-            stream
-                .write(&encode_resp_error_string(
-                    "wrong number of arguments for 'hset' command",
-                ))
-                .await
-                .unwrap();
+            return Err(CommandError::WrongArity("hset".to_string()));
         }
     };
+    Ok(())
+}
+
+pub fn hash_get_all(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let clock = server
+        .storage(client.db, &pure_cmd[1])
+        .hash_get_all(&pure_cmd[1]);
+    match clock {
+        Ok(fields) => {
+            reply.extend(encode_resp_map_header(fields.len(), client.protocol));
+            for (field, value) in fields {
+                reply.push_bulk_string(field);
+                reply.push_bulk_string(value);
+            }
+        }
+        Err(StorageError::BadType) => {
+            return Err(CommandError::WrongType);
+        }
+        _ => {
+            reply.extend(encode_resp_map(vec![], client.protocol));
+        }
+    }
+    Ok(())
 }
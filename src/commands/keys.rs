@@ -0,0 +1,362 @@
+use bytes::Bytes;
+
+use crate::{
+    client::Client,
+    commands::db_index,
+    decoder::*,
+    encoder::*,
+    error::{CommandError, CommandResult},
+    glob::glob_match,
+    server::Server,
+    storage::now_ms,
+};
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT.
+pub fn expire(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let name = String::from_utf8_lossy(&pure_cmd[0]).to_ascii_lowercase();
+    let (unit, absolute) = match name.as_str() {
+        "expire" => (1000, false),
+        "pexpire" => (1, false),
+        "expireat" => (1000, true),
+        _ => (1, true),
+    };
+    let time = parse_i64(&pure_cmd[2]).map_err(|_| CommandError::NotInteger)?;
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &pure_cmd[3..] {
+        match option.to_ascii_lowercase().as_slice() {
+            b"nx" => nx = true,
+            b"xx" => xx = true,
+            b"gt" => gt = true,
+            b"lt" => lt = true,
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "Unsupported option {}",
+                    String::from_utf8_lossy(option)
+                )));
+            }
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Err(CommandError::Other(
+            "NX and XX, GT or LT options at the same time are not compatible".to_string(),
+        ));
+    }
+    if gt && lt {
+        return Err(CommandError::Other(
+            "GT and LT options at the same time are not compatible".to_string(),
+        ));
+    }
+
+    let invalid = || CommandError::Other(format!("invalid expire time in '{}' command", name));
+    let mut at = time.checked_mul(unit).ok_or_else(invalid)?;
+    if !absolute {
+        at = at.checked_add(now_ms() as i64).ok_or_else(invalid)?;
+    }
+
+    let key = &pure_cmd[1];
+    let mut storage = server.storage(client.db, key);
+    let current = match storage.expire_time(key) {
+        Ok(current) => current,
+        Err(_) => {
+            reply.extend(encode_resp_integer("0"));
+            return Ok(());
+        }
+    };
+    // A key without an expiry counts as one infinitely far away.
+    let allowed = (!nx || current.is_none())
+        && (!xx || current.is_some())
+        && (!gt || current.is_some_and(|c| at > c as i64))
+        && (!lt || current.map(|c| at < c as i64).unwrap_or(true));
+    if !allowed {
+        reply.extend(encode_resp_integer("0"));
+        return Ok(());
+    }
+    // Like Redis, a time already in the past deletes the key right away.
+    if at <= now_ms() as i64 {
+        storage.delete(key);
+    } else {
+        storage.set_expire_time(key, Some(at as u64))?;
+    }
+    client.dirty += 1;
+    reply.extend(encode_resp_integer("1"));
+    Ok(())
+}
+
+/// TTL, PTTL, EXPIRETIME and PEXPIRETIME: -2 for a missing key, -1 for a
+/// key without an expiry.
+pub fn ttl(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let at = match server
+        .storage(client.db, &pure_cmd[1])
+        .expire_time(&pure_cmd[1])
+    {
+        Ok(Some(at)) => at,
+        Ok(None) => {
+            reply.extend(encode_resp_integer("-1"));
+            return Ok(());
+        }
+        Err(_) => {
+            reply.extend(encode_resp_integer("-2"));
+            return Ok(());
+        }
+    };
+    let remaining = at.saturating_sub(now_ms());
+    let value = match pure_cmd[0].to_ascii_lowercase().as_slice() {
+        b"ttl" => (remaining + 500) / 1000,
+        b"pttl" => remaining,
+        b"expiretime" => at / 1000,
+        _ => at,
+    };
+    reply.extend(encode_resp_integer(&value.to_string()));
+    Ok(())
+}
+
+pub fn persist(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let key = &pure_cmd[1];
+    let mut storage = server.storage(client.db, key);
+    let removed = match storage.expire_time(key) {
+        Ok(Some(_)) => {
+            storage.set_expire_time(key, None)?;
+            client.dirty += 1;
+            "1"
+        }
+        _ => "0",
+    };
+    reply.extend(encode_resp_integer(removed));
+    Ok(())
+}
+
+pub fn exists(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let keys = &pure_cmd[1..];
+    let mut shards = server.db(client.db).lock_keys(keys);
+    // A key named twice is counted twice, as in Redis.
+    let count = keys
+        .iter()
+        .filter(|key| shards.shard(server.db(client.db), key).exists(key))
+        .count();
+    reply.extend(encode_resp_integer(&count.to_string()));
+    Ok(())
+}
+
+pub fn key_type(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let name = server
+        .storage(client.db, &pure_cmd[1])
+        .type_name(&pure_cmd[1])
+        .unwrap_or("none");
+    reply.extend(encode_resp_simple_string(name));
+    Ok(())
+}
+
+pub fn keys(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let mut keys = vec![];
+    let db = server.db(client.db);
+    for i in 0..db.shard_count() {
+        keys.extend(db.shard_at(i).keys(&pure_cmd[1]));
+    }
+    reply.extend(encode_resp_set(
+        keys.iter().map(|k| encode_resp_bulk_string(k)).collect(),
+        client.protocol,
+    ));
+    Ok(())
+}
+
+pub fn scan(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let cursor =
+        parse_u64(&pure_cmd[1]).map_err(|_| CommandError::Other("invalid cursor".to_string()))?;
+    let mut pattern = None;
+    let mut count = 10;
+    let mut type_name = None;
+    let mut options = pure_cmd[2..].chunks(2);
+    for option in &mut options {
+        match (option[0].to_ascii_lowercase().as_slice(), option.get(1)) {
+            (b"match", Some(p)) => pattern = Some(p),
+            (b"count", Some(n)) => {
+                count = match parse_u64(n) {
+                    Ok(0) => return Err(CommandError::Syntax),
+                    Ok(n) => n as usize,
+                    Err(_) => return Err(CommandError::NotInteger),
+                };
+            }
+            (b"type", Some(t)) => type_name = Some(t.to_ascii_lowercase()),
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+
+    let (next, found) = server.db(client.db).scan(cursor, count);
+    let keys = found
+        .into_iter()
+        .filter(|(key, kind)| {
+            pattern.map(|p| glob_match(p, key, false)).unwrap_or(true)
+                && type_name
+                    .as_ref()
+                    .map(|t| t == kind.as_bytes())
+                    .unwrap_or(true)
+        })
+        .map(|(key, _)| encode_resp_bulk_string(&key))
+        .collect();
+    reply.extend(encode_resp_array(vec![
+        encode_resp_bulk_string(next.to_string().as_bytes()),
+        encode_resp_array(keys),
+    ]));
+    Ok(())
+}
+
+/// RENAME and RENAMENX. The value keeps its expiry.
+pub fn rename(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let nx = pure_cmd[0].eq_ignore_ascii_case(b"renamenx");
+    let (src, dst) = (&pure_cmd[1], &pure_cmd[2]);
+    let keyspace = server.db(client.db);
+    let mut shards = keyspace.lock_keys(&pure_cmd[1..3]);
+    if !shards.shard(keyspace, src).exists(src) {
+        return Err(CommandError::NoSuchKey);
+    }
+    if nx && shards.shard(keyspace, dst).exists(dst) {
+        reply.extend(encode_resp_integer("0"));
+        return Ok(());
+    }
+    if src != dst {
+        if let Some(unit) = shards.shard(keyspace, src).take(src) {
+            shards.shard(keyspace, dst).put(dst.clone(), unit);
+        }
+        client.dirty += 1;
+    }
+    if nx {
+        reply.extend(encode_resp_integer("1"));
+    } else {
+        reply.extend(encode_resp_simple_string("OK"));
+    }
+    Ok(())
+}
+
+pub fn move_key(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let key = &pure_cmd[1];
+    let db = db_index(&pure_cmd[2], server)?;
+    if db == client.db {
+        return Err(CommandError::Other(
+            "source and destination objects are the same".to_string(),
+        ));
+    }
+    let (mut src, mut dst) = server.storage_pair(client.db, key, db, key);
+    let moved = !dst.exists(key) && src.exists(key);
+    if moved {
+        if let Some(unit) = src.take(key) {
+            dst.put(key.clone(), unit);
+        }
+        client.dirty += 1;
+    }
+    reply.extend(encode_resp_integer(if moved { "1" } else { "0" }));
+    Ok(())
+}
+
+/// COPY source destination [DB db] [REPLACE]. The copy keeps the source's
+/// expiry.
+pub fn copy(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let (src, dst) = (&pure_cmd[1], &pure_cmd[2]);
+    let mut db = client.db;
+    let mut replace = false;
+    let mut i = 3;
+    while i < pure_cmd.len() {
+        match pure_cmd[i].to_ascii_lowercase().as_slice() {
+            b"replace" => replace = true,
+            b"db" if i + 1 < pure_cmd.len() => {
+                db = db_index(&pure_cmd[i + 1], server)?;
+                i += 1;
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+        i += 1;
+    }
+    if db == client.db && src == dst {
+        return Err(CommandError::Other(
+            "source and destination objects are the same".to_string(),
+        ));
+    }
+    let copied = if db == client.db {
+        let keyspace = server.db(db);
+        let mut shards = keyspace.lock_keys(&pure_cmd[1..3]);
+        match shards.shard(keyspace, src).duplicate(src) {
+            Some(unit) if replace || !shards.shard(keyspace, dst).exists(dst) => {
+                shards.shard(keyspace, dst).put(dst.clone(), unit);
+                true
+            }
+            _ => false,
+        }
+    } else {
+        let (mut from, mut to) = server.storage_pair(client.db, src, db, dst);
+        match from.duplicate(src) {
+            Some(unit) if replace || !to.exists(dst) => {
+                to.put(dst.clone(), unit);
+                true
+            }
+            _ => false,
+        }
+    };
+    if copied {
+        client.dirty += 1;
+    }
+    reply.extend(encode_resp_integer(if copied { "1" } else { "0" }));
+    Ok(())
+}
+
+pub fn randomkey(
+    reply: &mut ReplyBuffer,
+    _pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    match server.db(client.db).random_key() {
+        Some(key) => reply.push_bulk_string(key),
+        None => reply.extend(encode_resp_null(client.protocol)),
+    }
+    Ok(())
+}
@@ -1,142 +1,266 @@
+pub mod acl;
 pub mod array;
 pub mod hash;
+pub mod keys;
 pub mod queue;
+pub mod registry;
+pub mod server;
 
-use std::sync::{Arc, Mutex};
-
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use bytes::Bytes;
 
 use crate::{
+    client::Client,
     decoder::*,
     encoder::*,
-    storage::{Storage, StorageError},
+    error::{CommandError, CommandResult},
+    server::Server,
+    storage::StorageError,
 };
 
-pub async fn ping(stream: &mut TcpStream) {
-    stream
-        .write(&encode_resp_simple_string("PONG"))
-        .await
-        .unwrap();
+pub fn ping(
+    reply: &mut ReplyBuffer,
+    _pure_cmd: Vec<Bytes>,
+    _client: &mut Client,
+    _server: &Server,
+) -> CommandResult {
+    reply.extend(encode_resp_simple_string("PONG"));
+    Ok(())
 }
 
-pub async fn echo(stream: &mut TcpStream, pure_cmd: Vec<String>) {
-    if pure_cmd.len() < 2 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for ECHO"))
-            .await
-            .unwrap();
-    } else {
-        stream
-            .write(&encode_resp_bulk_string(pure_cmd[1].to_owned()))
-            .await
-            .unwrap();
+pub fn echo(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    _client: &mut Client,
+    _server: &Server,
+) -> CommandResult {
+    reply.push_bulk_string(pure_cmd[1].clone());
+    Ok(())
+}
+
+pub fn hello(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let mut protocol = client.protocol;
+    let mut name = None;
+    let mut user = None;
+    let mut i = 1;
+    if pure_cmd.len() > 1 {
+        match parse_i64(&pure_cmd[1]) {
+            Ok(2) => protocol = Protocol::Resp2,
+            Ok(3) => protocol = Protocol::Resp3,
+            Ok(_) => {
+                return Err(CommandError::NoProto);
+            }
+            Err(_) => {
+                return Err(CommandError::Other(
+                    "Protocol version is not an integer or out of range".to_string(),
+                ));
+            }
+        }
+        i = 2;
+    }
+    while i < pure_cmd.len() {
+        let remaining = pure_cmd.len() - i - 1;
+        match pure_cmd[i].to_ascii_lowercase().as_slice() {
+            b"auth" if remaining >= 2 => {
+                let username = String::from_utf8_lossy(&pure_cmd[i + 1]).into_owned();
+                server.acl().authenticate(&username, &pure_cmd[i + 2])?;
+                user = Some(username);
+                i += 3;
+            }
+            b"setname" if remaining >= 1 => {
+                name = Some(String::from_utf8_lossy(&pure_cmd[i + 1]).into_owned());
+                i += 2;
+            }
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "Syntax error in HELLO option '{}'",
+                    String::from_utf8_lossy(&pure_cmd[i])
+                )));
+            }
+        }
     }
+    if let Some(user) = user {
+        client.user = user;
+        client.authenticated = true;
+    } else if !client.authenticated {
+        return Err(CommandError::HelloNoAuth);
+    }
+    client.protocol = protocol;
+    if name.is_some() {
+        client.name = name;
+    }
+    let proto = match protocol {
+        Protocol::Resp2 => "2",
+        Protocol::Resp3 => "3",
+    };
+    let bulk = |s: &str| encode_resp_bulk_string(s.as_bytes());
+    reply.extend(encode_resp_map(
+        vec![
+            (bulk("server"), bulk("appledore")),
+            (bulk("version"), bulk(env!("CARGO_PKG_VERSION"))),
+            (bulk("proto"), encode_resp_integer(proto)),
+            (bulk("id"), encode_resp_integer(&client.id.to_string())),
+            (bulk("mode"), bulk("standalone")),
+            (bulk("role"), bulk("master")),
+            (bulk("modules"), encode_resp_empty_array()),
+        ],
+        protocol,
+    ));
+    Ok(())
+}
+
+pub fn select(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    client.db = db_index(&pure_cmd[1], server)?;
+    reply.extend(encode_resp_simple_string("OK"));
+    Ok(())
 }
 
-pub async fn set(stream: &mut TcpStream, pure_cmd: Vec<String>, client_store: Arc<Mutex<Storage>>) {
-    if pure_cmd.len() < 3 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for SET"))
-            .await
-            .unwrap();
-    } else if pure_cmd.len() == 3 {
+pub fn set(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    if pure_cmd.len() == 3 {
         let k = pure_cmd[1].to_owned();
         let v = pure_cmd[2].to_owned();
-        client_store.lock().unwrap().set_string(k, v);
-        stream
-            .write(&encode_resp_simple_string("OK"))
-            .await
-            .unwrap();
+        server.storage(client.db, &pure_cmd[1]).set_string(k, v);
+        client.dirty += 1;
+        reply.extend(encode_resp_simple_string("OK"));
     } else if pure_cmd.len() == 5 {
-        if pure_cmd[3].to_lowercase() == "px" || pure_cmd[3].to_lowercase() == "ex" {
+        let unit = pure_cmd[3].to_ascii_lowercase();
+        if unit == b"px" || unit == b"ex" {
             let key = pure_cmd[1].to_owned();
             let elapsed: u64;
-            match parse_u64(pure_cmd[4].as_str()) {
+            match parse_u64(&pure_cmd[4]) {
+                Ok(0) => {
+                    return Err(CommandError::Other(
+                        "invalid expire time in 'set' command".to_string(),
+                    ));
+                }
                 Ok(v) => {
                     elapsed = v;
-                    if pure_cmd[3].to_lowercase() == "px" {
-                        client_store.lock().unwrap().set_string_px(
+                    if unit == b"px" {
+                        server.storage(client.db, &pure_cmd[1]).set_string_px(
                             key,
                             pure_cmd[2].to_owned(),
                             elapsed,
                         );
                     } else {
-                        client_store.lock().unwrap().set_string_ex(
+                        server.storage(client.db, &pure_cmd[1]).set_string_ex(
                             key,
                             pure_cmd[2].to_owned(),
                             elapsed,
                         );
                     }
-                    stream
-                        .write(&encode_resp_simple_string("OK"))
-                        .await
-                        .unwrap();
+                    client.dirty += 1;
+                    reply.extend(encode_resp_simple_string("OK"));
                 }
                 _e => {
-                    stream
-                        .write(&encode_resp_error_string("Invalid args for GET"))
-                        .await
-                        .unwrap();
+                    return Err(CommandError::NotInteger);
                 }
             }
         } else {
-            stream
-                .write(&encode_resp_error_string("Invalid args for GET"))
-                .await
-                .unwrap();
+            return Err(CommandError::Syntax);
         }
+    } else {
+        return Err(CommandError::Syntax);
     }
+    Ok(())
 }
 
-pub async fn get(stream: &mut TcpStream, pure_cmd: Vec<String>, client_store: Arc<Mutex<Storage>>) {
-    if pure_cmd.len() < 2 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for GET"))
-            .await
-            .unwrap();
-    } else {
-        let key = pure_cmd[1].to_owned();
-        let clock = client_store.lock().unwrap().get_string(&key);
-        match clock {
-            Ok(value) => {
-                stream.write(&value).await.unwrap();
+pub fn get(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let key = pure_cmd[1].to_owned();
+    let clock = server.storage(client.db, &pure_cmd[1]).get_string(&key);
+    match clock {
+        Ok(value) => {
+            reply.push_bulk_string(value);
+        }
+        Err(e) => match e {
+            StorageError::BadType => {
+                return Err(CommandError::WrongType);
             }
-            Err(e) => match e {
-                StorageError::BadType => {
-                    stream
-                        .write(&encode_resp_error_string(
-                            "WRONGTYPE Operation against a key holding the wrong kind of value",
-                        ))
-                        .await
-                        .unwrap();
-                }
-                _ => {
-                    stream.write(&empty_bulk_string()).await.unwrap();
-                }
-            },
+            _ => {
+                reply.extend(encode_resp_null(client.protocol));
+            }
+        },
+    }
+    Ok(())
+}
+
+pub fn del(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let keys = &pure_cmd[1..];
+    let mut shards = server.db(client.db).lock_keys(keys);
+    let len = keys
+        .iter()
+        .filter(|key| shards.shard(server.db(client.db), key).delete(key))
+        .count();
+    client.dirty += len as u64;
+    reply.extend(encode_resp_integer(len.to_string().as_str()));
+    Ok(())
+}
+
+pub fn mget(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let keys = &pure_cmd[1..];
+    // Hold every shard involved so the values form one consistent snapshot.
+    let mut shards = server.db(client.db).lock_keys(keys);
+    reply.extend(encode_resp_array_header(keys.len()));
+    for key in keys {
+        match shards.shard(server.db(client.db), key).get_string(key) {
+            Ok(value) => reply.push_bulk_string(value),
+            Err(_) => reply.extend(encode_resp_null(client.protocol)),
         }
     }
+    Ok(())
 }
 
-pub async fn del(stream: &mut TcpStream, pure_cmd: Vec<String>, client_store: Arc<Mutex<Storage>>) {
-    if pure_cmd.len() < 2 {
-        stream
-            .write(&encode_resp_error_string("Invalid args for DEL"))
-            .await
-            .unwrap();
-    } else {
-        let keys = pure_cmd[1..pure_cmd.len()].to_vec();
-        let len = client_store.lock().unwrap().delete(keys);
-        stream
-            .write(&encode_resp_integer(len.to_string().as_str()))
-            .await
-            .unwrap();
+/// Parses a database index argument as SELECT, MOVE and COPY take it.
+pub fn db_index(arg: &[u8], server: &Server) -> Result<usize, CommandError> {
+    let index = parse_i64(arg).map_err(|_| CommandError::NotInteger)?;
+    match usize::try_from(index) {
+        Ok(index) if index < server.dbs().len() => Ok(index),
+        _ => Err(CommandError::Other("DB index is out of range".to_string())),
     }
 }
 
-pub async fn undefined(stream: &mut TcpStream) {
-    stream
-        .write(&encode_resp_error_string("Command not recognised"))
-        .await
-        .unwrap();
+pub fn unknown_command(pure_cmd: &[Bytes]) -> CommandError {
+    // Like Redis, quote the arguments until 128 bytes have been shown.
+    let mut args = vec![];
+    let mut shown = 0;
+    for arg in &pure_cmd[1..] {
+        if shown >= 128 {
+            break;
+        }
+        let arg = &arg[..arg.len().min(128 - shown)];
+        shown += arg.len() + 3;
+        args.push(String::from_utf8_lossy(arg).into_owned());
+    }
+    CommandError::UnknownCommand {
+        name: String::from_utf8_lossy(&pure_cmd[0]).into_owned(),
+        args,
+    }
 }
@@ -1,86 +1,78 @@
-use std::sync::{Arc, Mutex};
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use bytes::Bytes;
 
 use crate::{
+    client::Client,
     encoder::*,
-    storage::{Storage, StorageError},
+    error::{CommandError, CommandResult},
+    server::Server,
+    storage::StorageError,
 };
 
-pub async fn queue_add(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    let clock = client_store.lock().unwrap().queue_add(pure_cmd);
+pub fn queue_add(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let added = pure_cmd.len().saturating_sub(2) as u64;
+    let clock = server.storage(client.db, &pure_cmd[1]).queue_add(pure_cmd);
     match clock {
         Ok(()) => {
-            stream
-                .write(&encode_resp_simple_string("OK"))
-                .await
-                .unwrap();
+            client.dirty += added;
+            reply.extend(encode_resp_simple_string("OK"));
         }
         Err(StorageError::BadCommand) => {
-            stream
-                .write(&encode_resp_error_string(
-                    "wrong number of arguments for 'qadd' command",
-                ))
-                .await
-                .unwrap();
+            return Err(CommandError::WrongArity("qadd".to_string()));
         }
         _ => {
-            stream
-                .write(&encode_resp_error_string(
-                    "WRONGTYPE Operation against a key holding the wrong kind of value",
-                ))
-                .await
-                .unwrap();
+            return Err(CommandError::WrongType);
         }
     }
+    Ok(())
 }
 
-pub async fn dequeue(
-    stream: &mut TcpStream,
-    pure_cmd: Vec<String>,
-    client_store: Arc<Mutex<Storage>>,
-) {
-    let clock = client_store.lock().unwrap().dequeue(pure_cmd);
+pub fn dequeue(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let clock = server.storage(client.db, &pure_cmd[1]).dequeue(pure_cmd);
     match clock {
         Ok(s) => {
-            stream.write(&encode_resp_bulk_string(s)).await.unwrap();
+            client.dirty += 1;
+            reply.push_bulk_string(s);
         }
         Err(StorageError::OutOfRange) => {
-            stream.write(&empty_bulk_string()).await.unwrap();
+            reply.extend(encode_resp_null(client.protocol));
         }
         Err(StorageError::NotFound) => {
-            stream.write(&empty_bulk_string()).await.unwrap();
+            reply.extend(encode_resp_null(client.protocol));
         }
         _ => {
-            stream
-                .write(&encode_resp_error_string(
-                    "WRONGTYPE Operation against a key holding the wrong kind of value",
-                ))
-                .await
-                .unwrap();
+            return Err(CommandError::WrongType);
         }
     }
+    Ok(())
 }
 
-pub async fn qlen(stream: &mut TcpStream, pure_cmd: Vec<String>, client_store: Arc<Mutex<Storage>>) {
-    let clock = client_store.lock().unwrap().qlen(pure_cmd);
+pub fn qlen(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let clock = server.storage(client.db, &pure_cmd[1]).qlen(pure_cmd);
     match clock {
         Ok(s) => {
-            stream.write(&encode_resp_integer(s.to_string().as_str())).await.unwrap();
+            reply.extend(encode_resp_integer(s.to_string().as_str()));
         }
         Err(StorageError::NotFound) => {
-            stream.write(&encode_resp_integer(0.to_string().as_str())).await.unwrap();
+            reply.extend(encode_resp_integer(0.to_string().as_str()));
         }
         _ => {
-            stream
-                .write(&encode_resp_error_string(
-                    "WRONGTYPE Operation against a key holding the wrong kind of value",
-                ))
-                .await
-                .unwrap();
+            return Err(CommandError::WrongType);
         }
     }
+    Ok(())
 }
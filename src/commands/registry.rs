@@ -0,0 +1,788 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use bytes::Bytes;
+
+use super::{acl, array, hash, keys, queue, server};
+use crate::{client::Client, encoder::ReplyBuffer, error::CommandResult, server::Server};
+
+pub type Handler = fn(&mut ReplyBuffer, Vec<Bytes>, &mut Client, &Server) -> CommandResult;
+
+/// Command flags, as reported by COMMAND INFO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Write,
+    ReadOnly,
+    DenyOom,
+    Admin,
+    Fast,
+    /// May run before the connection has authenticated.
+    NoAuth,
+    Loading,
+    Stale,
+}
+
+impl Flag {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Flag::Write => "write",
+            Flag::ReadOnly => "readonly",
+            Flag::DenyOom => "denyoom",
+            Flag::Admin => "admin",
+            Flag::Fast => "fast",
+            Flag::NoAuth => "no_auth",
+            Flag::Loading => "loading",
+            Flag::Stale => "stale",
+        }
+    }
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Number of arguments including the command name; a negative arity
+    /// means at least that many.
+    pub arity: i64,
+    pub flags: &'static [Flag],
+    /// Position of the first key, 0 if the command takes none.
+    pub first_key: i64,
+    /// Position of the last key, negative counting from the end.
+    pub last_key: i64,
+    pub key_step: i64,
+    pub acl_categories: &'static [&'static str],
+    pub group: &'static str,
+    pub summary: &'static str,
+    pub handler: Handler,
+}
+
+impl CommandSpec {
+    pub fn has_flag(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub fn check_arity(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    /// The arguments of `args` that are keys.
+    pub fn keys<'a>(&self, args: &'a [Bytes]) -> Vec<&'a Bytes> {
+        if self.first_key == 0 {
+            return vec![];
+        }
+        let last = if self.last_key < 0 {
+            args.len() as i64 + self.last_key
+        } else {
+            self.last_key.min(args.len() as i64 - 1)
+        };
+        if last < self.first_key {
+            return vec![];
+        }
+        (self.first_key as usize..=last as usize)
+            .step_by(self.key_step as usize)
+            .map(|i| &args[i])
+            .collect()
+    }
+}
+
+static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "ping",
+        arity: -1,
+        flags: &[Flag::Fast, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["fast", "connection"],
+        group: "connection",
+        summary: "Returns the server's liveliness response.",
+        handler: super::ping,
+    },
+    CommandSpec {
+        name: "echo",
+        arity: 2,
+        flags: &[Flag::Fast, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["fast", "connection"],
+        group: "connection",
+        summary: "Returns the given string.",
+        handler: super::echo,
+    },
+    CommandSpec {
+        name: "hello",
+        arity: -1,
+        flags: &[Flag::NoAuth, Flag::Fast, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["fast", "connection"],
+        group: "connection",
+        summary: "Handshakes with the server.",
+        handler: super::hello,
+    },
+    CommandSpec {
+        name: "select",
+        arity: 2,
+        flags: &[Flag::Loading, Flag::Stale, Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["fast", "connection"],
+        group: "connection",
+        summary: "Changes the selected database.",
+        handler: super::select,
+    },
+    CommandSpec {
+        name: "auth",
+        arity: -2,
+        flags: &[Flag::NoAuth, Flag::Fast, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["fast", "connection"],
+        group: "connection",
+        summary: "Authenticates the connection.",
+        handler: acl::auth,
+    },
+    CommandSpec {
+        name: "acl",
+        arity: -2,
+        flags: &[Flag::Admin, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        group: "server",
+        summary: "Manages ACL users.",
+        handler: acl::acl,
+    },
+    CommandSpec {
+        name: "config",
+        arity: -2,
+        flags: &[Flag::Admin, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        group: "server",
+        summary: "Reads the server configuration.",
+        handler: server::config,
+    },
+    CommandSpec {
+        name: "command",
+        arity: -1,
+        flags: &[Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["slow", "connection"],
+        group: "server",
+        summary: "Returns detailed information about commands.",
+        handler: server::command,
+    },
+    CommandSpec {
+        name: "info",
+        arity: -1,
+        flags: &[Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["slow", "dangerous"],
+        group: "server",
+        summary: "Returns information and statistics about the server.",
+        handler: server::info,
+    },
+    CommandSpec {
+        name: "memory",
+        arity: -2,
+        flags: &[Flag::ReadOnly],
+        first_key: 2,
+        last_key: 2,
+        key_step: 1,
+        acl_categories: &["read", "slow"],
+        group: "server",
+        summary: "Reports memory use of the server and of single keys.",
+        handler: server::memory,
+    },
+    CommandSpec {
+        name: "debug",
+        arity: -2,
+        flags: &[Flag::Admin, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        group: "server",
+        summary: "Replies with sample values of each RESP type.",
+        handler: server::debug,
+    },
+    CommandSpec {
+        name: "dbsize",
+        arity: 1,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "server",
+        summary: "Returns the number of keys in the database.",
+        handler: server::dbsize,
+    },
+    CommandSpec {
+        name: "flushdb",
+        arity: -1,
+        flags: &[Flag::Write],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "write", "slow", "dangerous"],
+        group: "server",
+        summary: "Removes all keys from the current database.",
+        handler: server::flush,
+    },
+    CommandSpec {
+        name: "flushall",
+        arity: -1,
+        flags: &[Flag::Write],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "write", "slow", "dangerous"],
+        group: "server",
+        summary: "Removes all keys from all databases.",
+        handler: server::flush,
+    },
+    CommandSpec {
+        name: "swapdb",
+        arity: 3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "write", "fast", "dangerous"],
+        group: "server",
+        summary: "Swaps two Redis databases.",
+        handler: server::swapdb,
+    },
+    CommandSpec {
+        name: "save",
+        arity: 1,
+        flags: &[Flag::Admin],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        group: "server",
+        summary: "Synchronously saves the database(s) to disk.",
+        handler: server::save,
+    },
+    CommandSpec {
+        name: "bgsave",
+        arity: -1,
+        flags: &[Flag::Admin],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        group: "server",
+        summary: "Asynchronously saves the database(s) to disk.",
+        handler: server::bgsave,
+    },
+    CommandSpec {
+        name: "lastsave",
+        arity: 1,
+        flags: &[Flag::Loading, Flag::Stale, Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["fast", "dangerous"],
+        group: "server",
+        summary: "Returns the Unix timestamp of the last successful save to disk.",
+        handler: server::lastsave,
+    },
+    CommandSpec {
+        name: "bgrewriteaof",
+        arity: 1,
+        flags: &[Flag::Admin],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        group: "server",
+        summary: "Asynchronously rewrites the append-only file to disk.",
+        handler: server::bgrewriteaof,
+    },
+    CommandSpec {
+        name: "set",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "string", "slow"],
+        group: "string",
+        summary: "Sets the string value of a key, optionally with an expiry.",
+        handler: super::set,
+    },
+    CommandSpec {
+        name: "get",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["read", "string", "fast"],
+        group: "string",
+        summary: "Returns the string value of a key.",
+        handler: super::get,
+    },
+    CommandSpec {
+        name: "mget",
+        arity: -2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: -1,
+        key_step: 1,
+        acl_categories: &["read", "string", "fast"],
+        group: "string",
+        summary: "Atomically returns the string values of one or more keys.",
+        handler: super::mget,
+    },
+    CommandSpec {
+        name: "del",
+        arity: -2,
+        flags: &[Flag::Write],
+        first_key: 1,
+        last_key: -1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "slow"],
+        group: "generic",
+        summary: "Deletes one or more keys.",
+        handler: super::del,
+    },
+    CommandSpec {
+        name: "exists",
+        arity: -2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: -1,
+        key_step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "generic",
+        summary: "Determines whether one or more keys exist.",
+        handler: keys::exists,
+    },
+    CommandSpec {
+        name: "type",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "generic",
+        summary: "Determines the type of value stored at a key.",
+        handler: keys::key_type,
+    },
+    CommandSpec {
+        name: "keys",
+        arity: 2,
+        flags: &[Flag::ReadOnly],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "read", "slow", "dangerous"],
+        group: "generic",
+        summary: "Returns all key names that match a pattern.",
+        handler: keys::keys,
+    },
+    CommandSpec {
+        name: "scan",
+        arity: -2,
+        flags: &[Flag::ReadOnly],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "read", "slow"],
+        group: "generic",
+        summary: "Iterates over the key names in the database.",
+        handler: keys::scan,
+    },
+    CommandSpec {
+        name: "rename",
+        arity: 3,
+        flags: &[Flag::Write],
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "slow"],
+        group: "generic",
+        summary: "Renames a key and overwrites the destination.",
+        handler: keys::rename,
+    },
+    CommandSpec {
+        name: "renamenx",
+        arity: 3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Renames a key only when the target key name doesn't exist.",
+        handler: keys::rename,
+    },
+    CommandSpec {
+        name: "randomkey",
+        arity: 1,
+        flags: &[Flag::ReadOnly],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        acl_categories: &["keyspace", "read", "slow"],
+        group: "generic",
+        summary: "Returns a random key name from the database.",
+        handler: keys::randomkey,
+    },
+    CommandSpec {
+        name: "move",
+        arity: 3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Moves a key to another database.",
+        handler: keys::move_key,
+    },
+    CommandSpec {
+        name: "copy",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom],
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "slow"],
+        group: "generic",
+        summary: "Copies the value of a key to a new key.",
+        handler: keys::copy,
+    },
+    CommandSpec {
+        name: "expire",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Sets the expiration time of a key in seconds.",
+        handler: keys::expire,
+    },
+    CommandSpec {
+        name: "pexpire",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Sets the expiration time of a key in milliseconds.",
+        handler: keys::expire,
+    },
+    CommandSpec {
+        name: "expireat",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+        handler: keys::expire,
+    },
+    CommandSpec {
+        name: "pexpireat",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        handler: keys::expire,
+    },
+    CommandSpec {
+        name: "ttl",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "generic",
+        summary: "Returns the expiration time in seconds of a key.",
+        handler: keys::ttl,
+    },
+    CommandSpec {
+        name: "pttl",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "generic",
+        summary: "Returns the expiration time in milliseconds of a key.",
+        handler: keys::ttl,
+    },
+    CommandSpec {
+        name: "expiretime",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "generic",
+        summary: "Returns the expiration time of a key as a Unix timestamp.",
+        handler: keys::ttl,
+    },
+    CommandSpec {
+        name: "pexpiretime",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        group: "generic",
+        summary: "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+        handler: keys::ttl,
+    },
+    CommandSpec {
+        name: "persist",
+        arity: 2,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        group: "generic",
+        summary: "Removes the expiration time of a key.",
+        handler: keys::persist,
+    },
+    CommandSpec {
+        name: "llen",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["read", "list", "fast"],
+        group: "list",
+        summary: "Returns the length of a list.",
+        handler: array::llen,
+    },
+    CommandSpec {
+        name: "lpop",
+        arity: -2,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "fast"],
+        group: "list",
+        summary: "Removes and returns the first elements of a list.",
+        handler: array::lpop,
+    },
+    CommandSpec {
+        name: "rpop",
+        arity: -2,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "fast"],
+        group: "list",
+        summary: "Removes and returns the last elements of a list.",
+        handler: array::rpop,
+    },
+    CommandSpec {
+        name: "lrem",
+        arity: 4,
+        flags: &[Flag::Write],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "slow"],
+        group: "list",
+        summary: "Removes elements from a list.",
+        handler: array::lrem,
+    },
+    CommandSpec {
+        name: "lset",
+        arity: 4,
+        flags: &[Flag::Write, Flag::DenyOom],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "slow"],
+        group: "list",
+        summary: "Sets the value of an element in a list by its index.",
+        handler: array::lset,
+    },
+    CommandSpec {
+        name: "lpush",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "fast"],
+        group: "list",
+        summary: "Prepends one or more elements to a list.",
+        handler: array::lpush,
+    },
+    CommandSpec {
+        name: "rpush",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "fast"],
+        group: "list",
+        summary: "Appends one or more elements to a list.",
+        handler: array::rpush,
+    },
+    CommandSpec {
+        name: "lpushx",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "fast"],
+        group: "list",
+        summary: "Prepends one or more elements to a list only when the list exists.",
+        handler: array::lpushx,
+    },
+    CommandSpec {
+        name: "rpushx",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "list", "fast"],
+        group: "list",
+        summary: "Appends one or more elements to a list only when the list exists.",
+        handler: array::rpushx,
+    },
+    CommandSpec {
+        name: "lrange",
+        arity: 4,
+        flags: &[Flag::ReadOnly],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["read", "list", "slow"],
+        group: "list",
+        summary: "Returns a range of elements from a list.",
+        handler: array::lrange,
+    },
+    CommandSpec {
+        name: "lindex",
+        arity: 3,
+        flags: &[Flag::ReadOnly],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["read", "list", "slow"],
+        group: "list",
+        summary: "Returns an element from a list by its index.",
+        handler: array::lindex,
+    },
+    CommandSpec {
+        name: "hset",
+        arity: -4,
+        flags: &[Flag::Write, Flag::DenyOom, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "hash", "fast"],
+        group: "hash",
+        summary: "Sets the values of one or more fields in a hash.",
+        handler: hash::hash_set,
+    },
+    CommandSpec {
+        name: "hgetall",
+        arity: 2,
+        flags: &[Flag::ReadOnly],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["read", "hash", "slow"],
+        group: "hash",
+        summary: "Returns all fields and values in a hash.",
+        handler: hash::hash_get_all,
+    },
+    CommandSpec {
+        name: "qadd",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "queue", "fast"],
+        group: "queue",
+        summary: "Appends one or more items to a queue.",
+        handler: queue::queue_add,
+    },
+    CommandSpec {
+        name: "qread",
+        arity: 2,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["write", "queue", "fast"],
+        group: "queue",
+        summary: "Removes and returns the oldest item of a queue.",
+        handler: queue::dequeue,
+    },
+    CommandSpec {
+        name: "qlen",
+        arity: 2,
+        flags: &[Flag::ReadOnly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        acl_categories: &["read", "queue", "fast"],
+        group: "queue",
+        summary: "Returns the number of items in a queue.",
+        handler: queue::qlen,
+    },
+];
+
+pub fn commands() -> &'static [CommandSpec] {
+    COMMANDS
+}
+
+/// Finds a command by its lowercase name.
+pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    static BY_NAME: OnceLock<HashMap<&'static [u8], &'static CommandSpec>> = OnceLock::new();
+    BY_NAME
+        .get_or_init(|| COMMANDS.iter().map(|c| (c.name.as_bytes(), c)).collect())
+        .get(name)
+        .copied()
+}
@@ -0,0 +1,723 @@
+use std::{fmt::Write, process};
+
+use bytes::Bytes;
+
+use super::registry::{self, CommandSpec};
+use crate::{
+    aof,
+    client::Client,
+    decoder::{parse_i64, parse_u64},
+    encoder::*,
+    error::{CommandError, CommandResult},
+    glob::glob_match,
+    rdb,
+    server::Server,
+    storage::TYPE_NAMES,
+};
+
+pub fn config(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    match pure_cmd[1].to_ascii_lowercase().as_slice() {
+        b"get" if pure_cmd.len() > 2 => {
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = server
+                .config
+                .entries()
+                .into_iter()
+                .filter(|(name, _)| {
+                    pure_cmd[2..]
+                        .iter()
+                        .any(|pattern| glob_match(pattern, name.as_bytes(), true))
+                })
+                .map(|(name, value)| {
+                    (
+                        encode_resp_bulk_string(name.as_bytes()),
+                        encode_resp_bulk_string(value.as_bytes()),
+                    )
+                })
+                .collect();
+            reply.extend(encode_resp_map(entries, client.protocol));
+        }
+        b"get" => {
+            return Err(CommandError::WrongArity("config|get".to_string()));
+        }
+        _ => {
+            return Err(CommandError::UnknownSubcommand {
+                command: "config",
+                subcommand: String::from_utf8_lossy(&pure_cmd[1]).into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn command_info(command: &CommandSpec, protocol: Protocol) -> Vec<u8> {
+    let simple_strings = |items: Vec<String>| {
+        encode_resp_set(
+            items.iter().map(|s| encode_resp_simple_string(s)).collect(),
+            protocol,
+        )
+    };
+    encode_resp_array(vec![
+        encode_resp_bulk_string(command.name.as_bytes()),
+        encode_resp_integer(&command.arity.to_string()),
+        simple_strings(
+            command
+                .flags
+                .iter()
+                .map(|f| f.as_str().to_string())
+                .collect(),
+        ),
+        encode_resp_integer(&command.first_key.to_string()),
+        encode_resp_integer(&command.last_key.to_string()),
+        encode_resp_integer(&command.key_step.to_string()),
+        simple_strings(
+            command
+                .acl_categories
+                .iter()
+                .map(|c| format!("@{}", c))
+                .collect(),
+        ),
+        // Tips, key specifications and subcommands.
+        encode_resp_empty_array(),
+        encode_resp_empty_array(),
+        encode_resp_empty_array(),
+    ])
+}
+
+fn command_docs(command: &CommandSpec, protocol: Protocol) -> Vec<u8> {
+    let bulk = |s: &str| encode_resp_bulk_string(s.as_bytes());
+    encode_resp_map(
+        vec![
+            (bulk("summary"), bulk(command.summary)),
+            (bulk("group"), bulk(command.group)),
+        ],
+        protocol,
+    )
+}
+
+/// The commands named in `names`, or every command when none are given.
+fn named_commands(names: &[Bytes]) -> Vec<Option<&'static CommandSpec>> {
+    if names.is_empty() {
+        return registry::commands().iter().map(Some).collect();
+    }
+    names
+        .iter()
+        .map(|name| registry::lookup(&name.to_ascii_lowercase()))
+        .collect()
+}
+
+pub fn command(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    _server: &Server,
+) -> CommandResult {
+    if pure_cmd.len() == 1 {
+        reply.extend(encode_resp_array(
+            registry::commands()
+                .iter()
+                .map(|c| command_info(c, client.protocol))
+                .collect(),
+        ));
+        return Ok(());
+    }
+    match pure_cmd[1].to_ascii_lowercase().as_slice() {
+        b"count" if pure_cmd.len() == 2 => {
+            reply.extend(encode_resp_integer(&registry::commands().len().to_string()));
+        }
+        b"info" => {
+            let infos = named_commands(&pure_cmd[2..])
+                .into_iter()
+                .map(|c| match c {
+                    Some(c) => command_info(c, client.protocol),
+                    None => encode_resp_null(client.protocol),
+                })
+                .collect();
+            reply.extend(encode_resp_array(infos));
+        }
+        b"docs" => {
+            // Unknown names are left out rather than reported as null.
+            let docs = named_commands(&pure_cmd[2..])
+                .into_iter()
+                .flatten()
+                .map(|c| {
+                    (
+                        encode_resp_bulk_string(c.name.as_bytes()),
+                        command_docs(c, client.protocol),
+                    )
+                })
+                .collect();
+            reply.extend(encode_resp_map(docs, client.protocol));
+        }
+        b"count" => {
+            return Err(CommandError::WrongArity("command|count".to_string()));
+        }
+        _ => {
+            return Err(CommandError::UnknownSubcommand {
+                command: "command",
+                subcommand: String::from_utf8_lossy(&pure_cmd[1]).into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// INFO sections, in the order they are printed.
+const INFO_SECTIONS: &[&str] = &["server", "memory", "persistence", "stats", "keyspace"];
+
+fn info_section(name: &str, server: &Server, out: &mut String) {
+    match name {
+        "server" => {
+            let _ = write!(
+                out,
+                "# Server\r\nappledore_version:{}\r\nprocess_id:{}\r\ntcp_port:{}\r\nuptime_in_seconds:{}\r\nhz:{}\r\n",
+                env!("CARGO_PKG_VERSION"),
+                process::id(),
+                server.config.port,
+                server.started.elapsed().as_secs(),
+                server.config.hz,
+            );
+        }
+        "memory" => {
+            let totals = MemoryTotals::collect(server);
+            let _ = write!(
+                out,
+                "# Memory\r\nused_memory:{}\r\nused_memory_peak:{}\r\nused_memory_overhead:{}\r\nused_memory_dataset:{}\r\n",
+                server.memory.used(),
+                server.memory.peak(),
+                totals.overhead,
+                totals.dataset(),
+            );
+            for (name, bytes) in TYPE_NAMES.iter().zip(totals.by_type) {
+                let _ = write!(out, "used_memory_{}:{}\r\n", name, bytes);
+            }
+            let _ = write!(
+                out,
+                "maxmemory:{}\r\nmaxmemory_policy:{}\r\n",
+                server.config.maxmemory,
+                server.config.maxmemory_policy.as_str(),
+            );
+        }
+        "persistence" => {
+            let saves = &server.saves;
+            let _ = write!(
+                out,
+                "# Persistence\r\nloading:0\r\nrdb_changes_since_last_save:{}\r\nrdb_bgsave_in_progress:{}\r\nrdb_last_save_time:{}\r\nrdb_last_bgsave_status:{}\r\n",
+                saves.dirty(),
+                saves.in_progress() as u8,
+                saves.last_save(),
+                if saves.last_ok() { "ok" } else { "err" },
+            );
+            let aof = server.aof.as_ref();
+            let _ = write!(
+                out,
+                "aof_enabled:{}\r\naof_rewrite_in_progress:{}\r\naof_last_bgrewrite_status:{}\r\n",
+                aof.is_some() as u8,
+                aof.is_some_and(|a| a.rewrite_in_progress()) as u8,
+                if aof.map(|a| a.last_rewrite_ok()).unwrap_or(true) {
+                    "ok"
+                } else {
+                    "err"
+                },
+            );
+        }
+        "stats" => {
+            let (mut expired, mut evicted) = (0, 0);
+            for db in server.dbs() {
+                for i in 0..db.shard_count() {
+                    let shard = db.shard_at(i);
+                    expired += shard.expired_keys;
+                    evicted += shard.evicted_keys;
+                }
+            }
+            let _ = write!(
+                out,
+                "# Stats\r\nexpired_keys:{}\r\nevicted_keys:{}\r\n",
+                expired, evicted
+            );
+        }
+        "keyspace" => {
+            out.push_str("# Keyspace\r\n");
+            for (index, db) in server.dbs().iter().enumerate() {
+                let (mut keys, mut expires) = (0, 0);
+                for i in 0..db.shard_count() {
+                    let (k, e) = db.shard_at(i).key_counts();
+                    keys += k;
+                    expires += e;
+                }
+                if keys > 0 {
+                    let _ = write!(
+                        out,
+                        "db{}:keys={},expires={},avg_ttl=0\r\n",
+                        index, keys, expires
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn info(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let requested: Vec<String> = pure_cmd[1..]
+        .iter()
+        .map(|s| String::from_utf8_lossy(s).to_ascii_lowercase())
+        .collect();
+    let everything = requested.is_empty()
+        || requested
+            .iter()
+            .any(|s| matches!(s.as_str(), "all" | "default" | "everything"));
+    let mut out = String::new();
+    for section in INFO_SECTIONS {
+        if everything || requested.iter().any(|s| s == section) {
+            if !out.is_empty() {
+                out.push_str("\r\n");
+            }
+            info_section(section, server, &mut out);
+        }
+    }
+    reply.extend(encode_resp_verbatim_string(
+        "txt",
+        out.as_bytes(),
+        client.protocol,
+    ));
+    Ok(())
+}
+
+/// Memory figures summed over every shard.
+struct MemoryTotals {
+    keys: usize,
+    by_type: [usize; TYPE_NAMES.len()],
+    overhead: usize,
+}
+
+impl MemoryTotals {
+    fn collect(server: &Server) -> Self {
+        let mut totals = MemoryTotals {
+            keys: 0,
+            by_type: [0; TYPE_NAMES.len()],
+            overhead: 0,
+        };
+        for db in server.dbs() {
+            for i in 0..db.shard_count() {
+                let shard = db.shard_at(i);
+                totals.keys += shard.key_counts().0;
+                totals.overhead += shard.overhead();
+                for (total, bytes) in totals.by_type.iter_mut().zip(shard.memory_by_type()) {
+                    *total += bytes;
+                }
+            }
+        }
+        totals
+    }
+
+    fn used(&self) -> usize {
+        self.by_type.iter().sum()
+    }
+
+    fn dataset(&self) -> usize {
+        self.used() - self.overhead
+    }
+}
+
+/// Plain language findings about memory use, like Redis' MEMORY DOCTOR.
+fn memory_doctor(server: &Server) -> String {
+    let totals = MemoryTotals::collect(server);
+    let used = totals.used();
+    let peak = server.memory.peak();
+    let config = &server.config;
+    if used < 1024 * 1024 {
+        return "This instance is empty or is using very little memory, so there is nothing to diagnose yet.".to_string();
+    }
+    let mut findings = vec![];
+    if peak > used / 2 * 3 {
+        findings.push(format!(
+            "Peak memory: at some point {} bytes were used, more than 150% of the {} bytes used now. If that peak is expected, the instance must have room for it.",
+            peak, used
+        ));
+    }
+    if totals.overhead > used / 2 {
+        findings.push(format!(
+            "High key overhead: {} of {} bytes are spent on per key bookkeeping. Many small keys could be grouped into hashes.",
+            totals.overhead, used
+        ));
+    }
+    if config.maxmemory > 0 && used as u64 > config.maxmemory / 10 * 9 {
+        findings.push(format!(
+            "Near the limit: {} of the {} maxmemory bytes are in use and the {} policy is in effect.",
+            used,
+            config.maxmemory,
+            config.maxmemory_policy.as_str()
+        ));
+    }
+    if findings.is_empty() {
+        return "No memory issues found.".to_string();
+    }
+    let mut report = "Memory issues found:\n\n".to_string();
+    for finding in findings {
+        report.push_str(&format!(" * {}\n", finding));
+    }
+    report
+}
+
+pub fn memory(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let subcommand = pure_cmd[1].to_ascii_lowercase();
+    match (subcommand.as_slice(), pure_cmd.len()) {
+        (b"usage", n) if n == 3 || n == 5 => {
+            // Redis looks at five elements of a collection by default.
+            let mut samples = 5;
+            if n == 5 {
+                if pure_cmd[3].to_ascii_lowercase() != b"samples" {
+                    return Err(CommandError::Syntax);
+                }
+                samples = parse_u64(&pure_cmd[4]).map_err(|_| CommandError::NotInteger)? as usize;
+            }
+            match server
+                .storage(client.db, &pure_cmd[2])
+                .memory_usage(&pure_cmd[2], samples)
+            {
+                Some(bytes) => reply.extend(encode_resp_integer(&bytes.to_string())),
+                None => reply.extend(encode_resp_null(client.protocol)),
+            }
+        }
+        (b"stats", 2) => {
+            let totals = MemoryTotals::collect(server);
+            let bulk = |s: &str| encode_resp_bulk_string(s.as_bytes());
+            let int = |n: usize| encode_resp_integer(&n.to_string());
+            let used = totals.used();
+            let mut entries = vec![
+                (bulk("peak.allocated"), int(server.memory.peak())),
+                (bulk("total.allocated"), int(used)),
+                (bulk("overhead.total"), int(totals.overhead)),
+                (bulk("keys.count"), int(totals.keys)),
+                (
+                    bulk("keys.bytes-per-key"),
+                    int(used.checked_div(totals.keys).unwrap_or(0)),
+                ),
+                (bulk("dataset.bytes"), int(totals.dataset())),
+                (
+                    bulk("dataset.percentage"),
+                    encode_resp_double(
+                        if used == 0 {
+                            0.0
+                        } else {
+                            totals.dataset() as f64 * 100.0 / used as f64
+                        },
+                        client.protocol,
+                    ),
+                ),
+            ];
+            for (name, bytes) in TYPE_NAMES.iter().zip(totals.by_type) {
+                entries.push((bulk(&format!("keys.{}.bytes", name)), int(bytes)));
+            }
+            reply.extend(encode_resp_map(entries, client.protocol));
+        }
+        (b"doctor", 2) => {
+            reply.extend(encode_resp_verbatim_string(
+                "txt",
+                memory_doctor(server).as_bytes(),
+                client.protocol,
+            ));
+        }
+        (b"help", 2) => {
+            let lines = [
+                "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "DOCTOR",
+                "    Return memory problems reports.",
+                "STATS",
+                "    Return information about the memory usage of the server.",
+                "USAGE <key> [SAMPLES <count>]",
+                "    Return memory in bytes used by <key> and its value. Nested values are",
+                "    sampled up to <count> times (default: 5, 0 means sample all).",
+                "HELP",
+                "    Print this help.",
+            ];
+            reply.extend(encode_resp_array(
+                lines.iter().map(|l| encode_resp_simple_string(l)).collect(),
+            ));
+        }
+        (b"usage", _) | (b"stats", _) | (b"doctor", _) | (b"help", _) => {
+            return Err(CommandError::WrongArity(format!(
+                "memory|{}",
+                String::from_utf8_lossy(&subcommand)
+            )));
+        }
+        _ => {
+            return Err(CommandError::UnknownSubcommand {
+                command: "memory",
+                subcommand: String::from_utf8_lossy(&pure_cmd[1]).into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// DEBUG PROTOCOL, which replies with a sample of each reply type so client
+/// libraries can test their RESP3 parsing. Other DEBUG subcommands are not
+/// supported.
+pub fn debug(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    _server: &Server,
+) -> CommandResult {
+    let protocol = client.protocol;
+    let subcommand = pure_cmd[1].to_ascii_lowercase();
+    match (subcommand.as_slice(), pure_cmd.len()) {
+        (b"protocol", 3) => {
+            // The sample Redis replies with, not an approximation of pi.
+            #[allow(clippy::approx_constant)]
+            let double = 3.141;
+            let integers = || {
+                (0..3)
+                    .map(|i| encode_resp_integer(&i.to_string()))
+                    .collect()
+            };
+            let frame = match pure_cmd[2].to_ascii_lowercase().as_slice() {
+                b"string" => encode_resp_bulk_string(b"Hello World"),
+                b"integer" => encode_resp_integer("12345"),
+                b"double" => encode_resp_double(double, protocol),
+                b"bignum" => {
+                    encode_resp_big_number("1234567999999999999999999999999999999", protocol)
+                }
+                b"null" => encode_resp_null(protocol),
+                b"array" => encode_resp_array(integers()),
+                b"set" => encode_resp_set(integers(), protocol),
+                b"map" => encode_resp_map(
+                    (0..3)
+                        .map(|i| {
+                            (
+                                encode_resp_integer(&i.to_string()),
+                                encode_resp_boolean(i == 1, protocol),
+                            )
+                        })
+                        .collect(),
+                    protocol,
+                ),
+                b"push" => {
+                    if protocol == Protocol::Resp2 {
+                        return Err(CommandError::Other(
+                            "RESP2 is not supported by this command".to_string(),
+                        ));
+                    }
+                    reply.extend(encode_resp_push(
+                        vec![
+                            encode_resp_bulk_string(b"server-cpu-usage"),
+                            encode_resp_integer("42"),
+                        ],
+                        protocol,
+                    ));
+                    encode_resp_bulk_string(b"Some real reply following the push reply")
+                }
+                b"verbatim" => {
+                    encode_resp_verbatim_string("txt", b"This is a verbatim\nstring", protocol)
+                }
+                b"true" => encode_resp_boolean(true, protocol),
+                b"false" => encode_resp_boolean(false, protocol),
+                _ => {
+                    return Err(CommandError::Other(
+                        "Wrong protocol type name. Please use one of the following: string|integer|double|bignum|null|array|set|map|push|verbatim|true|false".to_string(),
+                    ));
+                }
+            };
+            reply.extend(frame);
+        }
+        (b"help", 2) => {
+            let lines = [
+                "DEBUG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "PROTOCOL <type>",
+                "    Reply with a test value of the specified type. <type> can be: string,",
+                "    integer, double, bignum, null, array, set, map, push, verbatim, true,",
+                "    false.",
+                "HELP",
+                "    Print this help.",
+            ];
+            reply.extend(encode_resp_array(
+                lines.iter().map(|l| encode_resp_simple_string(l)).collect(),
+            ));
+        }
+        _ => {
+            return Err(CommandError::UnknownSubcommand {
+                command: "debug",
+                subcommand: String::from_utf8_lossy(&pure_cmd[1]).into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn save(
+    reply: &mut ReplyBuffer,
+    _pure_cmd: Vec<Bytes>,
+    _client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    match rdb::save(server) {
+        Some(Ok(())) => reply.extend(encode_resp_simple_string("OK")),
+        Some(Err(e)) => {
+            eprintln!("Error saving DB on disk: {}", e);
+            return Err(CommandError::Other(format!(
+                "Error saving DB on disk: {}",
+                e
+            )));
+        }
+        None => {
+            return Err(CommandError::Other(
+                "Background save already in progress".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// BGSAVE [SCHEDULE]. With SCHEDULE a save that is already running is not
+/// an error; another one starts once it finishes.
+pub fn bgsave(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    _client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let schedule = match pure_cmd.len() {
+        1 => false,
+        2 if pure_cmd[1].eq_ignore_ascii_case(b"schedule") => true,
+        _ => return Err(CommandError::Syntax),
+    };
+    match rdb::bgsave(server) {
+        Some(Ok(())) => reply.extend(encode_resp_simple_string("Background saving started")),
+        Some(Err(e)) => {
+            return Err(CommandError::Other(format!(
+                "Background saving failed to start: {}",
+                e
+            )))
+        }
+        None if schedule => {
+            server.saves.schedule();
+            reply.extend(encode_resp_simple_string("Background saving scheduled"));
+        }
+        None => {
+            return Err(CommandError::Other(
+                "Background save already in progress".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+pub fn bgrewriteaof(
+    reply: &mut ReplyBuffer,
+    _pure_cmd: Vec<Bytes>,
+    _client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let log = match &server.aof {
+        Some(log) => log,
+        None => {
+            return Err(CommandError::Other(
+                "Append only file is turned off, set appendonly yes to use it".to_string(),
+            ))
+        }
+    };
+    match aof::bgrewrite(server, log) {
+        Some(Ok(())) => reply.extend(encode_resp_simple_string(
+            "Background append only file rewriting started",
+        )),
+        Some(Err(e)) => {
+            return Err(CommandError::Other(format!(
+                "Background append only file rewriting failed to start: {}",
+                e
+            )))
+        }
+        None => {
+            return Err(CommandError::Other(
+                "Background append only file rewriting already in progress".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+pub fn lastsave(
+    reply: &mut ReplyBuffer,
+    _pure_cmd: Vec<Bytes>,
+    _client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    reply.extend(encode_resp_integer(&server.saves.last_save().to_string()));
+    Ok(())
+}
+
+pub fn dbsize(
+    reply: &mut ReplyBuffer,
+    _pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    reply.extend(encode_resp_integer(
+        &server.db(client.db).key_count().to_string(),
+    ));
+    Ok(())
+}
+
+pub fn swapdb(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let index = |arg: &[u8], which: &str| -> Result<usize, CommandError> {
+        let index = parse_i64(arg)
+            .map_err(|_| CommandError::Other(format!("invalid {} DB index", which)))?;
+        match usize::try_from(index) {
+            Ok(index) if index < server.dbs().len() => Ok(index),
+            _ => Err(CommandError::Other("DB index is out of range".to_string())),
+        }
+    };
+    let a = index(&pure_cmd[1], "first")?;
+    let b = index(&pure_cmd[2], "second")?;
+    if a != b {
+        server.db(a.min(b)).swap(server.db(a.max(b)));
+    }
+    client.dirty += 1;
+    reply.extend(encode_resp_simple_string("OK"));
+    Ok(())
+}
+
+/// FLUSHDB and FLUSHALL. ASYNC is accepted but the keys are always freed
+/// right away.
+pub fn flush(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    match pure_cmd.get(1).map(|m| m.to_ascii_lowercase()).as_deref() {
+        None | Some(b"sync") | Some(b"async") if pure_cmd.len() <= 2 => {}
+        _ => return Err(CommandError::Syntax),
+    }
+    if pure_cmd[0].eq_ignore_ascii_case(b"flushall") {
+        for db in server.dbs() {
+            db.clear();
+        }
+    } else {
+        server.db(client.db).clear();
+    }
+    // Logged even when there was nothing to remove, as Redis does.
+    client.dirty += 1;
+    reply.extend(encode_resp_simple_string("OK"));
+    Ok(())
+}
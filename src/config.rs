@@ -0,0 +1,648 @@
+use std::{fmt, fs, path::PathBuf};
+
+use crate::decoder::split_args;
+
+const ENV_PREFIX: &str = "APPLEDORE_";
+/// The save rules Redis uses when the configuration sets none: after an
+/// hour if anything changed, 5 minutes after 100 changes and a minute
+/// after 10000.
+const DEFAULT_SAVE_RULES: [SaveRule; 3] = [
+    SaveRule {
+        seconds: 3600,
+        changes: 1,
+    },
+    SaveRule {
+        seconds: 300,
+        changes: 100,
+    },
+    SaveRule {
+        seconds: 60,
+        changes: 10000,
+    },
+];
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether TLS clients must present a certificate signed by the CA bundle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    No,
+    Yes,
+    Optional,
+}
+
+/// What to do when a write would take memory past `maxmemory`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MaxmemoryPolicy::NoEviction => "noeviction",
+            MaxmemoryPolicy::AllKeysLru => "allkeys-lru",
+            MaxmemoryPolicy::AllKeysLfu => "allkeys-lfu",
+            MaxmemoryPolicy::AllKeysRandom => "allkeys-random",
+            MaxmemoryPolicy::VolatileLru => "volatile-lru",
+            MaxmemoryPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// Whether only keys with an expiry may be evicted.
+    pub fn volatile(self) -> bool {
+        matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru | MaxmemoryPolicy::VolatileTtl
+        )
+    }
+}
+
+/// When writes logged to the append only file are flushed to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// Before every write is acknowledged.
+    Always,
+    /// Once a second, from a background thread.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No,
+}
+
+impl AppendFsync {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AppendFsync::Always => "always",
+            AppendFsync::EverySec => "everysec",
+            AppendFsync::No => "no",
+        }
+    }
+}
+
+/// Snapshot the dataset once `changes` writes happened and `seconds`
+/// passed since the last snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Server settings, assembled from (in increasing priority) built-in
+/// defaults, a redis.conf style file given as the first argument,
+/// `APPLEDORE_*` environment variables and `--name value...` flags.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub bind: Vec<String>,
+    pub port: u16,
+    pub unixsocket: Option<String>,
+    pub unixsocketperm: Option<u32>,
+    pub tls_port: Option<u16>,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    pub tls_ca_cert_file: Option<String>,
+    pub tls_auth_clients: TlsAuthClients,
+    pub requirepass: Option<String>,
+    /// `user <name> <rules...>` directives, applied to the ACL at startup.
+    pub users: Vec<Vec<String>>,
+    /// How many times a second the active expiry cycle runs.
+    pub hz: u32,
+    /// Number of logical databases.
+    pub databases: usize,
+    /// Memory limit in bytes; 0 means no limit.
+    pub maxmemory: u64,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// Keys sampled to pick each eviction victim.
+    pub maxmemory_samples: usize,
+    /// Directory the snapshot is written to.
+    pub dir: String,
+    pub dbfilename: String,
+    /// `None` until a `save` directive is seen, meaning the defaults.
+    save: Option<Vec<SaveRule>>,
+    /// Log every write to the append only file and load from it at startup.
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+    /// Load an append only file whose last command was cut short, instead
+    /// of refusing to start.
+    pub aof_load_truncated: bool,
+    /// A Redis RDB file imported at startup, over the data loaded from disk.
+    pub import_rdb: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: vec!["0.0.0.0".to_string()],
+            port: 6379,
+            unixsocket: None,
+            unixsocketperm: None,
+            tls_port: None,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: TlsAuthClients::Yes,
+            requirepass: None,
+            users: vec![],
+            hz: 10,
+            databases: 16,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            save: None,
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: AppendFsync::EverySec,
+            aof_load_truncated: true,
+            import_rdb: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load<A, E>(args: A, env: E) -> Result<Self, ConfigError>
+    where
+        A: IntoIterator<Item = String>,
+        E: IntoIterator<Item = (String, String)>,
+    {
+        let mut config = ServerConfig::default();
+        let mut args = args.into_iter().peekable();
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            config.load_file(&path)?;
+        }
+
+        for (name, value) in env {
+            let directive = match name.strip_prefix(ENV_PREFIX) {
+                Some(d) => d.to_ascii_lowercase().replace('_', "-"),
+                None => continue,
+            };
+            // Scripts and CI setups export their own variables with the
+            // prefix, so only settings we know are applied.
+            if !is_directive(&directive) {
+                eprintln!("Ignoring unknown environment variable {}", name);
+                continue;
+            }
+            let values = split_config_line(&value)
+                .map_err(|e| ConfigError(format!("environment variable {}: {}", name, e)))?;
+            config
+                .set(&directive, &values)
+                .map_err(|e| ConfigError(format!("environment variable {}: {}", name, e)))?;
+        }
+
+        let mut flag: Option<(String, Vec<String>)> = None;
+        for arg in args {
+            if let Some(name) = arg.strip_prefix("--") {
+                if let Some((name, values)) = flag.take() {
+                    config.set_flag(&name, &values)?;
+                }
+                flag = Some((name.to_ascii_lowercase(), vec![]));
+            } else {
+                match flag.as_mut() {
+                    Some((_, values)) => values.push(arg),
+                    None => return Err(ConfigError(format!("unexpected argument '{}'", arg))),
+                }
+            }
+        }
+        if let Some((name, values)) = flag {
+            config.set_flag(&name, &values)?;
+        }
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), ConfigError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("can't open config file '{}': {}", path, e)))?;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = split_config_line(line).and_then(|mut argv| {
+                let name = argv.remove(0).to_ascii_lowercase();
+                self.set(&name, &argv)
+            });
+            if let Err(e) = result {
+                return Err(ConfigError(format!(
+                    "{} line {}: >>> '{}'\n{}",
+                    path,
+                    i + 1,
+                    line,
+                    e
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_flag(&mut self, name: &str, values: &[String]) -> Result<(), ConfigError> {
+        self.set(name, values)
+            .map_err(|e| ConfigError(format!("--{}: {}", name, e)))
+    }
+
+    pub fn set(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        match (name, args) {
+            ("bind", addrs) if !addrs.is_empty() => self.bind = addrs.to_vec(),
+            ("port", [port]) => {
+                self.port = port.parse().map_err(|_| "Invalid port".to_string())?;
+            }
+            ("unixsocket", [path]) => self.unixsocket = Some(path.to_owned()),
+            ("unixsocketperm", [perm]) => {
+                let mode = u32::from_str_radix(perm, 8)
+                    .map_err(|_| "Invalid socket file permissions".to_string())?;
+                self.unixsocketperm = Some(mode);
+            }
+            ("tls-port", [port]) => {
+                let port: u16 = port.parse().map_err(|_| "Invalid port".to_string())?;
+                self.tls_port = if port == 0 { None } else { Some(port) };
+            }
+            ("tls-cert-file", [path]) => self.tls_cert_file = Some(path.to_owned()),
+            ("tls-key-file", [path]) => self.tls_key_file = Some(path.to_owned()),
+            ("tls-ca-cert-file", [path]) => self.tls_ca_cert_file = Some(path.to_owned()),
+            ("tls-auth-clients", [value]) => {
+                self.tls_auth_clients = match value.to_ascii_lowercase().as_str() {
+                    "no" => TlsAuthClients::No,
+                    "yes" => TlsAuthClients::Yes,
+                    "optional" => TlsAuthClients::Optional,
+                    _ => return Err("argument must be 'yes', 'no' or 'optional'".to_string()),
+                };
+            }
+            ("requirepass", [pass]) => {
+                self.requirepass = if pass.is_empty() {
+                    None
+                } else {
+                    Some(pass.to_owned())
+                };
+            }
+            ("user", rules) if !rules.is_empty() => self.users.push(rules.to_vec()),
+            ("hz", [hz]) => {
+                let hz: u32 = hz.parse().map_err(|_| "Invalid hz value".to_string())?;
+                // Redis clamps out of range values instead of refusing them.
+                self.hz = hz.clamp(1, 500);
+            }
+            ("databases", [n]) => {
+                self.databases = match n.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err("Invalid number of databases".to_string()),
+                };
+            }
+            ("maxmemory", [size]) => self.maxmemory = parse_memory(size)?,
+            ("maxmemory-policy", [policy]) => {
+                self.maxmemory_policy = match policy.to_ascii_lowercase().as_str() {
+                    "noeviction" => MaxmemoryPolicy::NoEviction,
+                    "allkeys-lru" => MaxmemoryPolicy::AllKeysLru,
+                    "allkeys-lfu" => MaxmemoryPolicy::AllKeysLfu,
+                    "allkeys-random" => MaxmemoryPolicy::AllKeysRandom,
+                    "volatile-lru" => MaxmemoryPolicy::VolatileLru,
+                    "volatile-ttl" => MaxmemoryPolicy::VolatileTtl,
+                    _ => return Err("Invalid maxmemory policy".to_string()),
+                };
+            }
+            ("maxmemory-samples", [samples]) => {
+                self.maxmemory_samples = match samples.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err("Invalid maxmemory-samples value".to_string()),
+                };
+            }
+            ("dir", [dir]) => self.dir = dir.to_owned(),
+            ("dbfilename", [name]) => {
+                if name.contains('/') {
+                    return Err("dbfilename can't be a path, just a filename".to_string());
+                }
+                self.dbfilename = name.to_owned();
+            }
+            // `save ""` turns snapshots off; every other `save` line adds
+            // rules, and the first one drops the defaults.
+            ("save", [off]) if off.is_empty() => self.save = Some(vec![]),
+            ("save", pairs) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+                let rules = self.save.get_or_insert_with(Vec::new);
+                for pair in pairs.chunks(2) {
+                    match (pair[0].parse(), pair[1].parse()) {
+                        (Ok(seconds), Ok(changes)) => rules.push(SaveRule { seconds, changes }),
+                        _ => return Err("Invalid save parameters".to_string()),
+                    }
+                }
+            }
+            ("appendonly", [value]) => self.appendonly = parse_yes_no(value)?,
+            ("appendfilename", [name]) => {
+                if name.contains('/') {
+                    return Err("appendfilename can't be a path, just a filename".to_string());
+                }
+                self.appendfilename = name.to_owned();
+            }
+            ("appendfsync", [policy]) => {
+                self.appendfsync = match policy.to_ascii_lowercase().as_str() {
+                    "always" => AppendFsync::Always,
+                    "everysec" => AppendFsync::EverySec,
+                    "no" => AppendFsync::No,
+                    _ => return Err("argument must be 'always', 'everysec' or 'no'".to_string()),
+                };
+            }
+            ("aof-load-truncated", [value]) => self.aof_load_truncated = parse_yes_no(value)?,
+            ("import-rdb", [path]) => self.import_rdb = Some(path.to_owned()),
+            _ => return Err("Bad directive or wrong number of arguments".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Every setting with its current value, as CONFIG GET reports them.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("bind", self.bind.join(" ")),
+            ("port", self.port.to_string()),
+            ("unixsocket", self.unixsocket.clone().unwrap_or_default()),
+            (
+                "unixsocketperm",
+                format!("{:o}", self.unixsocketperm.unwrap_or(0)),
+            ),
+            ("tls-port", self.tls_port.unwrap_or(0).to_string()),
+            (
+                "tls-cert-file",
+                self.tls_cert_file.clone().unwrap_or_default(),
+            ),
+            (
+                "tls-key-file",
+                self.tls_key_file.clone().unwrap_or_default(),
+            ),
+            (
+                "tls-ca-cert-file",
+                self.tls_ca_cert_file.clone().unwrap_or_default(),
+            ),
+            (
+                "tls-auth-clients",
+                match self.tls_auth_clients {
+                    TlsAuthClients::No => "no",
+                    TlsAuthClients::Yes => "yes",
+                    TlsAuthClients::Optional => "optional",
+                }
+                .to_string(),
+            ),
+            ("requirepass", self.requirepass.clone().unwrap_or_default()),
+            ("hz", self.hz.to_string()),
+            ("databases", self.databases.to_string()),
+            ("maxmemory", self.maxmemory.to_string()),
+            (
+                "maxmemory-policy",
+                self.maxmemory_policy.as_str().to_string(),
+            ),
+            ("maxmemory-samples", self.maxmemory_samples.to_string()),
+            ("dir", self.dir.clone()),
+            ("dbfilename", self.dbfilename.clone()),
+            (
+                "save",
+                self.save_rules()
+                    .iter()
+                    .map(|r| format!("{} {}", r.seconds, r.changes))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            ("appendonly", yes_no(self.appendonly)),
+            ("appendfilename", self.appendfilename.clone()),
+            ("appendfsync", self.appendfsync.as_str().to_string()),
+            ("aof-load-truncated", yes_no(self.aof_load_truncated)),
+            ("import-rdb", self.import_rdb.clone().unwrap_or_default()),
+        ]
+    }
+
+    pub fn save_rules(&self) -> &[SaveRule] {
+        self.save.as_deref().unwrap_or(&DEFAULT_SAVE_RULES)
+    }
+
+    /// Where snapshots are written and loaded from.
+    pub fn rdb_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.appendfilename)
+    }
+}
+
+fn is_directive(name: &str) -> bool {
+    name == "user"
+        || ServerConfig::default()
+            .entries()
+            .iter()
+            .any(|(directive, _)| *directive == name)
+}
+
+fn parse_yes_no(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+/// A byte count with an optional unit, as redis.conf writes them: `1k` is
+/// 1000 bytes and `1kb` is 1024, and likewise for m(b) and g(b).
+fn parse_memory(value: &str) -> Result<u64, String> {
+    let lower = value.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("Invalid memory size".to_string()),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| "Invalid memory size".to_string())
+}
+
+fn split_config_line(line: &str) -> Result<Vec<String>, String> {
+    split_args(line.as_bytes())
+        .map(|args| {
+            args.iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .map_err(|_| "Unbalanced quotes in configuration line".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    fn args(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Loads with `file` written to a temporary config file passed as the
+    /// first argument, ahead of `flags`.
+    fn load_with_file(
+        file: &str,
+        vars: &[(&str, &str)],
+        flags: &[&str],
+    ) -> Result<ServerConfig, ConfigError> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "appledore-config-{}-{}.conf",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, file).unwrap();
+        let mut all = vec![path.to_string_lossy().into_owned()];
+        all.extend(args(flags));
+        let result = ServerConfig::load(all, env(vars));
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn defaults_without_any_source() {
+        let config = ServerConfig::load(vec![], vec![]).unwrap();
+        assert_eq!(config.port, 6379);
+        assert_eq!(config.bind, vec!["0.0.0.0".to_string()]);
+        assert_eq!(config.save_rules(), &DEFAULT_SAVE_RULES);
+    }
+
+    #[test]
+    fn env_overrides_the_file_and_flags_override_both() {
+        let file = "port 7000\nhz 20\ndatabases 4\n";
+        let config = load_with_file(file, &[], &[]).unwrap();
+        assert_eq!((config.port, config.hz, config.databases), (7000, 20, 4));
+
+        let vars = [("APPLEDORE_PORT", "7001"), ("APPLEDORE_HZ", "30")];
+        let config = load_with_file(file, &vars, &[]).unwrap();
+        assert_eq!((config.port, config.hz, config.databases), (7001, 30, 4));
+
+        let config = load_with_file(file, &vars, &["--port", "7002"]).unwrap();
+        assert_eq!((config.port, config.hz, config.databases), (7002, 30, 4));
+    }
+
+    #[test]
+    fn env_names_map_to_directives() {
+        let vars = [
+            ("APPLEDORE_MAXMEMORY_POLICY", "allkeys-lru"),
+            ("APPLEDORE_SAVE", "60 10 300 1"),
+            ("OTHER_PORT", "1"),
+        ];
+        let config = ServerConfig::load(vec![], env(&vars)).unwrap();
+        assert_eq!(config.maxmemory_policy, MaxmemoryPolicy::AllKeysLru);
+        assert_eq!(
+            config.save_rules(),
+            &[
+                SaveRule {
+                    seconds: 60,
+                    changes: 10
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 1
+                },
+            ]
+        );
+        assert_eq!(config.port, 6379);
+    }
+
+    #[test]
+    fn unknown_env_variables_are_ignored() {
+        let vars = [
+            ("APPLEDORE_TEST_REDIS", "127.0.0.1:6379"),
+            ("APPLEDORE_PORT", "7000"),
+        ];
+        let config = ServerConfig::load(vec![], env(&vars)).unwrap();
+        assert_eq!(config.port, 7000);
+    }
+
+    #[test]
+    fn flags_take_several_values() {
+        let flags = args(&[
+            "--bind",
+            "127.0.0.1",
+            "::1",
+            "--save",
+            "",
+            "--maxmemory",
+            "1mb",
+        ]);
+        let config = ServerConfig::load(flags, vec![]).unwrap();
+        assert_eq!(config.bind, args(&["127.0.0.1", "::1"]));
+        assert!(config.save_rules().is_empty());
+        assert_eq!(config.maxmemory, 1024 * 1024);
+    }
+
+    #[test]
+    fn bad_values_are_reported_with_their_source() {
+        let err = |result: Result<ServerConfig, ConfigError>| result.unwrap_err().to_string();
+        assert_eq!(
+            err(ServerConfig::load(args(&["--port", "http"]), vec![])),
+            "--port: Invalid port"
+        );
+        assert_eq!(
+            err(ServerConfig::load(args(&["--nosuch", "1"]), vec![])),
+            "--nosuch: Bad directive or wrong number of arguments"
+        );
+        assert_eq!(
+            err(ServerConfig::load(args(&["--port", "1", "stray"]), vec![])),
+            "--port: Bad directive or wrong number of arguments"
+        );
+        assert_eq!(
+            err(ServerConfig::load(
+                vec![],
+                env(&[("APPLEDORE_MAXMEMORY", "lots")])
+            )),
+            "environment variable APPLEDORE_MAXMEMORY: Invalid memory size"
+        );
+        assert_eq!(
+            err(ServerConfig::load(
+                vec![],
+                env(&[("APPLEDORE_DIR", "\"unbalanced")])
+            )),
+            "environment variable APPLEDORE_DIR: Unbalanced quotes in configuration line"
+        );
+        let message = err(load_with_file("port 1\nhz fast\n", &[], &[]));
+        assert!(
+            message.ends_with("line 2: >>> 'hz fast'\nInvalid hz value"),
+            "{}",
+            message
+        );
+        assert!(
+            ServerConfig::load(vec!["/nonexistent/appledore.conf".to_string()], vec![]).is_err()
+        );
+    }
+
+    #[test]
+    fn memory_sizes_take_units() {
+        assert_eq!(parse_memory("100"), Ok(100));
+        assert_eq!(parse_memory("1k"), Ok(1000));
+        assert_eq!(parse_memory("1KB"), Ok(1024));
+        assert_eq!(parse_memory("2gb"), Ok(2 * 1024 * 1024 * 1024));
+        assert!(parse_memory("1tb").is_err());
+        assert!(parse_memory("-1").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+/// The Jones polynomial Redis checksums RDB files with, bit reflected.
+const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the checksum `crc` over `bytes`; start from 0.
+pub fn crc64(mut crc: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        crc = TABLE[((crc ^ b as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_redis_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn continues_over_pieces() {
+        assert_eq!(crc64(crc64(0, b"1234"), b"56789"), crc64(0, b"123456789"));
+    }
+}
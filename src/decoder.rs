@@ -1,29 +1,477 @@
-use std::num::ParseIntError;
+use std::{fmt, num::ParseIntError};
 
-pub fn decode_get_pure_command(cmd: Vec<&str>) -> Vec<String> {
-    let mut pure_cmds: Vec<String> = vec![];
-    let mut i = 0;
-    for c in cmd {
-        if i % 2 == 0 && i != 0 {
-            pure_cmds.push(c.to_owned())
+use bytes::{Buf, Bytes, BytesMut};
+
+const MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+const MAX_LINE_LEN: usize = 64 * 1024;
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    InvalidMultibulkLength,
+    InvalidBulkLength,
+    TooBigCount,
+    TooBigInline,
+    UnbalancedQuotes,
+    Unexpected { expected: u8, found: u8 },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::InvalidMultibulkLength => {
+                write!(f, "Protocol error: invalid multibulk length")
+            }
+            ProtocolError::InvalidBulkLength => write!(f, "Protocol error: invalid bulk length"),
+            ProtocolError::TooBigCount => write!(f, "Protocol error: too big count string"),
+            ProtocolError::TooBigInline => write!(f, "Protocol error: too big inline request"),
+            ProtocolError::UnbalancedQuotes => {
+                write!(f, "Protocol error: unbalanced quotes in request")
+            }
+            ProtocolError::Unexpected { expected, found } => write!(
+                f,
+                "Protocol error: expected '{}', got '{}'",
+                *expected as char, *found as char
+            ),
         }
-        i += 1;
     }
-    pure_cmds
 }
 
-pub fn decode_array_indices(s: &str, e: &str, len: usize) -> Result<Vec<usize>, ParseIntError> {
-    let mut decoded: Vec<usize> = vec![];
-    let start: usize = s.parse()?;
-    let mut endi: i64 = e.parse()?;
-    if endi < 0 {
-        endi = len as i64 + endi + 1
+/// A command whose array header has been read but whose arguments have not
+/// all arrived yet.
+struct PartialCommand {
+    count: usize,
+    args: Vec<Bytes>,
+    bulk_len: Option<usize>,
+}
+
+/// Incremental decoder for RESP requests (`*<n>` arrays of `$<len>` bulk
+/// strings, or plain inline lines as typed into telnet/nc). Bytes are read off
+/// the socket straight into `buffer()` and complete commands are pulled out
+/// one at a time; anything incomplete stays buffered until more data arrives.
+/// Bulk arguments are split off the read buffer without copying.
+pub struct RequestDecoder {
+    buf: BytesMut,
+    partial: Option<PartialCommand>,
+}
+
+impl RequestDecoder {
+    pub fn new() -> Self {
+        RequestDecoder {
+            buf: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            partial: None,
+        }
+    }
+
+    /// The buffer incoming bytes should be appended to.
+    pub fn buffer(&mut self) -> &mut BytesMut {
+        self.buf.reserve(READ_BUFFER_SIZE);
+        &mut self.buf
+    }
+
+    /// Returns the next complete command, `Ok(None)` if more bytes are
+    /// needed, or a protocol error after which the stream cannot be trusted.
+    pub fn next_command(&mut self) -> Result<Option<Vec<Bytes>>, ProtocolError> {
+        loop {
+            let mut partial = match self.partial.take() {
+                Some(p) => p,
+                None => {
+                    if self.buf.is_empty() {
+                        return Ok(None);
+                    }
+                    if self.buf[0] != b'*' {
+                        match self.read_inline()? {
+                            Some(args) if args.is_empty() => continue,
+                            Some(args) => return Ok(Some(args)),
+                            None => return Ok(None),
+                        }
+                    }
+                    let count = match self.read_length(ProtocolError::InvalidMultibulkLength)? {
+                        Some(n) => n,
+                        None => return Ok(None),
+                    };
+                    if count > MAX_MULTIBULK_LEN {
+                        return Err(ProtocolError::InvalidMultibulkLength);
+                    }
+                    if count <= 0 {
+                        continue;
+                    }
+                    PartialCommand {
+                        count: count as usize,
+                        args: Vec::with_capacity((count as usize).min(1024)),
+                        bulk_len: None,
+                    }
+                }
+            };
+
+            while partial.args.len() < partial.count {
+                match partial.bulk_len {
+                    None => {
+                        if self.buf.is_empty() {
+                            self.partial = Some(partial);
+                            return Ok(None);
+                        }
+                        if self.buf[0] != b'$' {
+                            return Err(ProtocolError::Unexpected {
+                                expected: b'$',
+                                found: self.buf[0],
+                            });
+                        }
+                        match self.read_length(ProtocolError::InvalidBulkLength)? {
+                            Some(len) if (0..=MAX_BULK_LEN).contains(&len) => {
+                                partial.bulk_len = Some(len as usize);
+                            }
+                            Some(_) => return Err(ProtocolError::InvalidBulkLength),
+                            None => {
+                                self.partial = Some(partial);
+                                return Ok(None);
+                            }
+                        }
+                    }
+                    Some(len) => {
+                        if self.buf.len() < len + 2 {
+                            self.buf.reserve(len + 2 - self.buf.len());
+                            self.partial = Some(partial);
+                            return Ok(None);
+                        }
+                        if &self.buf[len..len + 2] != b"\r\n" {
+                            return Err(ProtocolError::InvalidBulkLength);
+                        }
+                        partial.args.push(self.buf.split_to(len).freeze());
+                        self.buf.advance(2);
+                        partial.bulk_len = None;
+                    }
+                }
+            }
+            return Ok(Some(partial.args));
+        }
+    }
+
+    /// Reads one inline command line, terminated by `\n` with an optional
+    /// preceding `\r`.
+    fn read_inline(&mut self) -> Result<Option<Vec<Bytes>>, ProtocolError> {
+        let line_end = match self.buf.iter().position(|&b| b == b'\n') {
+            Some(i) => i,
+            None => {
+                if self.buf.len() > MAX_LINE_LEN {
+                    return Err(ProtocolError::TooBigInline);
+                }
+                return Ok(None);
+            }
+        };
+        let mut line = &self.buf[..line_end];
+        if let Some(stripped) = line.strip_suffix(b"\r") {
+            line = stripped;
+        }
+        let args = split_args(line)?;
+        self.buf.advance(line_end + 1);
+        Ok(Some(args.into_iter().map(Bytes::from).collect()))
+    }
+
+    /// Reads a `*<n>\r\n` or `$<n>\r\n` header at the start of the buffer.
+    fn read_length(&mut self, invalid: ProtocolError) -> Result<Option<i64>, ProtocolError> {
+        let line_end = match self.buf[1..].windows(2).position(|w| w == b"\r\n") {
+            Some(i) => i + 1,
+            None => {
+                if self.buf.len() > MAX_LINE_LEN {
+                    return Err(ProtocolError::TooBigCount);
+                }
+                return Ok(None);
+            }
+        };
+        let len = std::str::from_utf8(&self.buf[1..line_end])
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or(invalid)?;
+        self.buf.advance(line_end + 2);
+        Ok(Some(len))
     }
-    let end: usize = endi.try_into().unwrap();
-    decoded.extend([start, end]);
-    Ok(decoded)
 }
 
-pub fn parse_u64(s: &str) -> Result<u64, ParseIntError> {
-    s.parse::<u64>()
+/// Splits an inline command the way redis-cli and `sdssplitargs` do:
+/// arguments are separated by whitespace, double quotes understand the usual
+/// backslash escapes plus `\xHH`, and single quotes only escape `\'`.
+pub fn split_args(line: &[u8]) -> Result<Vec<Vec<u8>>, ProtocolError> {
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(args);
+        }
+        let mut current = vec![];
+        let mut in_double = false;
+        let mut in_single = false;
+        loop {
+            if in_double {
+                match line.get(i) {
+                    None => return Err(ProtocolError::UnbalancedQuotes),
+                    Some(b'\\')
+                        if i + 3 < line.len()
+                            && line[i + 1] == b'x'
+                            && line[i + 2].is_ascii_hexdigit()
+                            && line[i + 3].is_ascii_hexdigit() =>
+                    {
+                        let hex = std::str::from_utf8(&line[i + 2..i + 4]).unwrap();
+                        current.push(u8::from_str_radix(hex, 16).unwrap());
+                        i += 3;
+                    }
+                    Some(b'\\') if i + 1 < line.len() => {
+                        i += 1;
+                        current.push(match line[i] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            c => c,
+                        });
+                    }
+                    Some(b'"') => {
+                        if line.get(i + 1).is_some_and(|c| !c.is_ascii_whitespace()) {
+                            return Err(ProtocolError::UnbalancedQuotes);
+                        }
+                        in_double = false;
+                    }
+                    Some(&c) => current.push(c),
+                }
+            } else if in_single {
+                match line.get(i) {
+                    None => return Err(ProtocolError::UnbalancedQuotes),
+                    Some(b'\\') if line.get(i + 1) == Some(&b'\'') => {
+                        i += 1;
+                        current.push(b'\'');
+                    }
+                    Some(b'\'') => {
+                        if line.get(i + 1).is_some_and(|c| !c.is_ascii_whitespace()) {
+                            return Err(ProtocolError::UnbalancedQuotes);
+                        }
+                        in_single = false;
+                    }
+                    Some(&c) => current.push(c),
+                }
+            } else {
+                match line.get(i) {
+                    None => break,
+                    Some(c) if c.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(&c) => current.push(c),
+                }
+            }
+            i += 1;
+        }
+        args.push(current);
+    }
+}
+
+/// Arguments are raw bytes; anything that is not UTF-8 can never be a number,
+/// so it is mapped to the empty string which fails to parse just the same.
+fn as_str(s: &[u8]) -> &str {
+    std::str::from_utf8(s).unwrap_or("")
+}
+
+pub fn parse_u64(s: &[u8]) -> Result<u64, ParseIntError> {
+    as_str(s).parse::<u64>()
+}
+
+pub fn parse_i64(s: &[u8]) -> Result<i64, ParseIntError> {
+    as_str(s).parse::<i64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `input` to a fresh decoder and pulls out every command.
+    fn decode(input: &[u8]) -> Result<Vec<Vec<Bytes>>, ProtocolError> {
+        let mut decoder = RequestDecoder::new();
+        decoder.buffer().extend_from_slice(input);
+        let mut commands = vec![];
+        while let Some(args) = decoder.next_command()? {
+            commands.push(args);
+        }
+        Ok(commands)
+    }
+
+    fn command(args: &[&[u8]]) -> Vec<Bytes> {
+        args.iter().map(|a| Bytes::copy_from_slice(a)).collect()
+    }
+
+    #[test]
+    fn decodes_a_command_split_at_every_offset() {
+        let input = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$7\r\nva\r\nlue\r\n*1\r\n$4\r\nPING\r\n";
+        let expected = vec![
+            command(&[b"SET", b"key", b"va\r\nlue"]),
+            command(&[b"PING"]),
+        ];
+        for split in 0..=input.len() {
+            let mut decoder = RequestDecoder::new();
+            let mut commands = vec![];
+            for part in [&input[..split], &input[split..]] {
+                decoder.buffer().extend_from_slice(part);
+                while let Some(args) = decoder.next_command().unwrap() {
+                    commands.push(args);
+                }
+            }
+            assert_eq!(commands, expected, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn keeps_crlf_inside_bulk_payloads() {
+        assert_eq!(
+            decode(b"*2\r\n$4\r\nECHO\r\n$6\r\n\r\n\r\n\r\n\r\n").unwrap(),
+            vec![command(&[b"ECHO", b"\r\n\r\n\r\n"])]
+        );
+    }
+
+    #[test]
+    fn decodes_arrays_of_many_arguments() {
+        let args: Vec<Vec<u8>> = (0..12).map(|i| format!("arg{}", i).into_bytes()).collect();
+        let mut input = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in &args {
+            input.extend(format!("${}\r\n", arg.len()).into_bytes());
+            input.extend(arg);
+            input.extend(b"\r\n");
+        }
+        let decoded = decode(&input).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0], args);
+    }
+
+    #[test]
+    fn skips_empty_and_negative_length_arrays() {
+        assert_eq!(
+            decode(b"*0\r\n*-1\r\n*1\r\n$4\r\nPING\r\n").unwrap(),
+            vec![command(&[b"PING"])]
+        );
+    }
+
+    #[test]
+    fn rejects_an_oversized_array_length() {
+        assert_eq!(
+            decode(b"*1048577\r\n"),
+            Err(ProtocolError::InvalidMultibulkLength)
+        );
+        assert_eq!(
+            decode(b"*x\r\n"),
+            Err(ProtocolError::InvalidMultibulkLength)
+        );
+    }
+
+    #[test]
+    fn rejects_negative_oversized_and_malformed_bulk_lengths() {
+        for header in [&b"$-1"[..], b"$536870913", b"$abc"] {
+            let input = [&b"*1\r\n"[..], header, b"\r\n"].concat();
+            assert_eq!(
+                decode(&input),
+                Err(ProtocolError::InvalidBulkLength),
+                "{}",
+                String::from_utf8_lossy(header)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_bulk_string_without_its_crlf() {
+        assert_eq!(
+            decode(b"*1\r\n$3\r\nabcXY"),
+            Err(ProtocolError::InvalidBulkLength)
+        );
+    }
+
+    #[test]
+    fn rejects_an_argument_that_is_not_a_bulk_string() {
+        assert_eq!(
+            decode(b"*1\r\n:1\r\n"),
+            Err(ProtocolError::Unexpected {
+                expected: b'$',
+                found: b':'
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_a_header_that_never_ends() {
+        let mut input = b"*".to_vec();
+        input.resize(MAX_LINE_LEN + 2, b'1');
+        assert_eq!(decode(&input), Err(ProtocolError::TooBigCount));
+    }
+
+    #[test]
+    fn decodes_inline_commands_and_skips_blank_lines() {
+        assert_eq!(
+            decode(b"\r\nPING\r\nSET k v\n").unwrap(),
+            vec![command(&[b"PING"]), command(&[b"SET", b"k", b"v"])]
+        );
+    }
+
+    #[test]
+    fn parses_only_whole_integers() {
+        assert_eq!(parse_i64(b"-42"), Ok(-42));
+        assert_eq!(parse_u64(b"18446744073709551615"), Ok(u64::MAX));
+        for bad in [&b""[..], b"1.5", b" 1", b"9223372036854775808", b"\xff"] {
+            assert!(parse_i64(bad).is_err(), "{:?}", bad);
+        }
+        assert!(parse_u64(b"-1").is_err());
+    }
+
+    fn split(line: &str) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        split_args(line.as_bytes())
+    }
+
+    fn args(items: &[&[u8]]) -> Vec<Vec<u8>> {
+        items.iter().map(|i| i.to_vec()).collect()
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(split("  SET\tk   v "), Ok(args(&[b"SET", b"k", b"v"])));
+        assert_eq!(split("   "), Ok(vec![]));
+    }
+
+    #[test]
+    fn quotes_keep_whitespace_together() {
+        assert_eq!(
+            split(r#"SET "a b" 'x' ''"#),
+            Ok(args(&[b"SET", b"a b", b"x", b""]))
+        );
+    }
+
+    #[test]
+    fn double_quotes_understand_escapes() {
+        assert_eq!(
+            split(r#""\x41\x7a" "a\nb\tc\r" "\"q\" \\""#),
+            Ok(args(&[b"Az", b"a\nb\tc\r", b"\"q\" \\"]))
+        );
+        // Not two hex digits, so just an escaped x.
+        assert_eq!(split(r#""\xZZ""#), Ok(args(&[b"xZZ"])));
+    }
+
+    #[test]
+    fn single_quotes_only_escape_single_quotes() {
+        assert_eq!(split(r#"'it\'s' 'a\nb'"#), Ok(args(&[b"it's", b"a\\nb"])));
+    }
+
+    #[test]
+    fn rejects_unbalanced_quotes() {
+        for line in [r#"SET "abc"#, "SET 'abc", r#"SET "abc\""#] {
+            assert_eq!(
+                split(line),
+                Err(ProtocolError::UnbalancedQuotes),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_closing_quote_followed_by_more_text() {
+        assert_eq!(split(r#""a"b"#), Err(ProtocolError::UnbalancedQuotes));
+        assert_eq!(split("'a'b"), Err(ProtocolError::UnbalancedQuotes));
+    }
 }
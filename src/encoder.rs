@@ -1,60 +1,410 @@
-use std::io::Write;
+use std::io::{self, IoSlice, Write};
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::error::CommandError;
+
+/// Bulk payloads at least this large are queued by reference instead of being
+/// copied into the reply buffer.
+const ZERO_COPY_THRESHOLD: usize = 1024;
+const MAX_IOVECS: usize = 64;
+
+/// Replies accumulated for one batch of commands. Small frames are copied
+/// into a scratch buffer, large stored values are kept as shared `Bytes` and
+/// written to the socket with vectored I/O.
+pub struct ReplyBuffer {
+    frames: Vec<Bytes>,
+    scratch: BytesMut,
+}
+
+impl ReplyBuffer {
+    pub fn new() -> Self {
+        ReplyBuffer {
+            frames: Vec::new(),
+            scratch: BytesMut::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty() && self.scratch.is_empty()
+    }
+
+    /// Appends an already encoded frame.
+    pub fn extend(&mut self, encoded: Vec<u8>) {
+        self.scratch.extend_from_slice(&encoded);
+    }
+
+    pub fn push_bulk_string(&mut self, data: Bytes) {
+        self.scratch.extend_from_slice(b"$");
+        self.scratch
+            .extend_from_slice(data.len().to_string().as_bytes());
+        self.scratch.extend_from_slice(b"\r\n");
+        if data.len() >= ZERO_COPY_THRESHOLD {
+            self.frames.push(self.scratch.split().freeze());
+            self.frames.push(data);
+        } else {
+            self.scratch.extend_from_slice(&data);
+        }
+        self.scratch.extend_from_slice(b"\r\n");
+    }
+
+    pub fn push_error(&mut self, e: CommandError) {
+        self.extend(e.encode());
+    }
+
+    pub fn push_arrays(&mut self, arr: Vec<Bytes>) {
+        self.extend(encode_resp_aggregate_header(b'*', arr.len()));
+        for item in arr {
+            self.push_bulk_string(item);
+        }
+    }
+
+    pub async fn write_to<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> io::Result<()> {
+        if !self.scratch.is_empty() {
+            self.frames.push(self.scratch.split().freeze());
+        }
+        let mut pending = &mut self.frames[..];
+        while !pending.is_empty() {
+            let slices: Vec<IoSlice> = pending
+                .iter()
+                .take(MAX_IOVECS)
+                .map(|frame| IoSlice::new(frame))
+                .collect();
+            let mut written = writer.write_vectored(&slices).await?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            while written > 0 {
+                if written >= pending[0].len() {
+                    written -= pending[0].len();
+                    pending = &mut pending[1..];
+                } else {
+                    pending[0].advance(written);
+                    written = 0;
+                }
+            }
+        }
+        self.frames.clear();
+        Ok(())
+    }
+}
 
 pub fn encode_resp_simple_string(s: &str) -> Vec<u8> {
     let mut encoded: Vec<u8> = Vec::with_capacity(s.len() + 3);
     encoded.push(b'+');
     encoded.extend(s.as_bytes());
-    encoded.extend(&[b'\r', b'\n']);
+    encoded.extend(b"\r\n");
     encoded
 }
 
-pub fn encode_resp_error_string(s: &str) -> Vec<u8> {    
+/// Error messages often quote client input; newlines in it are sent as
+/// spaces, as Redis does, so they can't end the reply early.
+pub fn encode_resp_error_string(s: &str) -> Vec<u8> {
     let mut encoded = Vec::with_capacity(s.len() + 3);
-    encoded.push(b'-');    
-    encoded.extend_from_slice(s.as_bytes());
+    encoded.push(b'-');
+    encoded.extend(s.bytes().map(|b| match b {
+        b'\r' | b'\n' => b' ',
+        b => b,
+    }));
     encoded.extend_from_slice(b"\r\n");
-    encoded    
+    encoded
 }
 
 pub fn encode_resp_integer(value: &str) -> Vec<u8> {
     let mut encoded = Vec::with_capacity(value.len() + 3);
-    encoded.push(b':');    
+    encoded.push(b':');
     encoded.extend_from_slice(value.as_bytes());
     encoded.extend_from_slice(b"\r\n");
-    encoded    
+    encoded
 }
 
-pub fn encode_resp_bulk_string(data: String) -> Vec<u8> {
-    let len = data.len();    
+pub fn encode_resp_bulk_string(data: &[u8]) -> Vec<u8> {
+    let len = data.len();
     let mut encoded = Vec::with_capacity(len + len.to_string().len() + 5);
-    encoded.push(b'$');    
+    encoded.push(b'$');
     write!(&mut encoded, "{}", len).unwrap();
     encoded.extend_from_slice(b"\r\n");
-    encoded.extend_from_slice(data.as_bytes());
+    encoded.extend_from_slice(data);
     encoded.extend_from_slice(b"\r\n");
-    encoded    
+    encoded
 }
 
 pub fn empty_bulk_string() -> Vec<u8> {
     let mut encoded = Vec::with_capacity(5);
-    encoded.push(b'$');    
+    encoded.push(b'$');
     encoded.extend_from_slice(b"-1");
     encoded.extend_from_slice(b"\r\n");
-    encoded    
+    encoded
+}
+
+pub fn encode_resp_empty_array() -> Vec<u8> {
+    let encoded: Vec<u8> = vec![b'*', b'0', b'\r', b'\n'];
+    encoded
+}
+
+/// Wire protocol negotiated by a connection through HELLO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
 }
 
-pub fn encode_resp_arrays(arr: Vec<String>) -> Vec<u8> {
-    let mut encoded = Vec::with_capacity(arr.len() * 5);
-    encoded.push(b'*');    
-    write!(&mut encoded, "{}", arr.len()).unwrap();
+fn encode_resp_aggregate_header(prefix: u8, len: usize) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(8);
+    encoded.push(prefix);
+    write!(&mut encoded, "{}", len).unwrap();
     encoded.extend_from_slice(b"\r\n");
-    for item in arr {
-        encoded.extend(encode_resp_bulk_string(item));
+    encoded
+}
+
+pub fn encode_resp_array_header(len: usize) -> Vec<u8> {
+    encode_resp_aggregate_header(b'*', len)
+}
+
+/// Header for a map of `len` entries; RESP2 clients get a flat array of
+/// alternating keys and values instead.
+pub fn encode_resp_map_header(len: usize, protocol: Protocol) -> Vec<u8> {
+    match protocol {
+        Protocol::Resp3 => encode_resp_aggregate_header(b'%', len),
+        Protocol::Resp2 => encode_resp_aggregate_header(b'*', len * 2),
+    }
+}
+
+/// Encodes already-encoded key/value frames as a map.
+pub fn encode_resp_map(entries: Vec<(Vec<u8>, Vec<u8>)>, protocol: Protocol) -> Vec<u8> {
+    let mut encoded = encode_resp_map_header(entries.len(), protocol);
+    for (k, v) in entries {
+        encoded.extend(k);
+        encoded.extend(v);
     }
     encoded
 }
 
-pub fn encode_resp_empty_array() -> Vec<u8> {
-    let encoded: Vec<u8> = vec![b'*', b'0', b'\r', b'\n'];    
-    encoded    
+/// Encodes already-encoded frames as an array.
+pub fn encode_resp_array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut encoded = encode_resp_aggregate_header(b'*', items.len());
+    for item in items {
+        encoded.extend(item);
+    }
+    encoded
+}
+
+/// Encodes already-encoded frames as a set; RESP2 clients get an array.
+pub fn encode_resp_set(items: Vec<Vec<u8>>, protocol: Protocol) -> Vec<u8> {
+    let prefix = match protocol {
+        Protocol::Resp3 => b'~',
+        Protocol::Resp2 => b'*',
+    };
+    let mut encoded = encode_resp_aggregate_header(prefix, items.len());
+    for item in items {
+        encoded.extend(item);
+    }
+    encoded
+}
+
+/// Encodes an out-of-band push frame; RESP2 clients get an array.
+pub fn encode_resp_push(items: Vec<Vec<u8>>, protocol: Protocol) -> Vec<u8> {
+    let prefix = match protocol {
+        Protocol::Resp3 => b'>',
+        Protocol::Resp2 => b'*',
+    };
+    let mut encoded = encode_resp_aggregate_header(prefix, items.len());
+    for item in items {
+        encoded.extend(item);
+    }
+    encoded
+}
+
+pub fn encode_resp_null(protocol: Protocol) -> Vec<u8> {
+    match protocol {
+        Protocol::Resp3 => b"_\r\n".to_vec(),
+        Protocol::Resp2 => empty_bulk_string(),
+    }
+}
+
+/// The null a command that replies with an array sends for a missing key.
+pub fn encode_resp_null_array(protocol: Protocol) -> Vec<u8> {
+    match protocol {
+        Protocol::Resp3 => b"_\r\n".to_vec(),
+        Protocol::Resp2 => b"*-1\r\n".to_vec(),
+    }
+}
+
+/// RESP2 clients get `1` or `0`.
+pub fn encode_resp_boolean(value: bool, protocol: Protocol) -> Vec<u8> {
+    match (protocol, value) {
+        (Protocol::Resp3, true) => b"#t\r\n".to_vec(),
+        (Protocol::Resp3, false) => b"#f\r\n".to_vec(),
+        (Protocol::Resp2, true) => encode_resp_integer("1"),
+        (Protocol::Resp2, false) => encode_resp_integer("0"),
+    }
+}
+
+/// RESP2 clients get the number as a bulk string.
+pub fn encode_resp_double(value: f64, protocol: Protocol) -> Vec<u8> {
+    let formatted = if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
+    };
+    match protocol {
+        Protocol::Resp3 => {
+            let mut encoded = Vec::with_capacity(formatted.len() + 3);
+            encoded.push(b',');
+            encoded.extend_from_slice(formatted.as_bytes());
+            encoded.extend_from_slice(b"\r\n");
+            encoded
+        }
+        Protocol::Resp2 => encode_resp_bulk_string(formatted.as_bytes()),
+    }
+}
+
+/// `digits` is an integer of any size in decimal; RESP2 clients get it as a
+/// bulk string.
+pub fn encode_resp_big_number(digits: &str, protocol: Protocol) -> Vec<u8> {
+    match protocol {
+        Protocol::Resp3 => {
+            let mut encoded = Vec::with_capacity(digits.len() + 3);
+            encoded.push(b'(');
+            encoded.extend_from_slice(digits.as_bytes());
+            encoded.extend_from_slice(b"\r\n");
+            encoded
+        }
+        Protocol::Resp2 => encode_resp_bulk_string(digits.as_bytes()),
+    }
+}
+
+/// `format` is the three letter content hint, e.g. `txt` or `mkd`.
+pub fn encode_resp_verbatim_string(format: &str, data: &[u8], protocol: Protocol) -> Vec<u8> {
+    match protocol {
+        Protocol::Resp3 => {
+            let len = data.len() + 4;
+            let mut encoded = Vec::with_capacity(len + 8);
+            encoded.push(b'=');
+            write!(&mut encoded, "{}", len).unwrap();
+            encoded.extend_from_slice(b"\r\n");
+            encoded.extend_from_slice(format.as_bytes());
+            encoded.push(b':');
+            encoded.extend_from_slice(data);
+            encoded.extend_from_slice(b"\r\n");
+            encoded
+        }
+        Protocol::Resp2 => encode_resp_bulk_string(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Protocol::{Resp2, Resp3};
+
+    fn bulks(items: &[&str]) -> Vec<Vec<u8>> {
+        items
+            .iter()
+            .map(|s| encode_resp_bulk_string(s.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn errors_stay_on_one_line() {
+        assert_eq!(encode_resp_error_string("ERR bad"), b"-ERR bad\r\n");
+        assert_eq!(
+            encode_resp_error_string("ERR 'a\r\n+OK' \n"),
+            b"-ERR 'a  +OK'  \r\n"
+        );
+    }
+
+    #[test]
+    fn maps() {
+        let entries = || vec![(encode_resp_bulk_string(b"k"), encode_resp_integer("1"))];
+        assert_eq!(
+            encode_resp_map(entries(), Resp3),
+            b"%1\r\n$1\r\nk\r\n:1\r\n"
+        );
+        assert_eq!(
+            encode_resp_map(entries(), Resp2),
+            b"*2\r\n$1\r\nk\r\n:1\r\n"
+        );
+        assert_eq!(encode_resp_map(vec![], Resp3), b"%0\r\n");
+    }
+
+    #[test]
+    fn sets() {
+        assert_eq!(
+            encode_resp_set(bulks(&["a", "b"]), Resp3),
+            b"~2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            encode_resp_set(bulks(&["a", "b"]), Resp2),
+            b"*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(encode_resp_set(vec![], Resp3), b"~0\r\n");
+    }
+
+    #[test]
+    fn push_frames() {
+        assert_eq!(
+            encode_resp_push(bulks(&["message", "x"]), Resp3),
+            b">2\r\n$7\r\nmessage\r\n$1\r\nx\r\n"
+        );
+        assert_eq!(
+            encode_resp_push(bulks(&["message", "x"]), Resp2),
+            b"*2\r\n$7\r\nmessage\r\n$1\r\nx\r\n"
+        );
+    }
+
+    #[test]
+    fn nulls() {
+        assert_eq!(encode_resp_null(Resp3), b"_\r\n");
+        assert_eq!(encode_resp_null(Resp2), b"$-1\r\n");
+        assert_eq!(encode_resp_null_array(Resp3), b"_\r\n");
+        assert_eq!(encode_resp_null_array(Resp2), b"*-1\r\n");
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(encode_resp_boolean(true, Resp3), b"#t\r\n");
+        assert_eq!(encode_resp_boolean(false, Resp3), b"#f\r\n");
+        assert_eq!(encode_resp_boolean(true, Resp2), b":1\r\n");
+        assert_eq!(encode_resp_boolean(false, Resp2), b":0\r\n");
+    }
+
+    #[test]
+    fn doubles() {
+        assert_eq!(encode_resp_double(2.5, Resp3), b",2.5\r\n");
+        assert_eq!(encode_resp_double(-2.0, Resp3), b",-2\r\n");
+        assert_eq!(encode_resp_double(f64::INFINITY, Resp3), b",inf\r\n");
+        assert_eq!(encode_resp_double(f64::NEG_INFINITY, Resp3), b",-inf\r\n");
+        assert_eq!(encode_resp_double(f64::NAN, Resp3), b",nan\r\n");
+        assert_eq!(encode_resp_double(2.5, Resp2), b"$3\r\n2.5\r\n");
+    }
+
+    #[test]
+    fn big_numbers() {
+        let digits = "1234567999999999999999999999999999999";
+        assert_eq!(
+            encode_resp_big_number(digits, Resp3),
+            format!("({}\r\n", digits).into_bytes()
+        );
+        assert_eq!(
+            encode_resp_big_number(digits, Resp2),
+            format!("$37\r\n{}\r\n", digits).into_bytes()
+        );
+    }
+
+    #[test]
+    fn verbatim_strings() {
+        assert_eq!(
+            encode_resp_verbatim_string("txt", b"Some string", Resp3),
+            b"=15\r\ntxt:Some string\r\n"
+        );
+        assert_eq!(
+            encode_resp_verbatim_string("txt", b"Some string", Resp2),
+            b"$11\r\nSome string\r\n"
+        );
+    }
 }
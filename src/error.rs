@@ -0,0 +1,112 @@
+use std::fmt;
+
+use crate::{encoder::encode_resp_error_string, storage::StorageError};
+
+pub type CommandResult = Result<(), CommandError>;
+
+/// An error reply. The Display output is the exact text Redis sends after
+/// the leading `-`.
+#[derive(Debug)]
+pub enum CommandError {
+    UnknownCommand {
+        name: String,
+        args: Vec<String>,
+    },
+    /// A subcommand of `command`, e.g. `CONFIG FOO`.
+    UnknownSubcommand {
+        command: &'static str,
+        subcommand: String,
+    },
+    /// Wrong argument count for a command or a `command|subcommand` pair.
+    WrongArity(String),
+    WrongType,
+    NotInteger,
+    OutOfRange,
+    NoSuchKey,
+    Syntax,
+    NoAuth,
+    /// HELLO without AUTH on a connection that has not authenticated.
+    HelloNoAuth,
+    WrongPass,
+    NoPerm {
+        user: String,
+        command: String,
+    },
+    NoPermKey,
+    NoProto,
+    Oom,
+    /// Any other `ERR` reply; holds the text after the prefix.
+    Other(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand { name, args } => {
+                write!(
+                    f,
+                    "ERR unknown command '{}', with args beginning with: ",
+                    name
+                )?;
+                for arg in args {
+                    write!(f, "'{}' ", arg)?;
+                }
+                Ok(())
+            }
+            CommandError::UnknownSubcommand {
+                command,
+                subcommand,
+            } => write!(
+                f,
+                "ERR unknown subcommand '{}'. Try {} HELP.",
+                subcommand,
+                command.to_ascii_uppercase()
+            ),
+            CommandError::WrongArity(name) => {
+                write!(f, "ERR wrong number of arguments for '{}' command", name)
+            }
+            CommandError::WrongType => f.write_str(
+                "WRONGTYPE Operation against a key holding the wrong kind of value",
+            ),
+            CommandError::NotInteger => f.write_str("ERR value is not an integer or out of range"),
+            CommandError::OutOfRange => f.write_str("ERR index out of range"),
+            CommandError::NoSuchKey => f.write_str("ERR no such key"),
+            CommandError::Syntax => f.write_str("ERR syntax error"),
+            CommandError::NoAuth => f.write_str("NOAUTH Authentication required."),
+            CommandError::HelloNoAuth => f.write_str(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time",
+            ),
+            CommandError::WrongPass => {
+                f.write_str("WRONGPASS invalid username-password pair or user is disabled.")
+            }
+            CommandError::NoPerm { user, command } => write!(
+                f,
+                "NOPERM User {} has no permissions to run the '{}' command",
+                user, command
+            ),
+            CommandError::NoPermKey => f.write_str("NOPERM No permissions to access a key"),
+            CommandError::NoProto => f.write_str("NOPROTO unsupported protocol version"),
+            CommandError::Oom => {
+                f.write_str("OOM command not allowed when used memory > 'maxmemory'.")
+            }
+            CommandError::Other(msg) => write!(f, "ERR {}", msg),
+        }
+    }
+}
+
+impl CommandError {
+    pub fn encode(&self) -> Vec<u8> {
+        encode_resp_error_string(&self.to_string())
+    }
+}
+
+impl From<StorageError> for CommandError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::NotFound => CommandError::NoSuchKey,
+            StorageError::BadType => CommandError::WrongType,
+            StorageError::BadCommand => CommandError::Syntax,
+            StorageError::OutOfRange => CommandError::OutOfRange,
+        }
+    }
+}
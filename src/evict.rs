@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+
+use crate::{config::MaxmemoryPolicy, server::Server};
+
+/// Shards sampled for every key evicted; the best of their candidates is
+/// the one removed. Shards are numbered across databases.
+const SHARDS_PER_EVICTION: usize = 4;
+
+/// The shard the next eviction starts sampling from, kept across calls so
+/// that evictions spread over every shard instead of draining the first.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+/// Evicts keys until memory is back under `maxmemory`, following the
+/// configured policy. Returns false when that was not possible, in which
+/// case commands that would use more memory are refused. Evicted keys are
+/// logged to the append only file as deletions.
+pub fn perform_evictions(server: &Server) -> bool {
+    let config = &server.config;
+    let limit = config.maxmemory as usize;
+    if limit == 0 || server.memory.used() <= limit {
+        return true;
+    }
+    if config.maxmemory_policy == MaxmemoryPolicy::NoEviction {
+        return false;
+    }
+    let dbs = server.dbs();
+    let per_db = dbs[0].shard_count();
+    let shards = dbs.len() * per_db;
+    let shard = |index: usize| dbs[index / per_db].shard_at(index % per_db);
+    let mut start = NEXT_SHARD.load(Ordering::Relaxed) % shards;
+    while server.memory.used() > limit {
+        let mut best = None;
+        let mut sampled = 0;
+        for i in 0..shards {
+            let index = (start + i) % shards;
+            let candidate =
+                shard(index).eviction_candidate(config.maxmemory_policy, config.maxmemory_samples);
+            if let Some((key, score)) = candidate {
+                match best {
+                    Some((_, _, b)) if b >= score => {}
+                    _ => best = Some((index, key, score)),
+                }
+                sampled += 1;
+                if sampled == SHARDS_PER_EVICTION {
+                    break;
+                }
+            }
+        }
+        match best {
+            Some((index, key, _)) => {
+                // Held until the DEL is logged, so no write to the shard is
+                // logged between the eviction and its DEL.
+                let _order = server.aof.as_ref().map(|aof| aof.lock_shards(vec![index]));
+                let evicted = shard(index).evict(&key);
+                if let (true, Some(aof)) = (evicted, &server.aof) {
+                    let del = [Bytes::from_static(b"del"), key];
+                    if let Err(e) = aof.lock().log(index / per_db, &del) {
+                        eprintln!("Error writing to the AOF file: {}", e);
+                    }
+                }
+                start = index + 1;
+                NEXT_SHARD.store(start, Ordering::Relaxed);
+            }
+            None => return false,
+        }
+    }
+    true
+}
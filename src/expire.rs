@@ -0,0 +1,58 @@
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{keyspace::Keyspace, rng::Rng, server::Server};
+
+/// Keys with an expiry checked per shard in one round, as in Redis.
+const KEYS_PER_LOOP: usize = 20;
+/// A shard is sampled again while more than this percentage of the
+/// checked keys had expired.
+const ACCEPTABLE_STALE_PERCENT: usize = 10;
+/// Share of every tick the cycle may spend removing keys.
+const TIME_LIMIT_PERCENT: u32 = 25;
+
+/// Starts the thread that removes expired keys nobody reads, `hz` times a
+/// second. Keys read after their expiry are also removed lazily by the
+/// storage itself.
+pub fn spawn_active_expire(server: Arc<Server>) {
+    thread::Builder::new()
+        .name("active-expire".to_string())
+        .spawn(move || {
+            let period = Duration::from_secs(1) / server.config.hz;
+            let budget = period * TIME_LIMIT_PERCENT / 100;
+            let mut rng = Rng::new();
+            let mut next_shard = 0;
+            loop {
+                thread::sleep(period);
+                next_shard = expire_cycle(server.dbs(), next_shard, budget, &mut rng);
+            }
+        })
+        .expect("failed to spawn the active expire thread");
+}
+
+/// Samples every shard of every database once, starting at `start`,
+/// locking each only for a batch of `KEYS_PER_LOOP` keys at a time. Shards
+/// are numbered across databases. Returns the shard to start from next
+/// time, which is not `start` when the time budget ran out.
+fn expire_cycle(dbs: &[Keyspace], start: usize, budget: Duration, rng: &mut Rng) -> usize {
+    let deadline = Instant::now() + budget;
+    let per_db = dbs[0].shard_count();
+    let shards = dbs.len() * per_db;
+    for i in 0..shards {
+        let index = (start + i) % shards;
+        let shard = || dbs[index / per_db].shard_at(index % per_db);
+        loop {
+            let (checked, expired) = shard().expire_sample(KEYS_PER_LOOP, |n| rng.below(n));
+            if Instant::now() >= deadline {
+                return (index + 1) % shards;
+            }
+            if checked == 0 || expired * 100 <= checked * ACCEPTABLE_STALE_PERCENT {
+                break;
+            }
+        }
+    }
+    start
+}
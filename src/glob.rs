@@ -0,0 +1,82 @@
+/// Redis style glob matching as used by KEYS, CONFIG GET and friends:
+/// `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next byte.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+    let (mut p, mut s) = (0, 0);
+    // Where to resume if the current attempt after the last `*` fails.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < string.len() {
+        let mut matched = false;
+        let mut next_p = p + 1;
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    while next_p < pattern.len() && pattern[next_p] == b'*' {
+                        next_p += 1;
+                    }
+                    if next_p == pattern.len() {
+                        return true;
+                    }
+                    backtrack = Some((next_p, s));
+                    p = next_p;
+                    continue;
+                }
+                b'?' => matched = true,
+                b'[' => {
+                    let mut i = p + 1;
+                    let negate = i < pattern.len() && pattern[i] == b'^';
+                    if negate {
+                        i += 1;
+                    }
+                    let mut found = false;
+                    while i < pattern.len() && pattern[i] != b']' {
+                        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                            i += 1;
+                            found |= eq(pattern[i], string[s]);
+                        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' {
+                            let (mut lo, mut hi) = (pattern[i], pattern[i + 2]);
+                            if lo > hi {
+                                std::mem::swap(&mut lo, &mut hi);
+                            }
+                            let c = string[s];
+                            found |= if nocase {
+                                (lo..=hi).contains(&c.to_ascii_lowercase())
+                                    || (lo..=hi).contains(&c.to_ascii_uppercase())
+                            } else {
+                                (lo..=hi).contains(&c)
+                            };
+                            i += 2;
+                        } else {
+                            found |= eq(pattern[i], string[s]);
+                        }
+                        i += 1;
+                    }
+                    next_p = (i + 1).min(pattern.len());
+                    matched = found != negate;
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    next_p = p + 2;
+                    matched = eq(pattern[p + 1], string[s]);
+                }
+                c => matched = eq(c, string[s]),
+            }
+        }
+        if matched {
+            p = next_p;
+            s += 1;
+        } else if let Some((star_p, star_s)) = backtrack {
+            p = star_p;
+            s = star_s + 1;
+            backtrack = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
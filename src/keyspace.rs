@@ -0,0 +1,216 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use bytes::Bytes;
+
+use crate::{rng::Rng, storage::Storage};
+
+/// Number of shards is two to this; a power of two so a shard is picked
+/// with a mask.
+const SHARD_BITS: u32 = 6;
+const SHARDS: usize = 1 << SHARD_BITS;
+
+/// The hash a key is placed and ordered by. The hasher is unkeyed so it is
+/// the same on every run.
+pub fn key_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Memory used by every database together, readable without locking any
+/// shard.
+#[derive(Default)]
+pub struct MemoryUsage {
+    used: AtomicUsize,
+    /// The highest `used` has been.
+    peak: AtomicUsize,
+}
+
+/// One database, split into independently locked shards so that clients
+/// working on different keys do not contend on a single lock.
+pub struct Keyspace {
+    shards: Vec<Mutex<Storage>>,
+    memory: Arc<MemoryUsage>,
+}
+
+/// A locked shard. Whatever its memory grew or shrank by while locked is
+/// added to the total on unlock.
+pub struct ShardGuard<'a> {
+    storage: MutexGuard<'a, Storage>,
+    used_before: usize,
+    keyspace: &'a Keyspace,
+}
+
+/// Several shards locked together for a multi-key command.
+pub struct LockedShards<'a> {
+    /// Sorted by shard index.
+    guards: Vec<(usize, ShardGuard<'a>)>,
+}
+
+impl Deref for ShardGuard<'_> {
+    type Target = Storage;
+
+    fn deref(&self) -> &Storage {
+        &self.storage
+    }
+}
+
+impl DerefMut for ShardGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+impl Drop for ShardGuard<'_> {
+    fn drop(&mut self) {
+        let used = self.storage.used_memory();
+        let memory = &self.keyspace.memory;
+        if used > self.used_before {
+            let grown = used - self.used_before;
+            let total = memory.used.fetch_add(grown, Ordering::Relaxed) + grown;
+            memory.peak.fetch_max(total, Ordering::Relaxed);
+        } else {
+            memory
+                .used
+                .fetch_sub(self.used_before - used, Ordering::Relaxed);
+        }
+    }
+}
+
+impl MemoryUsage {
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+impl Keyspace {
+    pub fn new(memory: Arc<MemoryUsage>) -> Self {
+        Keyspace {
+            shards: (0..SHARDS).map(|_| Mutex::new(Storage::new())).collect(),
+            memory,
+        }
+    }
+
+    fn lock(&self, index: usize) -> ShardGuard<'_> {
+        let storage = self.shards[index]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        ShardGuard {
+            used_before: storage.used_memory(),
+            storage,
+            keyspace: self,
+        }
+    }
+
+    /// The shard `key` lives in, picked by the low bits of its hash.
+    pub fn index(&self, key: &[u8]) -> usize {
+        key_hash(key) as usize & (SHARDS - 1)
+    }
+
+    pub fn shard(&self, key: &[u8]) -> ShardGuard<'_> {
+        self.lock(self.index(key))
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_at(&self, index: usize) -> ShardGuard<'_> {
+        self.lock(index)
+    }
+
+    pub fn key_count(&self) -> usize {
+        (0..SHARDS).map(|i| self.lock(i).key_counts().0).sum()
+    }
+
+    pub fn clear(&self) {
+        for i in 0..SHARDS {
+            self.lock(i).clear();
+        }
+    }
+
+    /// Locks every shard, in index order.
+    pub fn lock_all(&self) -> Vec<ShardGuard<'_>> {
+        (0..SHARDS).map(|i| self.lock(i)).collect()
+    }
+
+    /// Exchanges the contents of two databases. Every shard of `self` is
+    /// locked before any of `other`, so `self` must be the database with
+    /// the lower index, the order all cross-database commands lock in.
+    pub fn swap(&self, other: &Keyspace) {
+        let mut mine = self.lock_all();
+        let mut theirs = other.lock_all();
+        for (a, b) in mine.iter_mut().zip(theirs.iter_mut()) {
+            std::mem::swap(&mut **a, &mut **b);
+        }
+    }
+
+    /// A random live key, from a random shard that has one.
+    pub fn random_key(&self) -> Option<Bytes> {
+        let start = Rng::new().below(SHARDS);
+        (0..SHARDS).find_map(|i| self.lock((start + i) % SHARDS).random_key())
+    }
+
+    /// Visits about `count` keys from `cursor` on and returns the cursor to
+    /// continue from, 0 once every shard was visited.
+    ///
+    /// A cursor is a key hash rotated so the shard bits come first: shards
+    /// are visited in order, and each one in hash order, so every key that
+    /// exists for the whole scan is returned at least once.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(Bytes, &'static str)>) {
+        let mut found = vec![];
+        let mut hash = cursor.rotate_left(SHARD_BITS);
+        let mut visited = 0;
+        loop {
+            let shard = hash as usize & (SHARDS - 1);
+            let before = found.len();
+            let next = self.lock(shard).scan(hash, count - visited, &mut found);
+            match next {
+                Some(hash) => return (hash.rotate_right(SHARD_BITS), found),
+                None if shard == SHARDS - 1 => return (0, found),
+                // The smallest hash of the next shard.
+                None => hash = shard as u64 + 1,
+            }
+            visited += found.len() - before;
+            if visited >= count {
+                return (hash.rotate_right(SHARD_BITS), found);
+            }
+        }
+    }
+
+    /// Locks every shard holding one of `keys`, always in index order so
+    /// that two multi-key commands cannot deadlock.
+    pub fn lock_keys<K: AsRef<[u8]>>(&self, keys: &[K]) -> LockedShards<'_> {
+        let mut indexes: Vec<usize> = keys.iter().map(|k| self.index(k.as_ref())).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        LockedShards {
+            guards: indexes.into_iter().map(|i| (i, self.lock(i))).collect(),
+        }
+    }
+}
+
+impl LockedShards<'_> {
+    /// The locked shard holding `key`, which must be one of the keys the
+    /// shards were locked for.
+    pub fn shard(&mut self, keyspace: &Keyspace, key: &[u8]) -> &mut Storage {
+        let index = keyspace.index(key);
+        let pos = self
+            .guards
+            .binary_search_by_key(&index, |(i, _)| *i)
+            .expect("key was not locked");
+        &mut self.guards[pos].1
+    }
+}
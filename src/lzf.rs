@@ -0,0 +1,78 @@
+use std::io;
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt LZF compressed string")
+}
+
+/// Expands an LZF compressed string, as Redis writes long strings to RDB
+/// files, back into the `len` bytes it was compressed from.
+pub fn decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    // The length comes from the file, so only trust it as far as LZF
+    // could expand the input.
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(16)));
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 1 << 5 {
+            // A run of ctrl + 1 literal bytes.
+            let literal = input.get(i..i + ctrl + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(literal);
+            i += ctrl + 1;
+        } else {
+            // A copy of earlier output: 3 bits of length, more in an extra
+            // byte when they are all set, and 13 bits of distance back.
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(corrupt)? as usize;
+                i += 1;
+            }
+            let back = ((ctrl & 0x1f) << 8) + *input.get(i).ok_or_else(corrupt)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(back).ok_or_else(corrupt)?;
+            // The copy may overlap the bytes it appends, so go one by one.
+            for k in start..start + run + 2 {
+                out.push(out[k]);
+            }
+        }
+        if out.len() > len {
+            return Err(corrupt());
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "abcabcabcabc!": three literal bytes, nine copied from three back,
+    /// then one more literal.
+    const COMPRESSED: &[u8] = &[0x02, b'a', b'b', b'c', 0xe0, 0x00, 0x02, 0x00, b'!'];
+
+    #[test]
+    fn expands_literals_and_copies() {
+        assert_eq!(decompress(COMPRESSED, 13).unwrap(), b"abcabcabcabc!");
+    }
+
+    #[test]
+    fn rejects_the_wrong_length() {
+        assert!(decompress(COMPRESSED, 12).is_err());
+        assert!(decompress(COMPRESSED, 14).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for len in 0..COMPRESSED.len() {
+            assert!(decompress(&COMPRESSED[..len], 13).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_a_copy_from_before_the_start() {
+        assert!(decompress(&[0x20, 0x05], 3).is_err());
+    }
+}
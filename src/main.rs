@@ -1,36 +1,348 @@
-use std::{
-    io,
-    sync::{Arc, Mutex},
-};
+use std::{io, path::Path, process, sync::Arc, time::Instant};
 
+use bytes::Bytes;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    net::TcpListener,
 };
+use tokio_rustls::TlsAcceptor;
 
+mod acl;
+mod aof;
+mod client;
 mod commands;
+mod config;
+mod crc64;
 mod decoder;
 mod encoder;
+mod error;
+mod evict;
+mod expire;
+mod glob;
+mod keyspace;
+mod lzf;
+mod packed;
+mod rdb;
+mod rng;
+mod server;
 mod storage;
+mod tls;
 
-use commands::*;
+use acl::Acl;
+use client::Client;
+use commands::{
+    registry::{self, Flag},
+    unknown_command,
+};
+use config::{AppendFsync, ServerConfig};
 use decoder::*;
 use encoder::*;
-use storage::Storage;
+use error::{CommandError, CommandResult};
+use server::Server;
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("0.0.0.0:6379").await.unwrap();
-    let storage_engine = Arc::new(Mutex::new(Storage::new()));
-    println!("Listening on ::6379");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "import-rdb") {
+        process::exit(import_rdb(&args[1..]));
+    }
+    let mut server = configure(args);
+    load_data(&mut server);
+    let server = Arc::new(server);
+    if let Some(aof) = &server.aof {
+        if server.config.appendfsync == AppendFsync::EverySec {
+            aof::spawn_fsync(Arc::clone(aof));
+        }
+    }
+    expire::spawn_active_expire(Arc::clone(&server));
+    rdb::spawn_autosave(Arc::clone(&server));
+    let config = &server.config;
+
+    let mut listeners = vec![];
+    for addr in &config.bind {
+        match TcpListener::bind((addr.as_str(), config.port)).await {
+            Ok(listener) => {
+                if let Ok(local) = listener.local_addr() {
+                    println!("Listening on {}", local);
+                }
+                listeners.push(listener);
+            }
+            Err(e) => {
+                eprintln!("Could not bind {}:{}: {}", addr, config.port, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut servers = vec![];
+    for listener in listeners {
+        servers.push(tokio::spawn(serve(listener, Arc::clone(&server))));
+    }
+    if let Some(tls_port) = config.tls_port {
+        let acceptor = match tls::load_acceptor(config) {
+            Ok(a) => a,
+            Err(e) => {
+                eprintln!("Failed to configure TLS: {}", e);
+                process::exit(1);
+            }
+        };
+        for addr in &config.bind {
+            let listener = match TcpListener::bind((addr.as_str(), tls_port)).await {
+                Ok(l) => l,
+                Err(e) => {
+                    eprintln!("Could not bind {}:{}: {}", addr, tls_port, e);
+                    process::exit(1);
+                }
+            };
+            if let Ok(local) = listener.local_addr() {
+                println!("Listening on {} (TLS)", local);
+            }
+            servers.push(tokio::spawn(serve_tls(
+                listener,
+                acceptor.clone(),
+                Arc::clone(&server),
+            )));
+        }
+    }
+    #[cfg(unix)]
+    if let Some(path) = &config.unixsocket {
+        let listener = match bind_unix(path, config.unixsocketperm) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Could not create unix socket {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+        println!("Listening on {}", path);
+        servers.push(tokio::spawn(serve_unix(listener, Arc::clone(&server))));
+    }
+    for server in servers {
+        let _ = server.await;
+    }
+}
+
+/// Reads the configuration from `args` and the environment and sets up
+/// the server it describes. Exits on a bad setting.
+fn configure(args: Vec<String>) -> Server {
+    let config = match ServerConfig::load(args, std::env::vars()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("*** FATAL CONFIG ERROR ***\n{}", e);
+            process::exit(1);
+        }
+    };
+    let mut acl = Acl::new(config.requirepass.as_deref());
+    for rules in &config.users {
+        let args: Vec<Bytes> = rules[1..]
+            .iter()
+            .map(|r| Bytes::from(r.clone().into_bytes()))
+            .collect();
+        if let Err(e) = acl.set_user(&rules[0], &args) {
+            eprintln!("*** FATAL CONFIG ERROR ***\nuser {}: {:?}", rules[0], e);
+            process::exit(1);
+        }
+    }
+    Server::new(config, acl)
+}
+
+/// `appledore import-rdb <file> [config...]`: imports a Redis RDB file into
+/// the configured snapshot without starting the server. Returns the exit
+/// code.
+fn import_rdb(args: &[String]) -> i32 {
+    let (source, config) = match args.split_first() {
+        Some((source, config)) if !source.starts_with("--") => (source, config),
+        _ => {
+            eprintln!("Usage: appledore import-rdb <file.rdb> [config file] [--name value...]");
+            return 1;
+        }
+    };
+    let server = configure(config.to_vec());
+    let aof_path = server.config.aof_path();
+    if server.config.appendonly && aof_path.exists() {
+        eprintln!(
+            "{} is loaded at startup instead of the snapshot; import with --import-rdb instead",
+            aof_path.display()
+        );
+        return 1;
+    }
+    // Keep the keys the snapshot already has.
+    if let Err(e) = rdb::load(&server) {
+        load_failed(&server.config.rdb_path(), e);
+    }
+    import_into(&server, source);
+    match rdb::save(&server).expect("nothing else saves offline") {
+        Ok(()) => {
+            println!("Saved to {}", server.config.rdb_path().display());
+            0
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed saving {}: {}",
+                server.config.rdb_path().display(),
+                e
+            );
+            1
+        }
+    }
+}
+
+/// Imports the Redis RDB file at `path` and prints what it held. Exits
+/// when the file cannot be read.
+fn import_into(server: &Server, path: &str) {
+    let importing = Instant::now();
+    let report = match rdb::import(server, Path::new(path)) {
+        Ok(report) => report,
+        Err(e) => load_failed(Path::new(path), e),
+    };
+    println!(
+        "Imported {} keys from {} in {:.3} seconds",
+        report.keys,
+        path,
+        importing.elapsed().as_secs_f64()
+    );
+    if report.expired > 0 {
+        println!("Left out {} keys that had already expired", report.expired);
+    }
+    if !report.skipped.is_empty() {
+        println!("Not converted:");
+        for (name, count) in &report.skipped {
+            println!("  {}: {}", name, count);
+        }
+    }
+}
+
+/// Loads the dataset from the append only file when it is on, from the
+/// RDB snapshot otherwise, imports a Redis RDB file over it when asked to,
+/// then opens the append only file for writing.
+/// Exits when the data on disk cannot be read.
+fn load_data(server: &mut Server) {
+    let loading = Instant::now();
+    if server.config.appendonly {
+        match aof::load(server) {
+            Ok(Some(commands)) => {
+                println!(
+                    "DB loaded from append only file: {} commands in {:.3} seconds",
+                    commands,
+                    loading.elapsed().as_secs_f64()
+                );
+            }
+            Ok(None) => {}
+            Err(e) => load_failed(&server.config.aof_path(), e),
+        }
+    }
+    // Without an append only file yet, the snapshot seeds the new one.
+    if !server.config.appendonly || !server.config.aof_path().exists() {
+        match rdb::load(server) {
+            Ok(Some(keys)) => println!(
+                "DB loaded from disk: {} keys in {:.3} seconds",
+                keys,
+                loading.elapsed().as_secs_f64()
+            ),
+            Ok(None) => {}
+            Err(e) => load_failed(&server.config.rdb_path(), e),
+        }
+    }
+    if let Some(path) = server.config.import_rdb.clone() {
+        import_into(server, &path);
+    }
+    if server.config.appendonly {
+        let existed = server.config.aof_path().exists();
+        match aof::Aof::open(server) {
+            Ok(aof) => {
+                let aof = Arc::new(aof);
+                // An existing log lacks the imported keys until rewritten.
+                if existed && server.config.import_rdb.is_some() {
+                    aof::bgrewrite(server, &aof);
+                }
+                server.aof = Some(aof);
+            }
+            Err(e) => {
+                eprintln!(
+                    "Can't open the append-only file {}: {}",
+                    server.config.aof_path().display(),
+                    e
+                );
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn load_failed(path: &Path, e: io::Error) -> ! {
+    eprintln!("Failed loading {}: {}", path.display(), e);
+    process::exit(1);
+}
+
+async fn serve_tls(listener: TcpListener, acceptor: TlsAcceptor, server: Arc<Server>) {
+    loop {
+        let incoming = listener.accept().await;
+        let cloned_server = Arc::clone(&server);
+        let acceptor = acceptor.clone();
+        match incoming {
+            Ok((stream, addr)) => {
+                println!("New Connection, {} (TLS)", addr);
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(mut stream) => {
+                            log_disconnect(handle_connection(&mut stream, cloned_server).await)
+                        }
+                        Err(e) => println!("TLS handshake with {} failed: {}", addr, e),
+                    }
+                });
+            }
+            Err(e) => {
+                println!("error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, perm: Option<u32>) -> io::Result<UnixListener> {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    // A socket file left behind by a previous run would make bind fail.
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = perm {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+#[cfg(unix)]
+async fn serve_unix(listener: UnixListener, server: Arc<Server>) {
+    loop {
+        let incoming = listener.accept().await;
+        let cloned_server = Arc::clone(&server);
+        match incoming {
+            Ok((mut stream, _)) => {
+                println!("New Connection, unix socket");
+                tokio::spawn(async move {
+                    log_disconnect(handle_connection(&mut stream, cloned_server).await);
+                });
+            }
+            Err(e) => {
+                println!("error: {}", e);
+            }
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, server: Arc<Server>) {
     loop {
         let incoming = listener.accept().await;
-        let cloned_storage = Arc::clone(&storage_engine);
+        let cloned_server = Arc::clone(&server);
         match incoming {
             Ok((mut stream, addr)) => {
                 println!("New Connection, {}", addr);
                 tokio::spawn(async move {
-                    handle_connection(&mut stream, cloned_storage).await;
+                    log_disconnect(handle_connection(&mut stream, cloned_server).await);
                 });
             }
             Err(e) => {
@@ -40,64 +352,105 @@ async fn main() {
     }
 }
 
-async fn read_data(stream: &mut TcpStream) -> Result<Vec<u8>, io::Error> {
-    const MAX_BUFFER_SIZE: usize = 512;
-    let mut buf: Vec<u8> = Vec::new();
-    let mut buffer = vec![0u8; MAX_BUFFER_SIZE];
+fn log_disconnect(result: io::Result<()>) {
+    match result {
+        Ok(()) => println!("Client closed the connection"),
+        Err(e) => println!("Connection error: {}", e),
+    }
+}
+
+/// Serves one client until it disconnects. An I/O error only ends this
+/// connection; it is returned so the accept loop can log it.
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    server: Arc<Server>,
+) -> io::Result<()> {
+    let mut decoder = RequestDecoder::new();
+    let mut reply = ReplyBuffer::new();
+    let mut client = Client::new(!server.acl().default_needs_auth());
     loop {
-        let n = stream.read(&mut buffer).await?;
-        if n == 0 {
-            break;
+        if stream.read_buf(decoder.buffer()).await? == 0 {
+            return Ok(());
+        }
+        // Run every complete command in the buffer; a trailing partial
+        // command stays in the decoder until the next read.
+        let mut closing = false;
+        loop {
+            match decoder.next_command() {
+                Ok(Some(args)) => {
+                    if let Err(e) = execute(&mut reply, args, &mut client, &server) {
+                        reply.push_error(e);
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    reply.push_error(CommandError::Other(e.to_string()));
+                    closing = true;
+                    break;
+                }
+            }
+        }
+        if !reply.is_empty() {
+            reply.write_to(stream).await?;
         }
-        buf.extend_from_slice(&buffer);
-        if n < MAX_BUFFER_SIZE {
-            break;
+        if closing {
+            return Ok(());
         }
     }
-    Ok(buf)
 }
 
-async fn handle_connection(stream: &mut TcpStream, client_store: Arc<Mutex<Storage>>) {
-    let mut buf: Vec<u8>;
-    loop {
-        match read_data(stream).await {
-            Ok(b) => buf = b,
-            Err(_) => break,
-        }
-        if buf.is_empty() {
-            println!("Client closed the connection");
-            break;
-        }
-        let str_cmd = String::from_utf8_lossy(&buf);
-        let cmd: Vec<&str> = str_cmd.split("\r\n").collect::<Vec<&str>>();
-        if let Ok(mut cmd_len) = cmd[0][1..2].parse::<usize>() {
-            cmd_len *= 2;
-            let pure_cmd = decode_get_pure_command(cmd[0..cmd_len + 1].to_vec());
-            match pure_cmd[0].to_ascii_lowercase().trim() {
-                "ping" => ping(stream).await,
-                "echo" => echo(stream, pure_cmd).await,
-                "set" => set(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "get" => get(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "del" => del(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "llen" => array::llen(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "lpop" => array::lpop(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "lrem" => array::lrem(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "lset" => array::lset(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "lpush" | "rpush" => array::push(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "lrange" => array::lrange(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "lindex" => array::lindex(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "hset" => hash::hash_set(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "qadd" => queue::queue_add(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "qread" => queue::dequeue(stream, pure_cmd, Arc::clone(&client_store)).await,
-                "qlen" => queue::qlen(stream, pure_cmd, Arc::clone(&client_store)).await,
-                _ => undefined(stream).await,
-            };
-            buf.clear();
-        } else {
-            stream
-                .write(&encode_resp_error_string("Error in parsing cmd length"))
-                .await
-                .unwrap();
+/// Looks the command up in the registry, validates its arity and the
+/// client's permissions, evicts keys if memory is over `maxmemory`, then
+/// runs its handler. Writes that changed the data count towards the save
+/// rules and are logged to the append only file.
+fn execute(
+    reply: &mut ReplyBuffer,
+    pure_cmd: Vec<Bytes>,
+    client: &mut Client,
+    server: &Server,
+) -> CommandResult {
+    let name = pure_cmd[0].to_ascii_lowercase();
+    let command = match registry::lookup(&name) {
+        Some(c) => c,
+        None => return Err(unknown_command(&pure_cmd)),
+    };
+    if !command.check_arity(pure_cmd.len()) {
+        return Err(CommandError::WrongArity(command.name.to_string()));
+    }
+    if !command.has_flag(Flag::NoAuth) {
+        if !client.authenticated {
+            return Err(CommandError::NoAuth);
+        }
+        match server.acl().get(&client.user) {
+            Some(user) if user.enabled() => user.check_command(command, &pure_cmd)?,
+            // The user was disabled or deleted since the connection logged
+            // in; it has to authenticate again.
+            _ => {
+                client.authenticated = false;
+                return Err(CommandError::NoAuth);
+            }
+        }
+    }
+    if !evict::perform_evictions(server) && command.has_flag(Flag::DenyOom) {
+        return Err(CommandError::Oom);
+    }
+    if !command.has_flag(Flag::Write) {
+        return (command.handler)(reply, pure_cmd, client, server);
+    }
+    // Held until the command is logged, so writes to the same shards are
+    // logged in the order they reached storage.
+    let _order = server
+        .aof
+        .as_ref()
+        .map(|aof| aof.lock_written(server, command, &pure_cmd, client.db));
+    let args = server.aof.as_ref().map(|_| pure_cmd.clone());
+    client.dirty = 0;
+    let result = (command.handler)(reply, pure_cmd, client, server);
+    if client.dirty > 0 {
+        server.saves.changed(client.dirty);
+        if let (Some(aof), Some(args)) = (&server.aof, args) {
+            aof.lock().log_write(server, client.db, &args);
         }
     }
+    result
 }
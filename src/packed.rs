@@ -0,0 +1,330 @@
+use std::io;
+
+use bytes::Bytes;
+
+const END: u8 = 0xff;
+
+/// A ziplist entry's previous entry length takes 4 more bytes after this.
+const ZIP_BIG_PREVLEN: u8 = 0xfe;
+
+fn corrupt(kind: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt {} value", kind),
+    )
+}
+
+/// Reads through a blob, failing on reads past its end.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    kind: &'static str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8], kind: &'static str) -> Self {
+        Cursor { buf, pos: 0, kind }
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos.saturating_add(n))
+            .ok_or_else(|| corrupt(self.kind))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        buf.copy_from_slice(self.take(N)?);
+        Ok(buf)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    /// A 24 bit little endian signed integer.
+    fn i24(&mut self) -> io::Result<i64> {
+        let [a, b, c] = self.array()?;
+        Ok((i32::from_le_bytes([0, a, b, c]) >> 8) as i64)
+    }
+
+    fn string(&mut self, len: usize) -> io::Result<Bytes> {
+        Ok(Bytes::copy_from_slice(self.take(len)?))
+    }
+}
+
+fn integer(n: i64) -> Bytes {
+    Bytes::from(n.to_string())
+}
+
+/// The entries of a ziplist, the blob Redis 6 and earlier pack small lists
+/// and hashes into. Integers are written out in decimal.
+pub fn ziplist(blob: &[u8]) -> io::Result<Vec<Bytes>> {
+    let mut input = Cursor::new(blob, "ziplist");
+    // Total bytes and offset of the last entry, then the entry count.
+    input.take(8)?;
+    let count = u16::from_le_bytes(input.array()?);
+    let mut items = Vec::with_capacity(count as usize);
+    loop {
+        match input.byte()? {
+            END => break,
+            ZIP_BIG_PREVLEN => {
+                input.take(4)?;
+            }
+            _ => {}
+        }
+        let encoding = input.byte()?;
+        let item = match (encoding >> 6, encoding) {
+            (0, _) => input.string((encoding & 0x3f) as usize)?,
+            (1, _) => {
+                let len = ((encoding & 0x3f) as usize) << 8 | input.byte()? as usize;
+                input.string(len)?
+            }
+            (2, 0x80) => {
+                let len = u32::from_be_bytes(input.array()?) as usize;
+                input.string(len)?
+            }
+            (_, 0xc0) => integer(i16::from_le_bytes(input.array()?) as i64),
+            (_, 0xd0) => integer(i32::from_le_bytes(input.array()?) as i64),
+            (_, 0xe0) => integer(i64::from_le_bytes(input.array()?)),
+            (_, 0xf0) => integer(input.i24()?),
+            (_, 0xfe) => integer(i8::from_le_bytes(input.array()?) as i64),
+            // 0 to 12 kept in the encoding byte itself, off by one.
+            (_, 0xf1..=0xfd) => integer((encoding & 0x0f) as i64 - 1),
+            _ => return Err(corrupt("ziplist")),
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Bytes a listpack entry of `len` bytes stores its length back in, so
+/// the list can be walked from the end.
+fn listpack_backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// The entries of a listpack, which replaced the ziplist in Redis 7.
+/// Integers are written out in decimal.
+pub fn listpack(blob: &[u8]) -> io::Result<Vec<Bytes>> {
+    let mut input = Cursor::new(blob, "listpack");
+    // Total bytes, then the entry count.
+    input.take(4)?;
+    let count = u16::from_le_bytes(input.array()?);
+    let mut items = Vec::with_capacity(count as usize);
+    loop {
+        let encoding = input.byte()?;
+        if encoding == END {
+            break;
+        }
+        let start = input.pos - 1;
+        let item = if encoding & 0x80 == 0 {
+            integer((encoding & 0x7f) as i64)
+        } else if encoding & 0xc0 == 0x80 {
+            input.string((encoding & 0x3f) as usize)?
+        } else if encoding & 0xe0 == 0xc0 {
+            let n = ((encoding & 0x1f) as i64) << 8 | input.byte()? as i64;
+            // 13 bit two's complement.
+            integer(if n >= 1 << 12 { n - (1 << 13) } else { n })
+        } else if encoding & 0xf0 == 0xe0 {
+            let len = ((encoding & 0x0f) as usize) << 8 | input.byte()? as usize;
+            input.string(len)?
+        } else {
+            match encoding {
+                0xf0 => {
+                    let len = u32::from_le_bytes(input.array()?) as usize;
+                    input.string(len)?
+                }
+                0xf1 => integer(i16::from_le_bytes(input.array()?) as i64),
+                0xf2 => integer(input.i24()?),
+                0xf3 => integer(i32::from_le_bytes(input.array()?) as i64),
+                0xf4 => integer(i64::from_le_bytes(input.array()?)),
+                _ => return Err(corrupt("listpack")),
+            }
+        };
+        input.take(listpack_backlen_size(input.pos - start))?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ziplist of "2" and "5" that Redis's ziplist.c documents its
+    /// layout with.
+    const ZIPLIST: &[u8] = &[
+        0x0f, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0xf3, 0x02, 0xf6, 0xff,
+    ];
+
+    fn strings(items: &[&[u8]]) -> Vec<Bytes> {
+        items.iter().map(|i| Bytes::copy_from_slice(i)).collect()
+    }
+
+    /// A ziplist of `entries`, each given as its encoding and data; the
+    /// previous entry lengths and the header are filled in.
+    fn ziplist_of(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = vec![];
+        let mut prev = 0;
+        for entry in entries {
+            let start = body.len();
+            if prev < ZIP_BIG_PREVLEN as usize {
+                body.push(prev as u8);
+            } else {
+                body.push(ZIP_BIG_PREVLEN);
+                body.extend((prev as u32).to_le_bytes());
+            }
+            body.extend(entry);
+            prev = body.len() - start;
+        }
+        let mut blob = ((11 + body.len()) as u32).to_le_bytes().to_vec();
+        blob.extend([0; 4]);
+        blob.extend((entries.len() as u16).to_le_bytes());
+        blob.extend(body);
+        blob.push(END);
+        blob
+    }
+
+    /// A listpack of `entries`, each given as its encoding and data; the
+    /// back lengths and the header are filled in.
+    fn listpack_of(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = vec![];
+        for entry in entries {
+            body.extend(entry);
+            let mut len = entry.len();
+            loop {
+                body.push((len & 0x7f) as u8);
+                len >>= 7;
+                if len == 0 {
+                    break;
+                }
+            }
+        }
+        let mut blob = ((7 + body.len()) as u32).to_le_bytes().to_vec();
+        blob.extend((entries.len() as u16).to_le_bytes());
+        blob.extend(body);
+        blob.push(END);
+        blob
+    }
+
+    fn with(encoding: &[u8], data: &[u8]) -> Vec<u8> {
+        [encoding, data].concat()
+    }
+
+    #[test]
+    fn reads_the_documented_ziplist() {
+        assert_eq!(ziplist(ZIPLIST).unwrap(), strings(&[b"2", b"5"]));
+    }
+
+    fn every_ziplist_encoding() -> (Vec<u8>, Vec<Bytes>) {
+        let blob = ziplist_of(&[
+            with(&[0x05], b"hello"),
+            with(&[0x40, 0x40], &[b'x'; 64]),
+            // 300 bytes, so the next entry needs a 5 byte previous length.
+            with(&[0x41, 0x2c], &[b'y'; 300]),
+            with(&[0x80, 0x00, 0x00, 0x00, 0x03], b"abc"),
+            vec![0xc0, 0xfe, 0xff],
+            vec![0xd0, 0x70, 0x11, 0x01, 0x00],
+            vec![0xe0, 0x00, 0x0e, 0xfa, 0xd5, 0xfe, 0xff, 0xff, 0xff],
+            vec![0xf0, 0x00, 0x00, 0x80],
+            vec![0xfe, 0x9c],
+            vec![0xf1],
+            vec![0xfd],
+        ]);
+        let items = strings(&[
+            b"hello",
+            &[b'x'; 64],
+            &[b'y'; 300],
+            b"abc",
+            b"-2",
+            b"70000",
+            b"-5000000000",
+            b"-8388608",
+            b"-100",
+            b"0",
+            b"12",
+        ]);
+        (blob, items)
+    }
+
+    #[test]
+    fn reads_every_ziplist_encoding() {
+        let (blob, items) = every_ziplist_encoding();
+        assert_eq!(ziplist(&blob).unwrap(), items);
+    }
+
+    #[test]
+    fn rejects_truncated_ziplists() {
+        let (blob, _) = every_ziplist_encoding();
+        for len in 0..blob.len() {
+            assert!(ziplist(&blob[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_ziplist_encodings() {
+        assert!(ziplist(&ziplist_of(&[vec![0x81]])).is_err());
+        assert!(ziplist(&ziplist_of(&[vec![0xc5]])).is_err());
+    }
+
+    fn every_listpack_encoding() -> (Vec<u8>, Vec<Bytes>) {
+        let blob = listpack_of(&[
+            vec![0x07],
+            with(&[0x85], b"hello"),
+            vec![0xdf, 0xff],
+            vec![0xcf, 0xff],
+            // Long enough for a 2 byte back length.
+            with(&[0xe1, 0x2c], &[b'y'; 300]),
+            with(&[0xf0, 0x03, 0x00, 0x00, 0x00], b"abc"),
+            // And a 3 byte one.
+            with(&[0xf0, 0x68, 0x42, 0x00, 0x00], &[b'z'; 17000]),
+            vec![0xf1, 0x18, 0xfc],
+            vec![0xf2, 0x00, 0x00, 0x80],
+            vec![0xf3, 0x70, 0x11, 0x01, 0x00],
+            vec![0xf4, 0x00, 0x0e, 0xfa, 0xd5, 0xfe, 0xff, 0xff, 0xff],
+        ]);
+        let items = strings(&[
+            b"7",
+            b"hello",
+            b"-1",
+            b"4095",
+            &[b'y'; 300],
+            b"abc",
+            &[b'z'; 17000],
+            b"-1000",
+            b"-8388608",
+            b"70000",
+            b"-5000000000",
+        ]);
+        (blob, items)
+    }
+
+    #[test]
+    fn reads_every_listpack_encoding() {
+        let (blob, items) = every_listpack_encoding();
+        assert_eq!(listpack(&blob).unwrap(), items);
+    }
+
+    #[test]
+    fn rejects_truncated_listpacks() {
+        let (blob, _) = every_listpack_encoding();
+        for len in 0..blob.len() {
+            assert!(listpack(&blob[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_listpack_encodings() {
+        assert!(listpack(&listpack_of(&[vec![0xf5]])).is_err());
+    }
+}
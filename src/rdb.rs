@@ -0,0 +1,1080 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    crc64::crc64,
+    lzf, packed,
+    server::Server,
+    storage::{now_ms, Queue, Unit, Value},
+};
+
+/// The format version written. Redis 5 and later load it.
+const RDB_VERSION: u32 = 9;
+/// The newest format version read, the one Redis 7.2 writes.
+const MAX_LOAD_VERSION: u32 = 11;
+
+const OPCODE_FUNCTION2: u8 = 0xf5;
+const OPCODE_MODULE_AUX: u8 = 0xf7;
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// How a Redis 7 quicklist node holds its elements: a single large one
+/// as is, or several packed into a listpack.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+/// Length prefixes keep their format in the top two bits of the first
+/// byte; the last format marks a specially encoded string instead.
+const LEN_14BIT: u8 = 1;
+const LEN_32BIT: u8 = 0x80;
+const LEN_64BIT: u8 = 0x81;
+const LEN_ENCODED: u8 = 3;
+
+const ENC_INT8: u64 = 0;
+const ENC_INT16: u64 = 1;
+const ENC_INT32: u64 = 2;
+const ENC_LZF: u64 = 3;
+
+/// Old style sorted set scores are a length byte and the score in text,
+/// with these lengths standing for scores that have no text.
+const ZSET_SCORE_NAN: u8 = 253;
+
+/// The characters a module type name is written with, 6 bits each.
+const MODULE_CHARSET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+/// Queues have no Redis type, so they are saved as values of a module
+/// type named "appledore", which Redis refuses to load instead of
+/// misreading.
+const QUEUE_MODULE_ID: u64 = module_id(b"appledore", 0);
+/// Elements allocated up front for a collection, whatever length the file
+/// claims.
+const MAX_PREALLOC: usize = 1024;
+/// How long the save rules wait before retrying a failed background save.
+const RETRY_DELAY_SECS: u64 = 5;
+
+const fn module_id(name: &[u8; 9], encoding_version: u64) -> u64 {
+    let mut id = 0;
+    let mut i = 0;
+    while i < name.len() {
+        let mut c = 0;
+        while MODULE_CHARSET[c] != name[i] {
+            c += 1;
+        }
+        id = (id << 6) | c as u64;
+        i += 1;
+    }
+    (id << 10) | encoding_version
+}
+
+/// The name a module type id was made from.
+fn module_name(id: u64) -> String {
+    (0..9)
+        .rev()
+        .map(|i| MODULE_CHARSET[((id >> (10 + 6 * i)) & 0x3f) as usize] as char)
+        .collect()
+}
+
+fn now_secs() -> u64 {
+    now_ms() / 1000
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Bookkeeping shared by SAVE, BGSAVE and the save rules.
+pub struct SaveState {
+    /// Writes since the last successful save.
+    dirty: AtomicU64,
+    /// Unix time in seconds of the last successful save.
+    last_save: AtomicU64,
+    /// Unix time in seconds the last save started.
+    last_attempt: AtomicU64,
+    saving: AtomicBool,
+    last_ok: AtomicBool,
+    /// A BGSAVE SCHEDULE waiting for the running save to finish.
+    scheduled: AtomicBool,
+}
+
+impl SaveState {
+    pub fn new() -> Self {
+        SaveState {
+            dirty: AtomicU64::new(0),
+            last_save: AtomicU64::new(now_secs()),
+            last_attempt: AtomicU64::new(0),
+            saving: AtomicBool::new(false),
+            last_ok: AtomicBool::new(true),
+            scheduled: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts `changes` towards the save rules.
+    pub fn changed(&self, changes: u64) {
+        self.dirty.fetch_add(changes, Ordering::Relaxed);
+    }
+
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.saving.load(Ordering::Acquire)
+    }
+
+    pub fn last_ok(&self) -> bool {
+        self.last_ok.load(Ordering::Relaxed)
+    }
+
+    pub fn schedule(&self) {
+        self.scheduled.store(true, Ordering::Relaxed);
+    }
+
+    /// Claims the right to save; false if a save is already running.
+    fn start(&self) -> bool {
+        if self.saving.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        self.last_attempt.store(now_secs(), Ordering::Relaxed);
+        true
+    }
+
+    fn finish(&self, snapshot: &Snapshot, result: &io::Result<()>) {
+        self.last_ok.store(result.is_ok(), Ordering::Relaxed);
+        if result.is_ok() {
+            // Writes made while the snapshot was written still count.
+            self.dirty.fetch_sub(snapshot.dirty, Ordering::Relaxed);
+            self.last_save.store(now_secs(), Ordering::Relaxed);
+        }
+        self.saving.store(false, Ordering::Release);
+    }
+}
+
+/// Every live key of every database. Clients only wait for the copy, not
+/// for the file to be written.
+///
+/// The copy is taken one shard index at a time, locking that shard in
+/// every database together, so writes elsewhere are only held up for one
+/// shard's worth of copying. Each shard is therefore a single point in
+/// time, and so is a key moved between databases by MOVE, which stays in
+/// the same shard index. Commands that change keys in several shards, or
+/// whole databases like SWAPDB and FLUSHALL, may show up half done when
+/// they run during the copy. The AOF rewrite holds every write back while
+/// it takes its copy, so that one is a single point in time.
+pub struct Snapshot {
+    dbs: Vec<Vec<(Bytes, Unit)>>,
+    /// The write count the snapshot includes.
+    dirty: u64,
+}
+
+impl Snapshot {
+    pub fn take(server: &Server) -> Self {
+        // Read before copying, so writes that make it into the copy still
+        // count as unsaved; at worst that causes one extra save.
+        let dirty = server.saves.dirty();
+        let dbs = server.dbs();
+        let mut copies = vec![Vec::new(); dbs.len()];
+        for index in 0..dbs[0].shard_count() {
+            // In database order, as every command that locks several
+            // databases does.
+            let shards: Vec<_> = dbs.iter().map(|db| db.shard_at(index)).collect();
+            for (copy, shard) in copies.iter_mut().zip(&shards) {
+                copy.extend(shard.snapshot());
+            }
+        }
+        Snapshot { dbs: copies, dirty }
+    }
+
+    /// The keys of each database.
+    pub fn dbs(&self) -> &[Vec<(Bytes, Unit)>] {
+        &self.dbs
+    }
+
+    /// Writes the snapshot through a temporary file renamed over `path`, so
+    /// a crash midway leaves the previous snapshot intact.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let temp = path.with_file_name(format!("temp-{}.rdb", process::id()));
+        let result = File::create(&temp).and_then(|file| {
+            let mut out = Encoder::new(BufWriter::new(file));
+            self.encode(&mut out)?;
+            let file = out.output.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()
+        });
+        match result {
+            Ok(()) => fs::rename(&temp, path),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    fn encode<W: Write>(&self, out: &mut Encoder<W>) -> io::Result<()> {
+        out.write(format!("REDIS{:04}", RDB_VERSION).as_bytes())?;
+        out.aux("redis-bits", "64")?;
+        out.aux("ctime", &now_secs().to_string())?;
+        out.aux("appledore-ver", env!("CARGO_PKG_VERSION"))?;
+        for (index, keys) in self.dbs.iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+            out.write(&[OPCODE_SELECTDB])?;
+            out.length(index as u64)?;
+            let expires = keys.iter().filter(|(_, u)| u.expireat().is_some()).count();
+            out.write(&[OPCODE_RESIZEDB])?;
+            out.length(keys.len() as u64)?;
+            out.length(expires as u64)?;
+            for (key, unit) in keys {
+                out.entry(key, unit)?;
+            }
+        }
+        out.write(&[OPCODE_EOF])?;
+        let checksum = out.crc;
+        out.write(&checksum.to_le_bytes())
+    }
+}
+
+struct Encoder<W> {
+    output: W,
+    crc: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(output: W) -> Self {
+        Encoder { output, crc: 0 }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.crc = crc64(self.crc, bytes);
+        self.output.write_all(bytes)
+    }
+
+    fn length(&mut self, len: u64) -> io::Result<()> {
+        if len < 1 << 6 {
+            self.write(&[len as u8])
+        } else if len < 1 << 14 {
+            self.write(&[(LEN_14BIT << 6) | (len >> 8) as u8, len as u8])
+        } else if len <= u32::MAX as u64 {
+            self.write(&[LEN_32BIT])?;
+            self.write(&(len as u32).to_be_bytes())
+        } else {
+            self.write(&[LEN_64BIT])?;
+            self.write(&len.to_be_bytes())
+        }
+    }
+
+    fn string(&mut self, s: &[u8]) -> io::Result<()> {
+        self.length(s.len() as u64)?;
+        self.write(s)
+    }
+
+    fn aux(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.write(&[OPCODE_AUX])?;
+        self.string(name.as_bytes())?;
+        self.string(value.as_bytes())
+    }
+
+    /// Writes one key. Empty lists, hashes and queues are skipped: Redis
+    /// has no empty keys and would refuse to load them.
+    fn entry(&mut self, key: &[u8], unit: &Unit) -> io::Result<()> {
+        if unit.value().is_empty() {
+            return Ok(());
+        }
+        if let Some(at) = unit.expireat() {
+            self.write(&[OPCODE_EXPIRETIME_MS])?;
+            self.write(&at.to_le_bytes())?;
+        }
+        match unit.value() {
+            Value::String(s) => {
+                self.write(&[TYPE_STRING])?;
+                self.string(key)?;
+                self.string(s)?;
+            }
+            Value::Vector(items) => {
+                self.write(&[TYPE_LIST])?;
+                self.string(key)?;
+                self.length(items.len() as u64)?;
+                for item in items {
+                    self.string(item)?;
+                }
+            }
+            Value::Hash(map) => {
+                self.write(&[TYPE_HASH])?;
+                self.string(key)?;
+                self.length(map.len() as u64)?;
+                for (field, value) in map {
+                    self.string(field)?;
+                    self.string(value)?;
+                }
+            }
+            Value::Queue(queue) => {
+                self.write(&[TYPE_MODULE_2])?;
+                self.string(key)?;
+                self.length(QUEUE_MODULE_ID)?;
+                self.length(MODULE_OPCODE_UINT)?;
+                self.length(queue.items().len() as u64)?;
+                for item in queue.items() {
+                    self.length(MODULE_OPCODE_STRING)?;
+                    self.string(item)?;
+                }
+                self.length(MODULE_OPCODE_EOF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Decoder<R> {
+    input: R,
+    crc: u64,
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(input: R) -> Self {
+        Decoder { input, crc: 0 }
+    }
+
+    fn bytes(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.input).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.crc = crc64(self.crc, &buf);
+        Ok(buf)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.input.read_exact(&mut buf)?;
+        self.crc = crc64(self.crc, &buf);
+        Ok(buf)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    /// A length, or the kind of a specially encoded string along with true.
+    fn length_or_encoding(&mut self) -> io::Result<(u64, bool)> {
+        let first = self.byte()?;
+        match (first >> 6, first) {
+            (_, LEN_32BIT) => Ok((u32::from_be_bytes(self.array()?) as u64, false)),
+            (_, LEN_64BIT) => Ok((u64::from_be_bytes(self.array()?), false)),
+            (0, _) => Ok((first as u64, false)),
+            (LEN_14BIT, _) => Ok(((((first & 0x3f) as u64) << 8) | self.byte()? as u64, false)),
+            (LEN_ENCODED, _) => Ok(((first & 0x3f) as u64, true)),
+            _ => Err(invalid(format!("unknown length encoding {:#04x}", first))),
+        }
+    }
+
+    fn length(&mut self) -> io::Result<u64> {
+        match self.length_or_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => Err(invalid("unexpected string encoding")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<Bytes> {
+        let (len, encoded) = self.length_or_encoding()?;
+        if !encoded {
+            return Ok(Bytes::from(self.bytes(len)?));
+        }
+        let n = match len {
+            ENC_INT8 => i8::from_le_bytes(self.array()?) as i64,
+            ENC_INT16 => i16::from_le_bytes(self.array()?) as i64,
+            ENC_INT32 => i32::from_le_bytes(self.array()?) as i64,
+            ENC_LZF => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                let compressed = self.bytes(compressed_len)?;
+                return Ok(Bytes::from(lzf::decompress(&compressed, len as usize)?));
+            }
+            _ => return Err(invalid(format!("unsupported string encoding {}", len))),
+        };
+        Ok(Bytes::from(n.to_string()))
+    }
+
+    /// A length followed by that many strings.
+    fn strings(&mut self) -> io::Result<Vec<Bytes>> {
+        let len = self.length()?;
+        let mut items = Vec::with_capacity((len as usize).min(MAX_PREALLOC));
+        for _ in 0..len {
+            items.push(self.string()?);
+        }
+        Ok(items)
+    }
+
+    fn module_opcode(&mut self, expected: u64) -> io::Result<()> {
+        if self.length()? != expected {
+            return Err(invalid("malformed module value"));
+        }
+        Ok(())
+    }
+
+    /// Reads past what a module saved. Module values tag every field they
+    /// write with its kind, so they can be skipped without the module.
+    fn skip_module_value(&mut self) -> io::Result<()> {
+        loop {
+            match self.length()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                    self.length()?;
+                }
+                MODULE_OPCODE_FLOAT => {
+                    self.array::<4>()?;
+                }
+                MODULE_OPCODE_DOUBLE => {
+                    self.array::<8>()?;
+                }
+                MODULE_OPCODE_STRING => {
+                    self.string()?;
+                }
+                opcode => return Err(invalid(format!("unknown module opcode {}", opcode))),
+            }
+        }
+    }
+
+    /// Reads past a stream: its entries, packed in listpacks, then its
+    /// metadata and consumer groups.
+    fn skip_stream(&mut self, kind: u8) -> io::Result<()> {
+        let listpacks = self.length()?;
+        for _ in 0..listpacks {
+            self.string()?;
+            self.string()?;
+        }
+        // Length and last id, then the first id, the last deleted id and
+        // the number of entries ever added.
+        let ids = if kind >= TYPE_STREAM_LISTPACKS_2 {
+            8
+        } else {
+            3
+        };
+        for _ in 0..ids {
+            self.length()?;
+        }
+        let groups = self.length()?;
+        for _ in 0..groups {
+            self.string()?;
+            self.length()?;
+            self.length()?;
+            if kind >= TYPE_STREAM_LISTPACKS_2 {
+                self.length()?;
+            }
+            let pending = self.length()?;
+            for _ in 0..pending {
+                // Entry id, delivery time and delivery count.
+                self.array::<16>()?;
+                self.array::<8>()?;
+                self.length()?;
+            }
+            let consumers = self.length()?;
+            for _ in 0..consumers {
+                self.string()?;
+                // Seen time, and from Redis 7.2 on the active time.
+                self.array::<8>()?;
+                if kind >= TYPE_STREAM_LISTPACKS_3 {
+                    self.array::<8>()?;
+                }
+                let pending = self.length()?;
+                for _ in 0..pending {
+                    self.array::<16>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, kind: u8) -> io::Result<Entry> {
+        let value = match kind {
+            TYPE_STRING => Value::String(self.string()?),
+            TYPE_LIST => Value::Vector(self.strings()?.into()),
+            TYPE_LIST_ZIPLIST => Value::Vector(packed::ziplist(&self.string()?)?.into()),
+            TYPE_LIST_QUICKLIST => {
+                let nodes = self.length()?;
+                let mut items = Vec::new();
+                for _ in 0..nodes {
+                    items.extend(packed::ziplist(&self.string()?)?);
+                }
+                Value::Vector(items.into())
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.length()?;
+                let mut items = Vec::new();
+                for _ in 0..nodes {
+                    let container = self.length()?;
+                    let node = self.string()?;
+                    match container {
+                        QUICKLIST_NODE_PLAIN => items.push(node),
+                        QUICKLIST_NODE_PACKED => items.extend(packed::listpack(&node)?),
+                        _ => {
+                            return Err(invalid(format!(
+                                "unknown quicklist container {}",
+                                container
+                            )))
+                        }
+                    }
+                }
+                Value::Vector(items.into())
+            }
+            TYPE_HASH => {
+                let len = self.length()?;
+                let mut map = HashMap::with_capacity((len as usize).min(MAX_PREALLOC));
+                for _ in 0..len {
+                    let field = self.string()?;
+                    map.insert(field, self.string()?);
+                }
+                Value::Hash(map)
+            }
+            TYPE_HASH_ZIPLIST => Value::Hash(pairs(packed::ziplist(&self.string()?)?)?),
+            TYPE_HASH_LISTPACK => Value::Hash(pairs(packed::listpack(&self.string()?)?)?),
+            TYPE_MODULE_2 => {
+                let id = self.length()?;
+                if id != QUEUE_MODULE_ID {
+                    self.skip_module_value()?;
+                    return Ok(Entry::Unsupported(format!(
+                        "module type {}",
+                        module_name(id)
+                    )));
+                }
+                self.module_opcode(MODULE_OPCODE_UINT)?;
+                let len = self.length()?;
+                let mut items = Vec::with_capacity((len as usize).min(MAX_PREALLOC));
+                for _ in 0..len {
+                    self.module_opcode(MODULE_OPCODE_STRING)?;
+                    items.push(self.string()?);
+                }
+                self.module_opcode(MODULE_OPCODE_EOF)?;
+                let mut queue = Queue::new();
+                queue.append(items);
+                Value::Queue(queue)
+            }
+            TYPE_SET => {
+                self.strings()?;
+                return Ok(Entry::Unsupported("set".to_string()));
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let len = self.length()?;
+                for _ in 0..len {
+                    self.string()?;
+                    if kind == TYPE_ZSET_2 {
+                        self.array::<8>()?;
+                    } else {
+                        let score_len = self.byte()?;
+                        if score_len < ZSET_SCORE_NAN {
+                            self.bytes(score_len as u64)?;
+                        }
+                    }
+                }
+                return Ok(Entry::Unsupported("zset".to_string()));
+            }
+            TYPE_SET_INTSET | TYPE_SET_LISTPACK | TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK
+            | TYPE_HASH_ZIPMAP => {
+                self.string()?;
+                let name = match kind {
+                    TYPE_SET_INTSET | TYPE_SET_LISTPACK => "set",
+                    TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => "zset",
+                    _ => "hash (zipmap)",
+                };
+                return Ok(Entry::Unsupported(name.to_string()));
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.skip_stream(kind)?;
+                return Ok(Entry::Unsupported("stream".to_string()));
+            }
+            _ => return Err(invalid(format!("unsupported value type {}", kind))),
+        };
+        Ok(Entry::Value(value))
+    }
+}
+
+/// Pairs up the alternating fields and values of a packed hash.
+fn pairs(items: Vec<Bytes>) -> io::Result<HashMap<Bytes, Bytes>> {
+    let pairs = items.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(invalid("hash with a field but no value"));
+    }
+    Ok(pairs
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+/// A key's value as read from the file.
+enum Entry {
+    Value(Value),
+    /// A value of a type Appledore has no counterpart for, read past and
+    /// named for the report.
+    Unsupported(String),
+}
+
+/// What reading an RDB file loaded, and what it left out.
+#[derive(Default)]
+pub struct LoadReport {
+    pub keys: usize,
+    /// Keys dropped because they expired before the file was read.
+    pub expired: usize,
+    /// Keys and other data that could not be converted, counted by their
+    /// Redis type.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+impl LoadReport {
+    /// Counts data of type `name` as left out, or fails when everything in
+    /// the file has to be loaded.
+    fn skip(&mut self, name: String, skip_unsupported: bool) -> io::Result<()> {
+        if !skip_unsupported {
+            return Err(invalid(format!(
+                "the file holds a {} value, which can't be loaded; import it with --import-rdb to leave such values out",
+                name
+            )));
+        }
+        *self.skipped.entry(name).or_insert(0) += 1;
+        Ok(())
+    }
+}
+
+/// Loads the snapshot at the configured path into the databases. Returns
+/// the number of keys loaded, or `None` when there is no snapshot yet.
+pub fn load(server: &Server) -> io::Result<Option<usize>> {
+    let file = match File::open(server.config.rdb_path()) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    read(server, file, false).map(|report| Some(report.keys))
+}
+
+/// Imports an RDB file written by Redis into the databases, over the keys
+/// already there. Values of types Appledore has no counterpart for, like
+/// sets and streams, are left out and counted in the report.
+pub fn import(server: &Server, path: &Path) -> io::Result<LoadReport> {
+    let report = read(server, File::open(path)?, true)?;
+    // Unlike loaded keys, imported ones are not in our own files yet.
+    server.saves.changed(report.keys as u64);
+    Ok(report)
+}
+
+fn read(server: &Server, file: File, skip_unsupported: bool) -> io::Result<LoadReport> {
+    let mut input = Decoder::new(BufReader::new(file));
+    let magic: [u8; 9] = input.array()?;
+    if &magic[..5] != b"REDIS" {
+        return Err(invalid("not an RDB file"));
+    }
+    let version: u32 = std::str::from_utf8(&magic[5..])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid("not an RDB file"))?;
+    if !(1..=MAX_LOAD_VERSION).contains(&version) {
+        return Err(invalid(format!(
+            "can't handle RDB format version {}",
+            version
+        )));
+    }
+    let now = now_ms();
+    let mut db = 0;
+    let mut expireat = None;
+    let mut report = LoadReport::default();
+    loop {
+        match input.byte()? {
+            OPCODE_AUX => {
+                input.string()?;
+                input.string()?;
+            }
+            OPCODE_RESIZEDB => {
+                input.length()?;
+                input.length()?;
+            }
+            OPCODE_SELECTDB => {
+                db = input.length()? as usize;
+                if db >= server.dbs().len() {
+                    return Err(invalid(format!(
+                        "the file uses database {} but only {} are configured",
+                        db,
+                        server.dbs().len()
+                    )));
+                }
+            }
+            OPCODE_EXPIRETIME_MS => expireat = Some(u64::from_le_bytes(input.array()?)),
+            OPCODE_EXPIRETIME => expireat = Some(u32::from_le_bytes(input.array()?) as u64 * 1000),
+            // The next key's LRU idle time and LFU counter, which eviction
+            // here starts over from.
+            OPCODE_IDLE => {
+                input.length()?;
+            }
+            OPCODE_FREQ => {
+                input.byte()?;
+            }
+            OPCODE_MODULE_AUX => {
+                let id = input.length()?;
+                input.skip_module_value()?;
+                report.skip(format!("module {} data", module_name(id)), skip_unsupported)?;
+            }
+            OPCODE_FUNCTION2 => {
+                input.string()?;
+                report.skip("function library".to_string(), skip_unsupported)?;
+            }
+            OPCODE_EOF => break,
+            kind => {
+                let key = input.string()?;
+                let entry = input.value(kind)?;
+                let expireat = expireat.take();
+                let value = match entry {
+                    Entry::Value(value) => value,
+                    Entry::Unsupported(name) => {
+                        report.skip(name, skip_unsupported)?;
+                        continue;
+                    }
+                };
+                // Keys that expired while the server was down are dropped.
+                if matches!(expireat, Some(at) if at <= now) {
+                    report.expired += 1;
+                    continue;
+                }
+                server
+                    .storage(db, &key)
+                    .put(key, Unit::new(value, expireat));
+                report.keys += 1;
+            }
+        }
+    }
+    if version >= 5 {
+        let expected = input.crc;
+        let checksum = u64::from_le_bytes(input.array()?);
+        // Writers with checksums turned off store zero.
+        if checksum != 0 && checksum != expected {
+            return Err(invalid("checksum mismatch"));
+        }
+    }
+    Ok(report)
+}
+
+/// Saves in the foreground. Returns `None` when another save is running.
+pub fn save(server: &Server) -> Option<io::Result<()>> {
+    if !server.saves.start() {
+        return None;
+    }
+    let snapshot = Snapshot::take(server);
+    let result = snapshot.write(&server.config.rdb_path());
+    server.saves.finish(&snapshot, &result);
+    Some(result)
+}
+
+/// Starts a background save. Returns `None` when another save is running,
+/// and an error when the thread to write it could not be started. The
+/// snapshot is taken before this returns; only writing it is left to
+/// another thread.
+pub fn bgsave(server: &Server) -> Option<io::Result<()>> {
+    if !server.saves.start() {
+        return None;
+    }
+    let snapshot = Snapshot::take(server);
+    let saves = Arc::clone(&server.saves);
+    let path = server.config.rdb_path();
+    let spawned = thread::Builder::new()
+        .name("bgsave".to_string())
+        .spawn(move || {
+            let started = Instant::now();
+            let result = snapshot.write(&path);
+            match &result {
+                Ok(()) => println!(
+                    "Background saving terminated with success in {:.3} seconds",
+                    started.elapsed().as_secs_f64()
+                ),
+                Err(e) => eprintln!("Background saving error: {}", e),
+            }
+            saves.finish(&snapshot, &result);
+        });
+    if let Err(e) = &spawned {
+        eprintln!("Can't save in background: {}", e);
+        server.saves.last_ok.store(false, Ordering::Relaxed);
+        server.saves.saving.store(false, Ordering::Release);
+    }
+    Some(spawned.map(|_| ()))
+}
+
+/// Starts the thread that runs scheduled background saves and the ones
+/// the `save` rules call for.
+pub fn spawn_autosave(server: Arc<Server>) {
+    thread::Builder::new()
+        .name("autosave".to_string())
+        .spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            let saves = &server.saves;
+            if saves.in_progress() {
+                continue;
+            }
+            let now = now_secs();
+            let dirty = saves.dirty();
+            let since_save = now.saturating_sub(saves.last_save());
+            let due = server
+                .config
+                .save_rules()
+                .iter()
+                .any(|rule| dirty >= rule.changes && since_save > rule.seconds);
+            let may_retry = saves.last_ok()
+                || now.saturating_sub(saves.last_attempt.load(Ordering::Relaxed))
+                    >= RETRY_DELAY_SECS;
+            if saves.scheduled.swap(false, Ordering::Relaxed) || (due && may_retry) {
+                bgsave(&server);
+            }
+        })
+        .expect("failed to spawn the autosave thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        sync::atomic::{AtomicBool, AtomicUsize},
+        thread,
+    };
+
+    use super::*;
+    use crate::{acl::Acl, config::ServerConfig};
+
+    /// "abcabcabcabc!" compressed with LZF, as a string with its lengths.
+    const LZF_STRING: &[u8] = &[
+        0xc3, 0x09, 0x0d, 0x02, b'a', b'b', b'c', 0xe0, 0x00, 0x02, 0x00, b'!',
+    ];
+    /// The ziplist of "2" and "5" from Redis's ziplist.c.
+    const ZIPLIST: &[u8] = &[
+        0x0f, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0xf3, 0x02, 0xf6, 0xff,
+    ];
+    /// A listpack of "a" and 7.
+    const LISTPACK: &[u8] = &[
+        0x0c, 0x00, 0x00, 0x00, 0x02, 0x00, 0x81, b'a', 0x02, 0x07, 0x01, 0xff,
+    ];
+    /// A ziplist and a listpack of the hash field "f" set to "v".
+    const HASH_ZIPLIST: &[u8] = &[
+        0x11, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x01, b'f', 0x03, 0x01,
+        b'v', 0xff,
+    ];
+    const HASH_LISTPACK: &[u8] = &[
+        0x0d, 0x00, 0x00, 0x00, 0x02, 0x00, 0x81, b'f', 0x02, 0x81, b'v', 0x02, 0xff,
+    ];
+
+    fn string(s: &[u8]) -> Vec<u8> {
+        [&[s.len() as u8], s].concat()
+    }
+
+    fn key(kind: u8, name: &[u8], value: &[u8]) -> Vec<u8> {
+        [&[kind], &string(name)[..], value].concat()
+    }
+
+    /// A version 11 file holding a key of every list and hash encoding
+    /// Redis writes, one of each kind of string, a set, which is left out,
+    /// and a key that has expired.
+    fn every_encoding() -> Vec<u8> {
+        let mut body = b"REDIS0011".to_vec();
+        body.extend([OPCODE_AUX]);
+        body.extend(string(b"redis-ver"));
+        body.extend(string(b"7.2.4"));
+        body.extend([OPCODE_SELECTDB, 0, OPCODE_RESIZEDB, 13, 1]);
+        body.extend(key(TYPE_STRING, b"plain", &string(b"hello")));
+        body.extend(key(TYPE_STRING, b"int8", &[0xc0, 0x85]));
+        body.extend(key(TYPE_STRING, b"int16", &[0xc1, 0x39, 0x30]));
+        body.extend(key(TYPE_STRING, b"int32", &[0xc2, 0xa0, 0x86, 0x01, 0x00]));
+        body.extend(key(TYPE_STRING, b"lzf", LZF_STRING));
+        body.extend(key(
+            TYPE_LIST,
+            b"list",
+            &[&[0x02], &string(b"a")[..], &string(b"b")].concat(),
+        ));
+        body.extend(key(TYPE_LIST_ZIPLIST, b"ziplist", &string(ZIPLIST)));
+        // One plain node, one compressed as a single LZF literal run.
+        let mut quicklist = vec![0x02];
+        quicklist.extend(string(ZIPLIST));
+        quicklist.extend([0xc3, 0x10, 0x0f, 0x0e]);
+        quicklist.extend(ZIPLIST);
+        body.extend(key(TYPE_LIST_QUICKLIST, b"quicklist", &quicklist));
+        let mut quicklist2 = vec![0x02, QUICKLIST_NODE_PLAIN as u8];
+        quicklist2.extend(string(b"plain"));
+        quicklist2.push(QUICKLIST_NODE_PACKED as u8);
+        quicklist2.extend(string(LISTPACK));
+        body.extend(key(TYPE_LIST_QUICKLIST_2, b"quicklist2", &quicklist2));
+        body.extend(key(
+            TYPE_HASH,
+            b"hash",
+            &[&[0x01], &string(b"f")[..], &string(b"v")].concat(),
+        ));
+        body.extend(key(TYPE_HASH_ZIPLIST, b"hashzl", &string(HASH_ZIPLIST)));
+        body.extend(key(TYPE_HASH_LISTPACK, b"hashlp", &string(HASH_LISTPACK)));
+        body.extend(key(
+            TYPE_SET,
+            b"set",
+            &[&[0x01], &string(b"m")[..]].concat(),
+        ));
+        body.push(OPCODE_EXPIRETIME_MS);
+        body.extend(1000u64.to_le_bytes());
+        body.extend(key(TYPE_STRING, b"expired", &string(b"v")));
+        body.push(OPCODE_EOF);
+        let checksum = crc64(0, &body);
+        body.extend(checksum.to_le_bytes());
+        body
+    }
+
+    /// Imports `file` through a temporary file, into a server of its own.
+    fn import_bytes(file: &[u8]) -> io::Result<(Server, LoadReport)> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "appledore-import-{}-{}.rdb",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, file)?;
+        let server = Server::new(ServerConfig::default(), Acl::new(None));
+        let result = import(&server, &path);
+        fs::remove_file(&path)?;
+        result.map(|report| (server, report))
+    }
+
+    fn list(server: &Server, key: &[u8]) -> Vec<Bytes> {
+        server.storage(0, key).get_array(key, 0, -1).unwrap()
+    }
+
+    fn strings(items: &[&[u8]]) -> Vec<Bytes> {
+        items.iter().map(|i| Bytes::copy_from_slice(i)).collect()
+    }
+
+    #[test]
+    fn imports_every_encoding() {
+        let (server, report) = import_bytes(&every_encoding()).unwrap();
+        assert_eq!(report.keys, 12);
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.skipped.get("set"), Some(&1));
+        let string = |key: &[u8]| server.storage(0, key).get_string(key).unwrap();
+        assert_eq!(string(b"plain"), "hello");
+        assert_eq!(string(b"int8"), "-123");
+        assert_eq!(string(b"int16"), "12345");
+        assert_eq!(string(b"int32"), "100000");
+        assert_eq!(string(b"lzf"), "abcabcabcabc!");
+        assert_eq!(list(&server, b"list"), strings(&[b"a", b"b"]));
+        assert_eq!(list(&server, b"ziplist"), strings(&[b"2", b"5"]));
+        assert_eq!(
+            list(&server, b"quicklist"),
+            strings(&[b"2", b"5", b"2", b"5"])
+        );
+        assert_eq!(
+            list(&server, b"quicklist2"),
+            strings(&[b"plain", b"a", b"7"])
+        );
+        for key in [&b"hash"[..], b"hashzl", b"hashlp"] {
+            let hash = server.storage(0, key).hash_get_all(key).unwrap();
+            assert_eq!(hash.get(&b"f"[..]).map(|v| &v[..]), Some(&b"v"[..]));
+        }
+        assert!(!server.storage(0, b"expired").exists(b"expired"));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let file = every_encoding();
+        for len in 0..file.len() {
+            assert!(import_bytes(&file[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_bytes() {
+        let file = every_encoding();
+        for i in 0..file.len() {
+            let mut corrupt = file.clone();
+            corrupt[i] ^= 0xff;
+            assert!(import_bytes(&corrupt).is_err(), "byte {} flipped", i);
+        }
+    }
+
+    #[test]
+    fn accepts_a_zero_checksum() {
+        let mut file = every_encoding();
+        let len = file.len();
+        file[len - 8..].fill(0);
+        assert!(import_bytes(&file).is_ok());
+    }
+
+    #[test]
+    fn snapshots_copy_each_shard_at_a_point_in_time() {
+        let server = Server::new(ServerConfig::default(), Acl::new(None));
+        let keys: Vec<Bytes> = (0..200)
+            .map(|i| Bytes::from(format!("key:{}", i)))
+            .collect();
+        for key in &keys {
+            server
+                .storage(0, key)
+                .set_string(key.clone(), Bytes::from_static(b"v"));
+        }
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            // Moves keys between databases the way MOVE does, while
+            // snapshots are taken.
+            scope.spawn(|| {
+                let mut round = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let (from, to) = (round % 3, (round + 1) % 3);
+                    for key in &keys {
+                        let (mut src, mut dst) = server.storage_pair(from, key, to, key);
+                        if let Some(unit) = src.take(key) {
+                            dst.put(key.clone(), unit);
+                        }
+                    }
+                    round += 1;
+                }
+            });
+            for _ in 0..20 {
+                let snapshot = Snapshot::take(&server);
+                let copied: usize = snapshot.dbs().iter().map(|db| db.len()).sum();
+                assert_eq!(copied, keys.len());
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+    }
+}
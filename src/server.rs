@@ -0,0 +1,86 @@
+use std::{
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Instant,
+};
+
+use crate::{
+    acl::Acl,
+    aof::Aof,
+    config::ServerConfig,
+    keyspace::{Keyspace, MemoryUsage, ShardGuard},
+    rdb::SaveState,
+};
+
+/// State shared by every connection.
+///
+/// The locks are taken through the accessors below, which ignore poisoning:
+/// a handler that panics only loses its own connection, and the data it was
+/// working on stays usable by everyone else.
+pub struct Server {
+    pub config: ServerConfig,
+    /// The logical databases, `databases` of them.
+    dbs: Vec<Keyspace>,
+    pub memory: Arc<MemoryUsage>,
+    pub saves: Arc<SaveState>,
+    /// Set once the data is loaded when `appendonly` is on.
+    pub aof: Option<Arc<Aof>>,
+    acl: RwLock<Acl>,
+    pub started: Instant,
+}
+
+impl Server {
+    pub fn new(config: ServerConfig, acl: Acl) -> Self {
+        let memory = Arc::new(MemoryUsage::default());
+        Server {
+            dbs: (0..config.databases)
+                .map(|_| Keyspace::new(Arc::clone(&memory)))
+                .collect(),
+            config,
+            memory,
+            saves: Arc::new(SaveState::new()),
+            aof: None,
+            acl: RwLock::new(acl),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn db(&self, index: usize) -> &Keyspace {
+        &self.dbs[index]
+    }
+
+    pub fn dbs(&self) -> &[Keyspace] {
+        &self.dbs
+    }
+
+    /// The shard of database `db` holding `key`.
+    pub fn storage(&self, db: usize, key: &[u8]) -> ShardGuard<'_> {
+        self.dbs[db].shard(key)
+    }
+
+    /// The shards holding `src` in database `src_db` and `dst` in the
+    /// different database `dst_db`. The lower database is always locked
+    /// first so that two cross-database commands cannot deadlock.
+    pub fn storage_pair(
+        &self,
+        src_db: usize,
+        src: &[u8],
+        dst_db: usize,
+        dst: &[u8],
+    ) -> (ShardGuard<'_>, ShardGuard<'_>) {
+        if src_db < dst_db {
+            let first = self.storage(src_db, src);
+            (first, self.storage(dst_db, dst))
+        } else {
+            let first = self.storage(dst_db, dst);
+            (self.storage(src_db, src), first)
+        }
+    }
+
+    pub fn acl(&self) -> RwLockReadGuard<'_, Acl> {
+        self.acl.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn acl_mut(&self) -> RwLockWriteGuard<'_, Acl> {
+        self.acl.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
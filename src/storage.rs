@@ -1,27 +1,118 @@
 use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
+    collections::{BTreeSet, HashMap, VecDeque},
+    mem::size_of,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::encoder::*;
+use bytes::Bytes;
+
+use crate::{config::MaxmemoryPolicy, glob::glob_match, keyspace::key_hash, rng::Rng};
+
+/// Starting access frequency of a new key, so it is not evicted before it
+/// had a chance to be read.
+const LFU_INIT_VAL: u8 = 5;
+/// How much harder each step of the logarithmic frequency counter gets.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// The frequency counter drops by one for every this many idle milliseconds.
+const LFU_DECAY_MS: u64 = 60_000;
+/// Per key bookkeeping: the unit itself and the key's entries in the table
+/// and in the ordered index.
+const ENTRY_OVERHEAD: usize = size_of::<Unit>() + 2 * size_of::<(Bytes, usize)>();
+const HASH_FIELD_OVERHEAD: usize = size_of::<u64>();
+/// Expired keys RANDOMKEY may run into before giving up.
+const RANDOM_KEY_TRIES: usize = 100;
 
 #[derive(Clone, Debug)]
-enum Value {
-    String(Vec<u8>),
-    Vector(Vec<String>),
-    Hash(HashMap<String, Vec<u8>>),
+pub enum Value {
+    String(Bytes),
+    /// A deque, so that pushing and popping at either end of a long list
+    /// does not move the rest.
+    Vector(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
     Queue(Queue),
 }
 
+/// A value with its expiry and access statistics, as moved between keys
+/// by RENAME and friends.
 #[derive(Clone, Debug)]
-struct Unit {
-    expireat: Option<Instant>,
+pub struct Unit {
+    /// Unix time in milliseconds.
+    expireat: Option<u64>,
     value: Value,
+    /// Unix time in milliseconds of the last read or write.
+    access: u64,
+    /// Logarithmic access frequency counter, as Redis keeps for LFU.
+    freq: u8,
 }
 
-#[derive(Clone, Debug)]
-struct Queue {
-    queue: Vec<String>
+/// Names of the value types, indexed by `Value::kind`.
+pub const TYPE_NAMES: [&str; 4] = ["string", "list", "hash", "queue"];
+
+impl Value {
+    fn kind(&self) -> usize {
+        match self {
+            Value::String(_) => 0,
+            Value::Vector(_) => 1,
+            Value::Hash(_) => 2,
+            Value::Queue(_) => 3,
+        }
+    }
+
+    /// Whether this is a collection with nothing in it.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::Vector(items) => items.is_empty(),
+            Value::Hash(map) => map.is_empty(),
+            Value::Queue(queue) => queue.queue.is_empty(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Queue {
+    queue: VecDeque<Bytes>,
+}
+
+/// Estimated bytes taken by a string, including its handle.
+fn item_size(item: &Bytes) -> usize {
+    size_of::<Bytes>() + item.len()
+}
+
+/// Sum of `sizes`, or with `samples` > 0 an estimate extrapolated from
+/// the first `samples` of them.
+fn estimate(sizes: impl ExactSizeIterator<Item = usize>, samples: usize) -> usize {
+    let len = sizes.len();
+    if samples == 0 || len <= samples {
+        return sizes.sum();
+    }
+    sizes.take(samples).sum::<usize>() * len / samples
+}
+
+/// Estimated bytes held by a value, looking at only `samples` elements of
+/// a collection unless that is 0.
+fn value_size(value: &Value, samples: usize) -> usize {
+    match value {
+        Value::String(s) => item_size(s),
+        Value::Vector(v) => estimate(v.iter().map(item_size), samples),
+        Value::Hash(map) => estimate(
+            map.iter()
+                .map(|(k, v)| item_size(k) + item_size(v) + HASH_FIELD_OVERHEAD),
+            samples,
+        ),
+        Value::Queue(q) => estimate(q.queue.iter().map(item_size), samples),
+    }
+}
+
+fn unit_size(key: &[u8], unit: &Unit) -> usize {
+    ENTRY_OVERHEAD + size_of::<Bytes>() + key.len() + value_size(&unit.value, 0)
+}
+
+/// The current unix time in milliseconds, the clock key expiries use.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
 }
 
 #[derive(Debug)]
@@ -33,155 +124,521 @@ pub enum StorageError {
 }
 
 pub enum PopReply {
-    String(String),
-    Vector(Vec<String>),
+    String(Bytes),
+    Vector(Vec<Bytes>),
+}
+
+/// The end of a list pushed to or popped from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListEnd {
+    Head,
+    Tail,
+}
+
+/// A set of keys kept in a vector, so the active expiry cycle can pick
+/// random ones in constant time.
+#[derive(Clone, Default)]
+struct KeySet {
+    keys: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
 }
 
 #[derive(Clone)]
-pub struct Storage(HashMap<String, Unit>);
+pub struct Storage {
+    data: HashMap<Bytes, Unit>,
+    /// Every key ordered by its hash, which does not depend on the table's
+    /// size. SCAN cursors are positions in this order, so they stay valid
+    /// however the table grows or shrinks, and picking the key after a
+    /// random hash samples keys for eviction and RANDOMKEY.
+    order: BTreeSet<(u64, Bytes)>,
+    /// The keys that have an expiry.
+    volatile: KeySet,
+    /// Estimated bytes held by this shard, by value type.
+    memory: [usize; TYPE_NAMES.len()],
+    rng: Rng,
+    /// Keys removed because their expiry passed, lazily or by the sweeper.
+    pub expired_keys: u64,
+    /// Keys removed to stay under `maxmemory`.
+    pub evicted_keys: u64,
+}
+
+impl Unit {
+    pub fn new(value: Value, expireat: Option<u64>) -> Self {
+        Unit {
+            expireat,
+            value,
+            access: now_ms(),
+            freq: LFU_INIT_VAL,
+        }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Unix time in milliseconds the key expires at.
+    pub fn expireat(&self) -> Option<u64> {
+        self.expireat
+    }
+
+    /// The access frequency, less one for every decay period it sat idle.
+    fn decayed_freq(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.access) / LFU_DECAY_MS;
+        self.freq.saturating_sub(periods.min(255) as u8)
+    }
+
+    /// Records an access for LRU and LFU. The frequency counter grows ever
+    /// more slowly, so it can tell apart hot keys with only eight bits.
+    fn touch(&mut self, now: u64, rng: &mut Rng) {
+        let mut freq = self.decayed_freq(now);
+        if freq < u8::MAX {
+            let base = freq.saturating_sub(LFU_INIT_VAL) as f64;
+            if rng.fraction() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                freq += 1;
+            }
+        }
+        self.freq = freq;
+        self.access = now;
+    }
+}
+
+impl KeySet {
+    fn insert(&mut self, key: Bytes) {
+        if !self.positions.contains_key(&key) {
+            self.positions.insert(key.clone(), self.keys.len());
+            self.keys.push(key);
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(pos) = self.positions.remove(key) {
+            self.keys.swap_remove(pos);
+            if let Some(moved) = self.keys.get(pos) {
+                self.positions.insert(moved.clone(), pos);
+            }
+        }
+    }
+}
 
 impl Queue {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Queue {
-            queue: Vec::new(),
+            queue: VecDeque::new(),
         }
     }
 
-    fn append(&mut self, items: Vec<String>) {
+    pub fn append(&mut self, items: Vec<Bytes>) {
         self.queue.extend(items);
     }
 
-    fn dequeue(&mut self) -> Option<String> {
-        if self.queue.is_empty() {
-            None
-        } else {
-            Some(self.queue.remove(0))
-        }
+    /// The items, oldest first.
+    pub fn items(&self) -> &VecDeque<Bytes> {
+        &self.queue
     }
 
-    fn size(&mut self) -> usize {
+    fn dequeue(&mut self) -> Option<Bytes> {
+        self.queue.pop_front()
+    }
+
+    fn size(&self) -> usize {
         self.queue.len()
     }
 }
 
 impl Storage {
     pub fn new() -> Self {
-        Storage(HashMap::new())
+        Storage {
+            data: HashMap::new(),
+            order: BTreeSet::new(),
+            volatile: KeySet::default(),
+            memory: [0; TYPE_NAMES.len()],
+            rng: Rng::new(),
+            expired_keys: 0,
+            evicted_keys: 0,
+        }
     }
 
-    pub fn set_string(&mut self, key: String, value: String) {
-        self.0.insert(
-            key,
-            Unit {
-                expireat: None,
-                value: Value::String(encode_resp_bulk_string(value)),
-            },
-        );
+    pub fn used_memory(&self) -> usize {
+        self.memory.iter().sum()
     }
 
-    pub fn set_string_px(&mut self, key: String, value: String, time: u64) {
-        let total_time = Instant::now() + Duration::from_millis(time);
-        self.0.insert(
-            key,
-            Unit {
-                expireat: Some(total_time),
-                value: Value::String(encode_resp_bulk_string(value)),
-            },
-        );
+    /// Estimated bytes held by keys of each type, indexed like `TYPE_NAMES`.
+    pub fn memory_by_type(&self) -> [usize; TYPE_NAMES.len()] {
+        self.memory
     }
 
-    pub fn set_string_ex(&mut self, key: String, value: String, time: u64) {
-        let total_time = Instant::now() + Duration::from_secs(time);
-        self.0.insert(
-            key,
-            Unit {
-                expireat: Some(total_time),
-                value: Value::String(encode_resp_bulk_string(value)),
-            },
-        );
+    /// The part of `used_memory` spent on bookkeeping rather than data.
+    pub fn overhead(&self) -> usize {
+        self.data.len() * ENTRY_OVERHEAD
     }
 
-    pub fn get_string(&mut self, key: &str) -> Result<Vec<u8>, StorageError> {
-        match self.0.get(key) {
-            Some(s) => match s.expireat {
-                Some(v) => {
-                    if v < Instant::now() {
-                        self.0.remove(key);
-                        Err(StorageError::NotFound)
-                    } else {
-                        match &s.value {
-                            Value::String(v) => Ok(v.to_vec()),
-                            _ => Err(StorageError::BadType),
-                        }
-                    }
+    /// Estimated bytes used by `key`, sampling `samples` elements of a
+    /// collection (all of them with 0). Does not count as an access.
+    pub fn memory_usage(&mut self, key: &[u8], samples: usize) -> Option<usize> {
+        let unit = self.peek(key)?;
+        Some(ENTRY_OVERHEAD + size_of::<Bytes>() + key.len() + value_size(&unit.value, samples))
+    }
+
+    /// Number of keys, and how many of them have an expiry.
+    pub fn key_counts(&self) -> (usize, usize) {
+        (self.data.len(), self.volatile.keys.len())
+    }
+
+    fn insert(&mut self, key: Bytes, unit: Unit) {
+        if unit.expireat.is_some() {
+            self.volatile.insert(key.clone());
+        } else {
+            self.volatile.remove(&key);
+        }
+        self.order.insert((key_hash(&key), key.clone()));
+        self.memory[unit.value.kind()] += unit_size(&key, &unit);
+        if let Some(old) = self.data.insert(key.clone(), unit) {
+            self.memory[old.value.kind()] -= unit_size(&key, &old);
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Unit> {
+        self.volatile.remove(key);
+        let (key, unit) = self.data.remove_entry(key)?;
+        self.memory[unit.value.kind()] -= unit_size(&key, &unit);
+        self.order.remove(&(key_hash(&key), key));
+        Some(unit)
+    }
+
+    /// Removes every key. The statistics counters are kept.
+    pub fn clear(&mut self) {
+        self.data = HashMap::new();
+        self.order.clear();
+        self.volatile = KeySet::default();
+        self.memory = [0; TYPE_NAMES.len()];
+    }
+
+    /// Removes `key` and returns its value, expiry included.
+    pub fn take(&mut self, key: &[u8]) -> Option<Unit> {
+        self.peek(key)?;
+        self.remove(key)
+    }
+
+    /// Stores a value taken from another key, replacing `key` if it exists.
+    pub fn put(&mut self, key: Bytes, unit: Unit) {
+        self.insert(key, unit);
+    }
+
+    /// A copy of every live key, for a snapshot.
+    pub fn snapshot(&self) -> Vec<(Bytes, Unit)> {
+        let now = now_ms();
+        self.data
+            .iter()
+            .filter(|(_, unit)| !matches!(unit.expireat, Some(at) if at <= now))
+            .map(|(key, unit)| (key.clone(), unit.clone()))
+            .collect()
+    }
+
+    /// A copy of the value at `key`, expiry included, for COPY.
+    pub fn duplicate(&mut self, key: &[u8]) -> Option<Unit> {
+        self.peek(key).cloned()
+    }
+
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// The type of the value at `key`, one of `TYPE_NAMES`.
+    pub fn type_name(&mut self, key: &[u8]) -> Option<&'static str> {
+        self.peek(key).map(|u| TYPE_NAMES[u.value.kind()])
+    }
+
+    /// Every live key matching the glob `pattern`.
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Bytes> {
+        let now = now_ms();
+        self.data
+            .iter()
+            .filter(|(key, unit)| {
+                !matches!(unit.expireat, Some(at) if at <= now) && glob_match(pattern, key, false)
+            })
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Visits up to `count` keys in hash order starting at hash `from`,
+    /// adding the live ones with their types to `out`. Returns the hash to
+    /// continue from, or `None` once the end of the shard was reached.
+    pub fn scan(
+        &self,
+        from: u64,
+        count: usize,
+        out: &mut Vec<(Bytes, &'static str)>,
+    ) -> Option<u64> {
+        let now = now_ms();
+        for (visited, (hash, key)) in self.order.range((from, Bytes::new())..).enumerate() {
+            if visited == count {
+                return Some(*hash);
+            }
+            if let Some(unit) = self.data.get(key) {
+                if !matches!(unit.expireat, Some(at) if at <= now) {
+                    out.push((key.clone(), TYPE_NAMES[unit.value.kind()]));
                 }
-                None => match &s.value {
-                    Value::String(v) => Ok(v.to_vec()),
-                    _ => Err(StorageError::BadType),
-                },
+            }
+        }
+        None
+    }
+
+    /// The key following a random hash, live or not.
+    fn random_entry(&mut self) -> Option<&Bytes> {
+        let from = self.rng.next();
+        self.order
+            .range((from, Bytes::new())..)
+            .next()
+            .or_else(|| self.order.iter().next())
+            .map(|(_, key)| key)
+    }
+
+    /// A random live key. Expired keys met on the way are removed, and
+    /// after a few of them in a row the search gives up.
+    pub fn random_key(&mut self) -> Option<Bytes> {
+        for _ in 0..RANDOM_KEY_TRIES {
+            let key = self.random_entry()?.clone();
+            if self.peek(&key).is_some() {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    fn expire(&mut self, key: &[u8]) {
+        self.remove(key);
+        self.expired_keys += 1;
+    }
+
+    /// `key` unless its expiry has passed, in which case it is removed.
+    /// Every command looks keys up through here or `live_mut`.
+    fn live(&mut self, key: &[u8]) -> Option<&Unit> {
+        self.live_mut(key).map(|u| &*u)
+    }
+
+    fn live_mut(&mut self, key: &[u8]) -> Option<&mut Unit> {
+        self.peek(key)?;
+        let unit = self.data.get_mut(key)?;
+        unit.touch(now_ms(), &mut self.rng);
+        Some(unit)
+    }
+
+    /// Like `live` but without recording an access, for introspection.
+    fn peek(&mut self, key: &[u8]) -> Option<&Unit> {
+        if matches!(self.data.get(key)?.expireat, Some(at) if at <= now_ms()) {
+            self.expire(key);
+            return None;
+        }
+        self.data.get(key)
+    }
+
+    /// Like `live_mut`, along with the memory counter of the value's type
+    /// for writes that grow or shrink the value in place.
+    fn live_sized(&mut self, key: &[u8]) -> Option<(&mut Unit, &mut usize)> {
+        self.live_mut(key)?;
+        let unit = self.data.get_mut(key)?;
+        let kind = unit.value.kind();
+        Some((unit, &mut self.memory[kind]))
+    }
+
+    /// Samples `samples` keys the policy may evict and returns the best
+    /// candidate with its score; a higher score is a better victim.
+    pub fn eviction_candidate(
+        &mut self,
+        policy: MaxmemoryPolicy,
+        samples: usize,
+    ) -> Option<(Bytes, u64)> {
+        let len = if policy.volatile() {
+            self.volatile.keys.len()
+        } else {
+            self.data.len()
+        };
+        if len == 0 {
+            return None;
+        }
+        let now = now_ms();
+        let mut best: Option<(Bytes, u64)> = None;
+        for _ in 0..samples.min(len) {
+            let key = if policy.volatile() {
+                self.volatile.keys[self.rng.below(len)].clone()
+            } else {
+                match self.random_entry() {
+                    Some(key) => key.clone(),
+                    None => break,
+                }
+            };
+            let unit = match self.data.get(&key) {
+                Some(u) => u,
+                None => continue,
+            };
+            let score = match policy {
+                MaxmemoryPolicy::NoEviction | MaxmemoryPolicy::AllKeysRandom => 0,
+                MaxmemoryPolicy::AllKeysLru | MaxmemoryPolicy::VolatileLru => {
+                    now.saturating_sub(unit.access)
+                }
+                MaxmemoryPolicy::AllKeysLfu => (u8::MAX - unit.decayed_freq(now)) as u64,
+                MaxmemoryPolicy::VolatileTtl => u64::MAX - unit.expireat.unwrap_or(u64::MAX),
+            };
+            match best {
+                Some((_, b)) if b >= score => {}
+                _ => best = Some((key, score)),
+            }
+        }
+        best
+    }
+
+    pub fn evict(&mut self, key: &[u8]) -> bool {
+        let evicted = self.remove(key).is_some();
+        if evicted {
+            self.evicted_keys += 1;
+        }
+        evicted
+    }
+
+    /// Checks up to `samples` random keys that have an expiry and removes
+    /// the ones past it. `random` picks a number below its argument.
+    /// Returns how many keys were checked and how many were removed.
+    pub fn expire_sample(
+        &mut self,
+        samples: usize,
+        mut random: impl FnMut(usize) -> usize,
+    ) -> (usize, usize) {
+        let now = now_ms();
+        let volatile = self.volatile.keys.len();
+        let checked = samples.min(volatile);
+        let mut expired = 0;
+        for i in 0..checked {
+            // With few volatile keys every one of them is checked in turn.
+            let pos = if volatile <= samples {
+                i - expired
+            } else {
+                random(self.volatile.keys.len())
+            };
+            let key = self.volatile.keys[pos].clone();
+            if matches!(self.data.get(&key).and_then(|u| u.expireat), Some(at) if at <= now) {
+                self.expire(&key);
+                expired += 1;
+            }
+        }
+        (checked, expired)
+    }
+
+    pub fn set_string(&mut self, key: Bytes, value: Bytes) {
+        self.insert(key, Unit::new(Value::String(value), None));
+    }
+
+    pub fn set_string_px(&mut self, key: Bytes, value: Bytes, time: u64) {
+        let total_time = now_ms().saturating_add(time);
+        self.insert(key, Unit::new(Value::String(value), Some(total_time)));
+    }
+
+    pub fn set_string_ex(&mut self, key: Bytes, value: Bytes, time: u64) {
+        let total_time = now_ms().saturating_add(time.saturating_mul(1000));
+        self.insert(key, Unit::new(Value::String(value), Some(total_time)));
+    }
+
+    pub fn get_string(&mut self, key: &[u8]) -> Result<Bytes, StorageError> {
+        match self.live(key) {
+            Some(u) => match &u.value {
+                Value::String(v) => Ok(v.clone()),
+                _ => Err(StorageError::BadType),
             },
-            _ => Err(StorageError::NotFound),
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> bool {
+        self.live(key).is_some() && self.remove(key).is_some()
+    }
+
+    /// The expiry of `key` as unix milliseconds, `None` if it has none.
+    pub fn expire_time(&mut self, key: &[u8]) -> Result<Option<u64>, StorageError> {
+        match self.live(key) {
+            Some(u) => Ok(u.expireat),
+            None => Err(StorageError::NotFound),
         }
     }
 
-    pub fn delete(&mut self, keys: Vec<String>) -> usize {
-        let mut len = 0;
-        for key in keys {
-            match self.0.remove(&key) {
-                Some(_) => len += 1,
-                None => (),
+    /// Sets or, with `None`, clears the expiry of an existing key.
+    pub fn set_expire_time(&mut self, key: &[u8], at: Option<u64>) -> Result<(), StorageError> {
+        let unit = self.live_mut(key).ok_or(StorageError::NotFound)?;
+        unit.expireat = at;
+        match at {
+            Some(_) => {
+                let key = Bytes::copy_from_slice(key);
+                self.volatile.insert(key);
             }
+            None => self.volatile.remove(key),
         }
-        len
+        Ok(())
     }
 
+    /// Pushes `items` one at a time to the `end` of the list at `key`, so
+    /// pushing to the head leaves them in reverse order. A missing list is
+    /// created unless `existing_only`, in which case nothing is pushed and
+    /// the length is 0.
     pub fn set_array(
         &mut self,
-        key: String,
-        arr: Vec<String>,
-        cmd: &str,
+        key: Bytes,
+        items: Vec<Bytes>,
+        end: ListEnd,
+        existing_only: bool,
     ) -> Result<usize, StorageError> {
-        match self.get_array(&key, [0, 0].to_vec()) {
-            Ok(_) => match self.0.get_mut(&key) {
-                None => Err(StorageError::NotFound),
-                Some(v) => match &mut v.value {
-                    Value::Vector(vec) => {
-                        if cmd == "rpush" {
-                            vec.extend(arr)
-                        } else {
-                            vec.splice(0..0, arr);
+        match self.live_sized(&key) {
+            Some((u, used)) => match &mut u.value {
+                Value::Vector(list) => {
+                    *used += items.iter().map(item_size).sum::<usize>();
+                    match end {
+                        ListEnd::Head => {
+                            for item in items {
+                                list.push_front(item);
+                            }
                         }
-                        Ok(vec.len())
+                        ListEnd::Tail => list.extend(items),
                     }
-                    _ => Err(StorageError::BadType),
-                },
+                    Ok(list.len())
+                }
+                _ => Err(StorageError::BadType),
             },
-            Err(_) => {
-                let len = arr.len();
-                self.0.insert(
-                    key,
-                    Unit {
-                        expireat: None,
-                        value: Value::Vector(arr),
-                    },
-                );
+            None if existing_only => Ok(0),
+            None => {
+                let list: VecDeque<Bytes> = match end {
+                    ListEnd::Head => items.into_iter().rev().collect(),
+                    ListEnd::Tail => items.into(),
+                };
+                let len = list.len();
+                self.insert(key, Unit::new(Value::Vector(list), None));
                 Ok(len)
             }
         }
     }
 
-    pub fn get_array(&mut self, key: &str, bound: Vec<usize>) -> Result<Vec<String>, StorageError> {
-        match self.0.get(key) {
+    /// The elements from `start` to `stop` inclusive. Negative indexes
+    /// count from the end, and the range is clamped to the list.
+    pub fn get_array(
+        &mut self,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Bytes>, StorageError> {
+        match self.live(key) {
             Some(s) => match &s.value {
                 Value::Vector(v) => {
-                    if bound[1] < bound[0] {
-                        return Err(StorageError::BadCommand);
-                    }
-                    let length = bound[1] - bound[0];
-                    if length > v.len() {
-                        return Err(StorageError::BadCommand);
+                    let len = v.len() as i64;
+                    let start = if start < 0 {
+                        (len + start).max(0)
+                    } else {
+                        start
+                    };
+                    let stop = if stop < 0 {
+                        len + stop
+                    } else {
+                        stop.min(len - 1)
+                    };
+                    if start > stop {
+                        return Ok(vec![]);
                     }
-                    Ok(v[bound[0]..bound[1]].to_vec())
+                    Ok(v.range(start as usize..=stop as usize).cloned().collect())
                 }
                 _ => Err(StorageError::BadType),
             },
@@ -189,8 +646,8 @@ impl Storage {
         }
     }
 
-    pub fn get_array_len(&mut self, key: &str) -> Result<usize, StorageError> {
-        match self.0.get(key) {
+    pub fn get_array_len(&mut self, key: &[u8]) -> Result<usize, StorageError> {
+        match self.live(key) {
             Some(s) => match &s.value {
                 Value::Vector(v) => Ok(v.len()),
                 _ => Err(StorageError::BadType),
@@ -199,90 +656,94 @@ impl Storage {
         }
     }
 
-    pub fn pop_array(&mut self, cmd: Vec<String>) -> Result<PopReply, StorageError> {
-        let key = cmd[1].as_str();
-        match self.0.get_mut(key) {
-            Some(u) => match &mut u.value {
-                Value::Vector(v) => {
-                    if cmd.len() == 2 {
-                        return Ok(PopReply::String(v.pop().unwrap()));
-                    }
-                    match cmd[2].parse::<u64>() {
-                        Ok(mut n) => {
-                            let mut final_vec: Vec<String> = vec![];
-                            if n > v.len() as u64 {
-                                n = v.len() as u64;
-                            }
-                            for _ in 0..n {
-                                final_vec.push(v.pop().unwrap())
-                            }
-                            Ok(PopReply::Vector(final_vec))
-                        }
-                        Err(_) => Err(StorageError::BadCommand),
-                    }
-                }
-                _ => Err(StorageError::BadType),
-            },
-            _ => Err(StorageError::NotFound),
+    /// Pops one element from the `end` of a list, or with a `count` up to
+    /// that many as an array. A list left empty is deleted.
+    pub fn pop_array(
+        &mut self,
+        key: &[u8],
+        end: ListEnd,
+        count: Option<usize>,
+    ) -> Result<PopReply, StorageError> {
+        let (u, used) = self.live_sized(key).ok_or(StorageError::NotFound)?;
+        let list = match &mut u.value {
+            Value::Vector(v) => v,
+            _ => return Err(StorageError::BadType),
+        };
+        let n = count.unwrap_or(1).min(list.len());
+        let popped: Vec<Bytes> = (0..n)
+            .filter_map(|_| match end {
+                ListEnd::Head => list.pop_front(),
+                ListEnd::Tail => list.pop_back(),
+            })
+            .collect();
+        *used -= popped.iter().map(item_size).sum::<usize>();
+        if list.is_empty() {
+            self.remove(key);
+        }
+        match count {
+            Some(_) => Ok(PopReply::Vector(popped)),
+            None => popped
+                .into_iter()
+                .next()
+                .map(PopReply::String)
+                .ok_or(StorageError::NotFound),
         }
     }
 
+    /// Removes elements equal to `element`: the first `count` from the head
+    /// when it is positive, the last `-count` when negative, all when 0. A
+    /// list left empty is deleted.
     pub fn remove_array(
         &mut self,
-        key: &str,
-        mut count: i64,
-        element: String,
+        key: &[u8],
+        count: i64,
+        element: Bytes,
     ) -> Result<i64, StorageError> {
-        match self.0.get_mut(key) {
-            Some(u) => match &mut u.value {
-                Value::Vector(v) => {
-                    let mut idxs: Vec<usize> = vec![];
-                    if v.is_empty() {
-                        return Ok(0);
-                    }
-                    if count < 0 {
-                        count = -count;
-                        let mut idx = v.len().checked_sub(1);
-                        while let Some(i) = idx {
-                            if idxs.len() as i64 != count {
-                                if v[i] == element {
-                                    idxs.push(i);
-                                }
-                            } else {
-                                break;
-                            }
-                            idx = i.checked_sub(1)
-                        }
-                    } else {
-                        for (i, item) in v.iter().enumerate() {
-                            if idxs.len() as i64 != count {
-                                if item == &element {
-                                    idxs.push(i);
-                                }
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                    for i in &idxs {
-                        v.remove(*i);
-                    }
-                    Ok(idxs.len() as i64)
-                }
-                _ => Err(StorageError::BadCommand),
-            },
-            _ => Err(StorageError::NotFound),
+        let (u, used) = self.live_sized(key).ok_or(StorageError::NotFound)?;
+        let list = match &mut u.value {
+            Value::Vector(v) => v,
+            _ => return Err(StorageError::BadType),
+        };
+        let limit = match count {
+            0 => usize::MAX,
+            n => usize::try_from(n.unsigned_abs()).unwrap_or(usize::MAX),
+        };
+        // Counting from the tail means passing over the matches before the
+        // last `limit` ones.
+        let skip = if count < 0 {
+            let matches = list.iter().filter(|item| **item == element).count();
+            matches.saturating_sub(limit)
+        } else {
+            0
+        };
+        let mut seen = 0;
+        let mut removed = 0;
+        list.retain(|item| {
+            if *item != element {
+                return true;
+            }
+            seen += 1;
+            if seen <= skip || removed == limit {
+                return true;
+            }
+            removed += 1;
+            false
+        });
+        *used -= removed * item_size(&element);
+        if list.is_empty() {
+            self.remove(key);
         }
+        Ok(removed as i64)
     }
 
-    pub fn array_get(&mut self, key: &str, mut index: i64) -> Result<String, StorageError> {
-        match self.0.get(key) {
+    pub fn array_get(&mut self, key: &[u8], mut index: i64) -> Result<Bytes, StorageError> {
+        match self.live(key) {
             Some(u) => match &u.value {
                 Value::Vector(v) => {
                     if index < 0 {
-                        index = v.len() as i64 - -index;
+                        index += v.len() as i64;
                     }
-                    if v.is_empty() || index >= v.len() as i64 || index < 0 {
+                    if index < 0 || index >= v.len() as i64 {
                         return Err(StorageError::NotFound);
                     }
                     Ok(v[index as usize].to_owned())
@@ -295,19 +756,21 @@ impl Storage {
 
     pub fn array_set(
         &mut self,
-        key: &str,
+        key: &[u8],
         mut index: i64,
-        element: String,
+        element: Bytes,
     ) -> Result<(), StorageError> {
-        match self.0.get_mut(key) {
-            Some(u) => match &mut u.value {
+        match self.live_sized(key) {
+            Some((u, used)) => match &mut u.value {
                 Value::Vector(v) => {
                     if index < 0 {
-                        index = v.len() as i64 - -index
+                        index += v.len() as i64;
                     }
-                    if index >= v.len() as i64 {
+                    if index < 0 || index >= v.len() as i64 {
                         return Err(StorageError::OutOfRange);
                     }
+                    *used += item_size(&element);
+                    *used -= item_size(&v[index as usize]);
                     v[index as usize] = element;
                     Ok(())
                 }
@@ -317,107 +780,115 @@ impl Storage {
         }
     }
 
-    pub fn queue_add(&mut self, cmd: Vec<String>) -> Result<(), StorageError> {
+    pub fn queue_add(&mut self, cmd: Vec<Bytes>) -> Result<(), StorageError> {
         if cmd.len() < 3 {
             return Err(StorageError::BadCommand);
         }
         let key = &cmd[1];
-        match self.0.get_mut(key) {
-            Some(u) => match &mut u.value {
+        match self.live_sized(key) {
+            Some((u, used)) => match &mut u.value {
                 Value::Queue(q) => {
-                    let items: Vec<_> = cmd[2..].to_vec().into();
+                    let items: Vec<_> = cmd[2..].to_vec();
+                    *used += items.iter().map(item_size).sum::<usize>();
                     q.append(items);
-                    return Ok(());
+                    Ok(())
                 }
                 _ => Err(StorageError::BadType),
             },
             _ => {
                 let mut new_queue: Queue = Queue::new();
-                let items: Vec<_> = cmd[2..].to_owned().into();
+                let items: Vec<_> = cmd[2..].to_owned();
                 new_queue.append(items);
-                self.0.insert(
-                    cmd[1].to_owned(),
-                    Unit {
-                        expireat: None,
-                        value: Value::Queue(new_queue),
-                    },
-                );
-                return Ok(());
+                self.insert(cmd[1].to_owned(), Unit::new(Value::Queue(new_queue), None));
+                Ok(())
             }
         }
     }
 
-    pub fn dequeue(&mut self, cmd: Vec<String>) -> Result<String, StorageError> {
+    pub fn dequeue(&mut self, cmd: Vec<Bytes>) -> Result<Bytes, StorageError> {
         if cmd.len() < 2 {
             return Err(StorageError::BadCommand);
         }
-        match self.0.get_mut(&cmd[1]) {
-            Some(u) => match &mut u.value {
-                Value::Queue(q) => {
-                    if q.size() == 0 {
-                        self.0.remove(&cmd[1]);
-                        return Err(StorageError::OutOfRange);
+        match self.live_sized(&cmd[1]) {
+            Some((u, used)) => match &mut u.value {
+                Value::Queue(q) => match q.dequeue() {
+                    Some(item) => {
+                        *used -= item_size(&item);
+                        // Like an emptied list, an emptied queue is deleted.
+                        if q.size() == 0 {
+                            self.remove(&cmd[1]);
+                        }
+                        Ok(item)
                     }
-                    return Ok(q.dequeue().unwrap());
-                }
+                    None => {
+                        self.remove(&cmd[1]);
+                        Err(StorageError::OutOfRange)
+                    }
+                },
                 _ => Err(StorageError::BadType),
             },
             None => Err(StorageError::NotFound),
         }
     }
 
-    pub fn qlen(&mut self, cmd: Vec<String>) -> Result<usize, StorageError> {
+    pub fn qlen(&mut self, cmd: Vec<Bytes>) -> Result<usize, StorageError> {
         if cmd.len() < 2 {
             return Err(StorageError::BadCommand);
         }
-        match self.0.get(&cmd[1]) {
+        match self.live(&cmd[1]) {
             Some(u) => match &u.value {
-                Value::Queue(q) => Ok(q.clone().size()),
+                Value::Queue(q) => Ok(q.size()),
                 _ => Err(StorageError::BadType),
             },
             None => Err(StorageError::NotFound),
         }
     }
 
-    pub fn hash_set(&mut self, cmd: Vec<String>) -> Result<usize, StorageError> {
-        if cmd.len() % 2 != 0 {
+    /// Sets each field/value pair and returns how many fields are new.
+    pub fn hash_set(&mut self, cmd: Vec<Bytes>) -> Result<usize, StorageError> {
+        if cmd.len() % 2 == 1 {
             return Err(StorageError::BadCommand);
         }
         let key = &cmd[1];
-        match self.0.get_mut(key) {
-            Some(u) => match &mut u.value {
+        match self.live_sized(key) {
+            Some((u, used)) => match &mut u.value {
                 Value::Hash(map) => {
-                    let mut i = 0usize;
+                    let mut added = 0usize;
                     for item in cmd.chunks(2).skip(1) {
-                        i += 1;
-                        map.insert(
-                            item[0].to_owned(),
-                            encode_resp_bulk_string(item[1].to_owned()),
-                        );
+                        *used += item_size(&item[1]);
+                        match map.insert(item[0].to_owned(), item[1].clone()) {
+                            Some(old) => *used -= item_size(&old),
+                            None => {
+                                added += 1;
+                                *used += item_size(&item[0]) + HASH_FIELD_OVERHEAD;
+                            }
+                        }
                     }
-                    Ok(i)
+                    Ok(added)
                 }
                 _ => Err(StorageError::BadType),
             },
             _ => {
-                let mut i = 0usize;
-                let mut map: HashMap<String, Vec<u8>> = HashMap::new();
+                let mut map: HashMap<Bytes, Bytes> = HashMap::new();
                 for item in cmd.chunks(2).skip(1) {
-                    i += 1;
-                    map.insert(
-                        item[0].to_owned(),
-                        encode_resp_bulk_string(item[1].to_owned()),
-                    );
+                    map.insert(item[0].to_owned(), item[1].clone());
                 }
-                self.0.insert(
-                    key.to_owned(),
-                    Unit {
-                        expireat: None,
-                        value: Value::Hash(map),
-                    },
-                );
-                Ok(i)
+                // A field repeated in one command only counts once.
+                let added = map.len();
+                self.insert(key.to_owned(), Unit::new(Value::Hash(map), None));
+                Ok(added)
             }
         }
     }
+
+    /// Returns every field of a hash with its value.
+    pub fn hash_get_all(&mut self, key: &[u8]) -> Result<HashMap<Bytes, Bytes>, StorageError> {
+        match self.live(key) {
+            Some(u) => match &u.value {
+                Value::Hash(map) => Ok(map.clone()),
+                _ => Err(StorageError::BadType),
+            },
+            None => Err(StorageError::NotFound),
+        }
+    }
 }
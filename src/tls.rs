@@ -0,0 +1,79 @@
+use std::{fs::File, io::BufReader, sync::Arc};
+
+use tokio_rustls::{
+    rustls::{
+        server::{
+            AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+        },
+        Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::config::{ServerConfig as AppledoreConfig, TlsAuthClients};
+
+/// Builds the acceptor for the TLS listener from the `tls-*` settings.
+pub fn load_acceptor(config: &AppledoreConfig) -> Result<TlsAcceptor, String> {
+    let cert_file = config
+        .tls_cert_file
+        .as_deref()
+        .ok_or("tls-cert-file is not set")?;
+    let key_file = config
+        .tls_key_file
+        .as_deref()
+        .ok_or("tls-key-file is not set")?;
+    let certs = load_certs(cert_file)?;
+    let key = load_key(key_file)?;
+
+    let verifier = match (config.tls_auth_clients, &config.tls_ca_cert_file) {
+        (TlsAuthClients::No, _) => NoClientAuth::boxed(),
+        (_, None) => {
+            return Err("tls-ca-cert-file is required unless tls-auth-clients is 'no'".to_string())
+        }
+        (auth, Some(ca_file)) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_file)? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| format!("{}: {}", ca_file, e))?;
+            }
+            if auth == TlsAuthClients::Optional {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            }
+        }
+    };
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("{}: {}", key_file, e))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let certs =
+        rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &str) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path, e))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("{}: no private key found", path))
+}
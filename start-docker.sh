@@ -7,4 +7,4 @@ if [ "$1" = "stop" ]; then
 fi
 
 docker build -t appledore .
-docker run -d -p 6379:6379 --name appledore appledore
+docker run -d -p 6379:6379 -v appledore-data:/data --name appledore appledore
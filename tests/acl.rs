@@ -0,0 +1,120 @@
+//! AUTH and ACL users over the wire.
+
+mod common;
+
+use common::{Reply, Server};
+
+const NOAUTH: &str = "NOAUTH Authentication required.";
+const WRONGPASS: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+
+#[test]
+fn auth_failures() {
+    let server = Server::start();
+    let mut admin = server.connect();
+    assert_eq!(
+        admin.cmd(&["AUTH", "pw"]),
+        Reply::error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?")
+    );
+    assert_eq!(
+        admin.cmd(&["ACL", "SETUSER", "u", "on", ">pw", "+@all", "~*"]),
+        Reply::ok()
+    );
+
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["AUTH", "u", "wrong"]), Reply::error(WRONGPASS));
+    assert_eq!(c.cmd(&["AUTH", "nobody", "pw"]), Reply::error(WRONGPASS));
+    assert_eq!(
+        c.cmd(&["AUTH", "a", "b", "c"]),
+        Reply::error("ERR syntax error")
+    );
+    // A failed AUTH leaves the connection logged in as it was.
+    assert_eq!(c.cmd(&["ACL", "WHOAMI"]), Reply::bulk("default"));
+    assert_eq!(c.cmd(&["AUTH", "u", "pw"]), Reply::ok());
+    assert_eq!(c.cmd(&["ACL", "WHOAMI"]), Reply::bulk("u"));
+}
+
+#[test]
+fn denied_commands_and_keys() {
+    let server = Server::start();
+    let mut admin = server.connect();
+    admin.cmd(&["SET", "secret", "x"]);
+    admin.cmd(&["SET", "app:1", "y"]);
+    assert_eq!(
+        admin.cmd(&["ACL", "SETUSER", "reader", "on", "nopass", "+@read", "-keys", "~app:*"]),
+        Reply::ok()
+    );
+
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["AUTH", "reader", "any"]), Reply::ok());
+    assert_eq!(c.cmd(&["GET", "app:1"]), Reply::bulk("y"));
+    assert_eq!(
+        c.cmd(&["GET", "secret"]),
+        Reply::error("NOPERM No permissions to access a key")
+    );
+    assert_eq!(
+        c.cmd(&["MGET", "app:1", "secret"]),
+        Reply::error("NOPERM No permissions to access a key")
+    );
+    assert_eq!(
+        c.cmd(&["SET", "app:1", "z"]),
+        Reply::error("NOPERM User reader has no permissions to run the 'set' command")
+    );
+    assert_eq!(
+        c.cmd(&["KEYS", "*"]),
+        Reply::error("NOPERM User reader has no permissions to run the 'keys' command")
+    );
+    assert_eq!(admin.cmd(&["GET", "app:1"]), Reply::bulk("y"));
+}
+
+#[test]
+fn disabled_and_deleted_users_are_logged_out() {
+    let server = Server::start();
+    let mut admin = server.connect();
+    admin.cmd(&["ACL", "SETUSER", "u", "on", ">pw", "+@all", "~*"]);
+    admin.cmd(&["ACL", "SETUSER", "v", "on", ">pw", "+@all", "~*"]);
+
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["AUTH", "u", "pw"]), Reply::ok());
+    assert_eq!(c.cmd(&["PING"]), Reply::Status("PONG".to_string()));
+    assert_eq!(admin.cmd(&["ACL", "SETUSER", "u", "off"]), Reply::ok());
+    assert_eq!(c.cmd(&["PING"]), Reply::error(NOAUTH));
+    assert_eq!(c.cmd(&["GET", "k"]), Reply::error(NOAUTH));
+    assert_eq!(c.cmd(&["AUTH", "u", "pw"]), Reply::error(WRONGPASS));
+    // Turning the user back on doesn't log old connections back in.
+    admin.cmd(&["ACL", "SETUSER", "u", "on"]);
+    assert_eq!(c.cmd(&["PING"]), Reply::error(NOAUTH));
+    assert_eq!(c.cmd(&["AUTH", "u", "pw"]), Reply::ok());
+    assert_eq!(c.cmd(&["PING"]), Reply::Status("PONG".to_string()));
+
+    let mut d = server.connect();
+    assert_eq!(d.cmd(&["AUTH", "v", "pw"]), Reply::ok());
+    assert_eq!(admin.cmd(&["ACL", "DELUSER", "v"]), Reply::Integer(1));
+    assert_eq!(d.cmd(&["PING"]), Reply::error(NOAUTH));
+}
+
+#[test]
+fn getuser_reports_the_rules() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&[
+        "ACL", "SETUSER", "u", "on", "nopass", "+@read", "-get", "~app:*",
+    ]);
+    assert_eq!(
+        c.cmd(&["ACL", "GETUSER", "u"]),
+        Reply::Array(Some(vec![
+            Reply::bulk("flags"),
+            Reply::bulks(&["on", "nopass"]),
+            Reply::bulk("passwords"),
+            Reply::Array(Some(vec![])),
+            Reply::bulk("commands"),
+            Reply::bulk("-@all +@read -get"),
+            Reply::bulk("keys"),
+            Reply::bulk("~app:*"),
+        ]))
+    );
+    assert_eq!(c.cmd(&["ACL", "GETUSER", "nobody"]), Reply::Bulk(None));
+    assert_eq!(
+        c.cmd(&["ACL", "DELUSER", "default"]),
+        Reply::error("ERR The 'default' user cannot be removed")
+    );
+}
@@ -0,0 +1,221 @@
+//! Starting a server and talking RESP to it from tests.
+
+#![allow(dead_code)]
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// A server running on a free port, killed when dropped.
+pub struct Server {
+    child: Child,
+    pub port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Server {
+    /// Starts appledore without persistence and waits until it accepts
+    /// connections.
+    pub fn start() -> Self {
+        Server::start_with(&[])
+    }
+
+    /// Like `start`, with extra `--name value` flags.
+    pub fn start_with(flags: &[&str]) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_appledore"))
+            .args(["--bind", "127.0.0.1", "--port", &port.to_string()])
+            .args(["--save", ""])
+            .args(flags)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start appledore");
+        let server = Server { child, port };
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return server;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("appledore did not start listening on port {}", port);
+    }
+
+    pub fn connect(&self) -> Connection {
+        Connection::open(&format!("127.0.0.1:{}", self.port))
+    }
+}
+
+/// A reply, in RESP2 or RESP3.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+    /// RESP3's null.
+    Null,
+    Map(Vec<(Reply, Reply)>),
+    Set(Vec<Reply>),
+    Push(Vec<Reply>),
+    Boolean(bool),
+    /// Doubles and big numbers, as sent.
+    Double(String),
+    BigNumber(String),
+    Verbatim(String, Vec<u8>),
+}
+
+impl Reply {
+    pub fn bulk(s: &str) -> Reply {
+        Reply::Bulk(Some(s.as_bytes().to_vec()))
+    }
+
+    pub fn bulks(items: &[&str]) -> Reply {
+        Reply::Array(Some(items.iter().map(|s| Reply::bulk(s)).collect()))
+    }
+
+    pub fn error(msg: &str) -> Reply {
+        Reply::Error(msg.to_string())
+    }
+
+    pub fn ok() -> Reply {
+        Reply::Status("OK".to_string())
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Status(s) => write!(f, "+{}", s),
+            Reply::Error(e) => write!(f, "-{}", e),
+            Reply::Integer(n) => write!(f, "(integer) {}", n),
+            Reply::Bulk(Some(b)) => write!(f, "{:?}", String::from_utf8_lossy(b)),
+            Reply::Bulk(None) => f.write_str("(nil)"),
+            Reply::Array(None) => f.write_str("(nil array)"),
+            Reply::Array(Some(items)) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Reply::Null => f.write_str("(null)"),
+            Reply::Map(entries) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", k, v)?;
+                }
+                f.write_str("}")
+            }
+            Reply::Set(items) => write!(f, "set {}", Reply::Array(Some(items.clone()))),
+            Reply::Push(items) => write!(f, "push {}", Reply::Array(Some(items.clone()))),
+            Reply::Boolean(b) => write!(f, "({})", b),
+            Reply::Double(d) => write!(f, "(double) {}", d),
+            Reply::BigNumber(n) => write!(f, "(big number) {}", n),
+            Reply::Verbatim(format, b) => {
+                write!(f, "{}:{:?}", format, String::from_utf8_lossy(b))
+            }
+        }
+    }
+}
+
+pub struct Connection {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    /// Connects to `addr`, `host:port`.
+    pub fn open(addr: &str) -> Self {
+        let stream = TcpStream::connect(addr)
+            .unwrap_or_else(|e| panic!("could not connect to {}: {}", addr, e));
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Connection {
+            writer: stream.try_clone().unwrap(),
+            reader: BufReader::new(stream),
+        }
+    }
+
+    pub fn cmd<S: AsRef<[u8]>>(&mut self, args: &[S]) -> Reply {
+        let mut out = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            let arg = arg.as_ref();
+            out.extend(format!("${}\r\n", arg.len()).into_bytes());
+            out.extend(arg);
+            out.extend(b"\r\n");
+        }
+        self.writer.write_all(&out).unwrap();
+        self.read_reply()
+    }
+
+    fn read_replies(&mut self, n: i64) -> Vec<Reply> {
+        (0..n).map(|_| self.read_reply()).collect()
+    }
+
+    /// Reads the next frame without sending anything, e.g. the reply that
+    /// follows a push frame.
+    pub fn read_reply(&mut self) -> Reply {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let line = line.trim_end_matches("\r\n");
+        let (kind, rest) = line.split_at(1);
+        let len = || rest.parse::<i64>().unwrap();
+        match kind {
+            "+" => Reply::Status(rest.to_string()),
+            "-" => Reply::Error(rest.to_string()),
+            ":" => Reply::Integer(len()),
+            "_" => Reply::Null,
+            "$" if len() < 0 => Reply::Bulk(None),
+            "$" => {
+                let mut body = vec![0; len() as usize + 2];
+                self.reader.read_exact(&mut body).unwrap();
+                body.truncate(len() as usize);
+                Reply::Bulk(Some(body))
+            }
+            "*" if len() < 0 => Reply::Array(None),
+            "*" => Reply::Array(Some(self.read_replies(len()))),
+            "%" => Reply::Map(
+                (0..len())
+                    .map(|_| (self.read_reply(), self.read_reply()))
+                    .collect(),
+            ),
+            "~" => Reply::Set(self.read_replies(len())),
+            ">" => Reply::Push(self.read_replies(len())),
+            "#" => Reply::Boolean(rest == "t"),
+            "," => Reply::Double(rest.to_string()),
+            "(" => Reply::BigNumber(rest.to_string()),
+            "=" => {
+                let mut body = vec![0; len() as usize + 2];
+                self.reader.read_exact(&mut body).unwrap();
+                body.truncate(len() as usize);
+                let data = body.split_off(4);
+                body.truncate(3);
+                Reply::Verbatim(String::from_utf8(body).unwrap(), data)
+            }
+            _ => panic!("unexpected reply line {:?}", line),
+        }
+    }
+}
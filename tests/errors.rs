@@ -0,0 +1,36 @@
+//! Error replies.
+
+mod common;
+
+use common::{Reply, Server};
+
+#[test]
+fn unknown_commands_quote_their_arguments() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(
+        c.cmd(&["NOSUCH", "a", "b"]),
+        Reply::error("ERR unknown command 'NOSUCH', with args beginning with: 'a' 'b' ")
+    );
+    assert_eq!(
+        c.cmd(&["NOSUCH"]),
+        Reply::error("ERR unknown command 'NOSUCH', with args beginning with: ")
+    );
+}
+
+#[test]
+fn client_input_cannot_inject_replies_into_errors() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(
+        c.cmd(&["NOSUCH\r\n+OK", "x\r\n+OK\r\n"]),
+        Reply::error("ERR unknown command 'NOSUCH  +OK', with args beginning with: 'x  +OK  ' ")
+    );
+    // The next reply is PING's, not an injected one.
+    assert_eq!(c.cmd(&["PING"]), Reply::Status("PONG".to_string()));
+    assert_eq!(
+        c.cmd(&["CONFIG", "x\ny"]),
+        Reply::error("ERR unknown subcommand 'x y'. Try CONFIG HELP.")
+    );
+    assert_eq!(c.cmd(&["PING"]), Reply::Status("PONG".to_string()));
+}
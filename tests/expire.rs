@@ -0,0 +1,176 @@
+//! Key expiry: EXPIRE and its options, TTL and PERSIST.
+
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use common::{Reply, Server};
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn integer(reply: Reply) -> i64 {
+    match reply {
+        Reply::Integer(n) => n,
+        other => panic!("expected an integer, got {}", other),
+    }
+}
+
+#[test]
+fn ttl_of_missing_and_persistent_keys() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["TTL", "missing"]), Reply::Integer(-2));
+    assert_eq!(c.cmd(&["PTTL", "missing"]), Reply::Integer(-2));
+    assert_eq!(c.cmd(&["EXPIRETIME", "missing"]), Reply::Integer(-2));
+    c.cmd(&["SET", "k", "v"]);
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(-1));
+    assert_eq!(c.cmd(&["PEXPIRETIME", "k"]), Reply::Integer(-1));
+    assert_eq!(c.cmd(&["EXPIRE", "missing", "100"]), Reply::Integer(0));
+}
+
+#[test]
+fn expire_sets_a_ttl_on_every_type() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["SET", "s", "v"]);
+    c.cmd(&["RPUSH", "l", "a"]);
+    c.cmd(&["HSET", "h", "f", "v"]);
+    c.cmd(&["QADD", "q", "a"]);
+    for key in ["s", "l", "h", "q"] {
+        assert_eq!(c.cmd(&["EXPIRE", key, "100"]), Reply::Integer(1));
+        assert_eq!(c.cmd(&["TTL", key]), Reply::Integer(100));
+        let pttl = integer(c.cmd(&["PTTL", key]));
+        assert!(pttl > 99_000 && pttl <= 100_000, "PTTL {}", pttl);
+        let at = integer(c.cmd(&["EXPIRETIME", key]));
+        assert!((at - (now_secs() + 100)).abs() <= 1, "EXPIRETIME {}", at);
+    }
+
+    let at = (now_secs() + 1000).to_string();
+    assert_eq!(c.cmd(&["EXPIREAT", "s", &at]), Reply::Integer(1));
+    assert_eq!(
+        c.cmd(&["EXPIRETIME", "s"]),
+        Reply::Integer(at.parse().unwrap())
+    );
+    assert_eq!(c.cmd(&["PEXPIRE", "s", "5000"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["TTL", "s"]), Reply::Integer(5));
+}
+
+#[test]
+fn keys_disappear_once_they_expire() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["SET", "s", "v"]);
+    c.cmd(&["RPUSH", "l", "a", "b"]);
+    c.cmd(&["HSET", "h", "f", "v"]);
+    c.cmd(&["QADD", "q", "a"]);
+    for key in ["s", "l", "h", "q"] {
+        assert_eq!(c.cmd(&["PEXPIRE", key, "100"]), Reply::Integer(1));
+    }
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(c.cmd(&["GET", "s"]), Reply::Bulk(None));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::Array(Some(vec![]))
+    );
+    assert_eq!(c.cmd(&["HGETALL", "h"]), Reply::Array(Some(vec![])));
+    assert_eq!(c.cmd(&["QREAD", "q"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["EXISTS", "s", "l", "h", "q"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["TTL", "l"]), Reply::Integer(-2));
+}
+
+#[test]
+fn a_time_in_the_past_deletes_the_key() {
+    let server = Server::start();
+    let mut c = server.connect();
+    for (command, time) in [
+        ("EXPIRE", "-1"),
+        ("PEXPIRE", "0"),
+        ("EXPIREAT", "1"),
+        ("PEXPIREAT", "-100"),
+    ] {
+        c.cmd(&["RPUSH", "k", "a"]);
+        assert_eq!(
+            c.cmd(&[command, "k", time]),
+            Reply::Integer(1),
+            "{}",
+            command
+        );
+        assert_eq!(c.cmd(&["EXISTS", "k"]), Reply::Integer(0), "{}", command);
+    }
+    assert_eq!(c.cmd(&["EXPIRE", "k", "-1"]), Reply::Integer(0));
+    assert_eq!(
+        c.cmd(&["EXPIRE", "k", "9223372036854775807"]),
+        Reply::error("ERR invalid expire time in 'expire' command")
+    );
+    assert_eq!(
+        c.cmd(&["EXPIRE", "k", "soon"]),
+        Reply::error("ERR value is not an integer or out of range")
+    );
+}
+
+#[test]
+fn nx_xx_gt_lt() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["SET", "k", "v"]);
+
+    // Without a TTL: XX and GT refuse, NX and LT set one.
+    assert_eq!(c.cmd(&["EXPIRE", "k", "100", "XX"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "100", "GT"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(-1));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "100", "LT"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["PERSIST", "k"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "100", "NX"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(100));
+
+    // With a TTL of 100.
+    assert_eq!(c.cmd(&["EXPIRE", "k", "200", "NX"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "50", "GT"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "200", "LT"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(100));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "200", "GT"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(200));
+    assert_eq!(
+        c.cmd(&["EXPIRE", "k", "150", "XX", "LT"]),
+        Reply::Integer(1)
+    );
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(150));
+    assert_eq!(c.cmd(&["EXPIRE", "k", "300", "xx"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(300));
+
+    assert_eq!(
+        c.cmd(&["EXPIRE", "k", "1", "NX", "XX"]),
+        Reply::error("ERR NX and XX, GT or LT options at the same time are not compatible")
+    );
+    assert_eq!(
+        c.cmd(&["EXPIRE", "k", "1", "GT", "LT"]),
+        Reply::error("ERR GT and LT options at the same time are not compatible")
+    );
+    assert_eq!(
+        c.cmd(&["EXPIRE", "k", "1", "SOON"]),
+        Reply::error("ERR Unsupported option SOON")
+    );
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(300));
+}
+
+#[test]
+fn persist_removes_the_ttl() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["PERSIST", "missing"]), Reply::Integer(0));
+    c.cmd(&["RPUSH", "k", "a"]);
+    assert_eq!(c.cmd(&["PERSIST", "k"]), Reply::Integer(0));
+    c.cmd(&["PEXPIRE", "k", "100"]);
+    assert_eq!(c.cmd(&["PERSIST", "k"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["TTL", "k"]), Reply::Integer(-1));
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(c.cmd(&["LRANGE", "k", "0", "-1"]), Reply::bulks(&["a"]));
+}
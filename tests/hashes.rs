@@ -0,0 +1,25 @@
+//! Hash commands.
+
+mod common;
+
+use common::{Reply, Server};
+
+#[test]
+fn hset_counts_new_fields_only() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["HSET", "h", "f", "v"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["HSET", "h", "f", "v"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["HSET", "h", "f", "w", "g", "x"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["HSET", "n", "a", "1", "a", "2"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["HGETALL", "n"]), Reply::bulks(&["a", "2"]));
+    assert_eq!(
+        c.cmd(&["HSET", "h", "f"]),
+        Reply::error("ERR wrong number of arguments for 'hset' command")
+    );
+    c.cmd(&["SET", "s", "x"]);
+    assert_eq!(
+        c.cmd(&["HSET", "s", "f", "v"]),
+        Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
@@ -0,0 +1,149 @@
+//! Commands that touch keys in several shards of the keyspace.
+
+mod common;
+
+use std::thread;
+
+use common::{Reply, Server};
+
+fn keys(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("key:{}", i)).collect()
+}
+
+#[test]
+fn multi_key_commands_span_shards() {
+    let server = Server::start();
+    let mut c = server.connect();
+    // Enough keys that every shard holds some of them.
+    let keys = keys(500);
+    for key in &keys {
+        assert_eq!(c.cmd(&["SET", key, key]), Reply::ok());
+    }
+
+    let mut mget = vec!["MGET".to_string(), "missing".to_string()];
+    mget.extend(keys.iter().cloned());
+    let mut expected = vec![Reply::Bulk(None)];
+    expected.extend(keys.iter().map(|k| Reply::bulk(k)));
+    assert_eq!(c.cmd(&mget), Reply::Array(Some(expected)));
+
+    let mut exists = vec!["EXISTS".to_string()];
+    exists.extend(keys.iter().cloned());
+    exists.extend(keys[..10].iter().cloned());
+    exists.push("missing".to_string());
+    assert_eq!(c.cmd(&exists), Reply::Integer(510));
+
+    let mut del = vec!["DEL".to_string()];
+    del.extend(keys[..250].iter().cloned());
+    del.extend(keys[..10].iter().cloned());
+    assert_eq!(c.cmd(&del), Reply::Integer(250));
+    assert_eq!(c.cmd(&["DBSIZE"]), Reply::Integer(250));
+    assert_eq!(c.cmd(&exists), Reply::Integer(250));
+}
+
+#[test]
+fn rename_moves_values_between_shards() {
+    let server = Server::start();
+    let mut c = server.connect();
+    let keys = keys(100);
+    for key in &keys {
+        c.cmd(&["RPUSH", key, key]);
+    }
+    for key in &keys {
+        let dst = format!("renamed:{}", key);
+        assert_eq!(c.cmd(&["RENAME", key, &dst]), Reply::ok());
+        assert_eq!(c.cmd(&["EXISTS", key]), Reply::Integer(0));
+        assert_eq!(c.cmd(&["LRANGE", &dst, "0", "-1"]), Reply::bulks(&[key]));
+    }
+    assert_eq!(c.cmd(&["DBSIZE"]), Reply::Integer(100));
+
+    c.cmd(&["SET", "a", "1"]);
+    c.cmd(&["SET", "b", "2"]);
+    assert_eq!(c.cmd(&["RENAMENX", "a", "b"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["RENAMENX", "a", "c"]), Reply::Integer(1));
+    assert_eq!(
+        c.cmd(&["MGET", "a", "b", "c"]),
+        Reply::Array(Some(vec![
+            Reply::Bulk(None),
+            Reply::bulk("2"),
+            Reply::bulk("1")
+        ]))
+    );
+    assert_eq!(
+        c.cmd(&["RENAME", "a", "d"]),
+        Reply::error("ERR no such key")
+    );
+}
+
+/// Clients rename keys back and forth while others read both names in
+/// both orders. Every multi-key read must see exactly one of the pair, and
+/// taking the shard locks in argument order would deadlock.
+#[test]
+fn concurrent_multi_key_commands_are_atomic() {
+    const PAIRS: usize = 8;
+    const ROUNDS: usize = 300;
+    let server = Server::start();
+    let mut c = server.connect();
+    let pairs: Vec<(String, String)> = (0..PAIRS)
+        .map(|i| (format!("left:{}", i), format!("right:{}", i)))
+        .collect();
+    for (left, _) in &pairs {
+        c.cmd(&["SET", left, "v"]);
+    }
+
+    thread::scope(|scope| {
+        for (left, right) in &pairs {
+            let (server, left, right) = (&server, left.as_str(), right.as_str());
+            scope.spawn(move || {
+                let mut c = server.connect();
+                for _ in 0..ROUNDS {
+                    assert_eq!(c.cmd(&["RENAME", left, right]), Reply::ok());
+                    assert_eq!(c.cmd(&["RENAME", right, left]), Reply::ok());
+                }
+            });
+            for order in [[left, right], [right, left]] {
+                scope.spawn(move || {
+                    let mut c = server.connect();
+                    for _ in 0..ROUNDS {
+                        assert_eq!(c.cmd(&["EXISTS", order[0], order[1]]), Reply::Integer(1));
+                        match c.cmd(&["MGET", order[0], order[1]]) {
+                            Reply::Array(Some(values)) => assert_eq!(
+                                values.iter().filter(|v| **v == Reply::bulk("v")).count(),
+                                1,
+                                "MGET {} {} saw {:?}",
+                                order[0],
+                                order[1],
+                                values
+                            ),
+                            other => panic!("MGET replied {}", other),
+                        }
+                    }
+                });
+            }
+        }
+        // Deletes of keys in every shard, in both orders, alongside.
+        for reverse in [false, true] {
+            let server = &server;
+            scope.spawn(move || {
+                let mut c = server.connect();
+                let mut keys = keys(64);
+                if reverse {
+                    keys.reverse();
+                }
+                for _ in 0..ROUNDS / 10 {
+                    for key in &keys {
+                        c.cmd(&["SET", key, "x"]);
+                    }
+                    let mut del = vec!["DEL".to_string()];
+                    del.extend(keys.iter().cloned());
+                    c.cmd(&del);
+                }
+            });
+        }
+    });
+
+    let mut c = server.connect();
+    for (left, right) in &pairs {
+        assert_eq!(c.cmd(&["GET", left]), Reply::bulk("v"));
+        assert_eq!(c.cmd(&["EXISTS", right]), Reply::Integer(0));
+    }
+}
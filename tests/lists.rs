@@ -0,0 +1,337 @@
+//! List commands, checked against the replies Redis gives.
+
+mod common;
+
+use common::{Connection, Reply, Server};
+
+fn int(n: i64) -> Reply {
+    Reply::Integer(n)
+}
+
+const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+const NOT_INTEGER: &str = "ERR value is not an integer or out of range";
+const NOT_POSITIVE: &str = "ERR value is out of range, must be positive";
+
+#[test]
+fn lpush_inserts_values_in_reverse_order() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["LPUSH", "l", "a", "b", "c"]), int(3));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["c", "b", "a"])
+    );
+    assert_eq!(c.cmd(&["LPUSH", "l", "d", "e"]), int(5));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["e", "d", "c", "b", "a"])
+    );
+}
+
+#[test]
+fn rpush_appends_whatever_the_case_of_the_command() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["RPUSH", "l", "a", "b"]), int(2));
+    assert_eq!(c.cmd(&["rpush", "l", "c"]), int(3));
+    assert_eq!(c.cmd(&["RpUsH", "l", "d"]), int(4));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["a", "b", "c", "d"])
+    );
+}
+
+#[test]
+fn lpop_pops_from_the_head_and_rpop_from_the_tail() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["RPUSH", "l", "a", "b", "c", "d", "e"]);
+    assert_eq!(c.cmd(&["LPOP", "l"]), Reply::bulk("a"));
+    assert_eq!(c.cmd(&["RPOP", "l"]), Reply::bulk("e"));
+    assert_eq!(c.cmd(&["LPOP", "l", "2"]), Reply::bulks(&["b", "c"]));
+    assert_eq!(c.cmd(&["RPOP", "l", "5"]), Reply::bulks(&["d"]));
+    assert_eq!(c.cmd(&["EXISTS", "l"]), int(0));
+}
+
+#[test]
+fn pop_counts() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["RPUSH", "l", "a", "b"]);
+    assert_eq!(c.cmd(&["LPOP", "l", "0"]), Reply::bulks(&[]));
+    assert_eq!(c.cmd(&["LPOP", "l", "-1"]), Reply::error(NOT_POSITIVE));
+    assert_eq!(c.cmd(&["RPOP", "l", "x"]), Reply::error(NOT_POSITIVE));
+    assert_eq!(
+        c.cmd(&["LPOP", "l", "1", "2"]),
+        Reply::error("ERR wrong number of arguments for 'lpop' command")
+    );
+    assert_eq!(c.cmd(&["LLEN", "l"]), int(2));
+    // The count is checked before the key.
+    c.cmd(&["SET", "s", "v"]);
+    assert_eq!(c.cmd(&["LPOP", "s", "-1"]), Reply::error(NOT_POSITIVE));
+    assert_eq!(c.cmd(&["LPOP", "s", "1"]), Reply::error(WRONGTYPE));
+}
+
+#[test]
+fn popping_a_missing_list_replies_nil() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["LPOP", "missing"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["RPOP", "missing"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["LPOP", "missing", "3"]), Reply::Array(None));
+    assert_eq!(c.cmd(&["RPOP", "missing", "0"]), Reply::Array(None));
+    c.cmd(&["RPUSH", "l", "a"]);
+    assert_eq!(c.cmd(&["LPOP", "l"]), Reply::bulk("a"));
+    assert_eq!(c.cmd(&["LPOP", "l"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["PING"]), Reply::Status("PONG".to_string()));
+}
+
+#[test]
+fn resp3_clients_get_resp3_nulls() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["HELLO", "3"]);
+    assert_eq!(c.cmd(&["LPOP", "missing"]), Reply::Null);
+    assert_eq!(c.cmd(&["RPOP", "missing", "2"]), Reply::Null);
+    assert_eq!(c.cmd(&["LINDEX", "missing", "0"]), Reply::Null);
+}
+
+#[test]
+fn emptied_lists_are_deleted() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["RPUSH", "a", "x"]);
+    c.cmd(&["LPOP", "a"]);
+    c.cmd(&["RPUSH", "b", "x", "y"]);
+    c.cmd(&["RPOP", "b", "2"]);
+    c.cmd(&["RPUSH", "c", "x", "x", "x"]);
+    assert_eq!(c.cmd(&["LREM", "c", "0", "x"]), int(3));
+    for key in ["a", "b", "c"] {
+        assert_eq!(c.cmd(&["EXISTS", key]), int(0), "{} still exists", key);
+        assert_eq!(c.cmd(&["TYPE", key]), Reply::Status("none".to_string()));
+    }
+    assert_eq!(c.cmd(&["DBSIZE"]), int(0));
+    // A deleted list is a missing key, so it can become something else.
+    assert_eq!(c.cmd(&["SET", "a", "v"]), Reply::ok());
+    assert_eq!(c.cmd(&["LPUSHX", "b", "v"]), int(0));
+}
+
+#[test]
+fn pushx_only_pushes_to_existing_lists() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["LPUSHX", "l", "a"]), int(0));
+    assert_eq!(c.cmd(&["RPUSHX", "l", "a"]), int(0));
+    assert_eq!(c.cmd(&["EXISTS", "l"]), int(0));
+    c.cmd(&["RPUSH", "l", "m"]);
+    assert_eq!(c.cmd(&["LPUSHX", "l", "b", "a"]), int(3));
+    assert_eq!(c.cmd(&["RPUSHX", "l", "y", "z"]), int(5));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["a", "b", "m", "y", "z"])
+    );
+    c.cmd(&["SET", "s", "v"]);
+    assert_eq!(c.cmd(&["LPUSHX", "s", "a"]), Reply::error(WRONGTYPE));
+    assert_eq!(c.cmd(&["RPUSHX", "s", "a"]), Reply::error(WRONGTYPE));
+}
+
+#[test]
+fn lrem_counts_from_either_end() {
+    let server = Server::start();
+    let mut c = server.connect();
+    let fill = |c: &mut Connection| {
+        c.cmd(&["DEL", "l"]);
+        c.cmd(&["RPUSH", "l", "x", "a", "x", "b", "x", "c", "x"]);
+    };
+    fill(&mut c);
+    assert_eq!(c.cmd(&["LREM", "l", "2", "x"]), int(2));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["a", "b", "x", "c", "x"])
+    );
+    fill(&mut c);
+    assert_eq!(c.cmd(&["LREM", "l", "-2", "x"]), int(2));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["x", "a", "x", "b", "c"])
+    );
+    fill(&mut c);
+    assert_eq!(c.cmd(&["LREM", "l", "0", "x"]), int(4));
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["a", "b", "c"])
+    );
+    assert_eq!(c.cmd(&["LREM", "l", "1", "nope"]), int(0));
+    assert_eq!(c.cmd(&["LREM", "missing", "1", "x"]), int(0));
+    assert_eq!(c.cmd(&["LREM", "l", "x", "a"]), Reply::error(NOT_INTEGER));
+}
+
+#[test]
+fn lrange_clamps_indexes() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["RPUSH", "l", "a", "b", "c"]);
+    let range = |c: &mut Connection, start: &str, stop: &str| c.cmd(&["LRANGE", "l", start, stop]);
+    assert_eq!(range(&mut c, "0", "-1"), Reply::bulks(&["a", "b", "c"]));
+    assert_eq!(range(&mut c, "-100", "100"), Reply::bulks(&["a", "b", "c"]));
+    assert_eq!(range(&mut c, "1", "1"), Reply::bulks(&["b"]));
+    assert_eq!(range(&mut c, "-2", "-1"), Reply::bulks(&["b", "c"]));
+    assert_eq!(range(&mut c, "2", "1"), Reply::bulks(&[]));
+    assert_eq!(range(&mut c, "5", "10"), Reply::bulks(&[]));
+    assert_eq!(range(&mut c, "0", "-4"), Reply::bulks(&[]));
+    assert_eq!(range(&mut c, "a", "1"), Reply::error(NOT_INTEGER));
+    assert_eq!(c.cmd(&["LRANGE", "missing", "0", "-1"]), Reply::bulks(&[]));
+}
+
+#[test]
+fn lindex_and_lset() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["RPUSH", "l", "a", "b", "c"]);
+    assert_eq!(c.cmd(&["LINDEX", "l", "-1"]), Reply::bulk("c"));
+    assert_eq!(c.cmd(&["LINDEX", "l", "3"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["LINDEX", "l", "-4"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["LINDEX", "l", "x"]), Reply::error(NOT_INTEGER));
+    assert_eq!(c.cmd(&["LINDEX", "missing", "x"]), Reply::Bulk(None));
+    assert_eq!(c.cmd(&["LSET", "l", "-1", "z"]), Reply::ok());
+    assert_eq!(
+        c.cmd(&["LSET", "l", "-4", "z"]),
+        Reply::error("ERR index out of range")
+    );
+    assert_eq!(
+        c.cmd(&["LSET", "l", "3", "z"]),
+        Reply::error("ERR index out of range")
+    );
+    assert_eq!(
+        c.cmd(&["LSET", "missing", "0", "z"]),
+        Reply::error("ERR no such key")
+    );
+    assert_eq!(
+        c.cmd(&["LSET", "missing", "x", "z"]),
+        Reply::error("ERR no such key")
+    );
+    assert_eq!(
+        c.cmd(&["LRANGE", "l", "0", "-1"]),
+        Reply::bulks(&["a", "b", "z"])
+    );
+}
+
+#[test]
+fn list_commands_reject_other_types() {
+    let server = Server::start();
+    let mut c = server.connect();
+    c.cmd(&["SET", "s", "v"]);
+    for args in [
+        &["LPUSH", "s", "a"][..],
+        &["RPUSH", "s", "a"],
+        &["LPOP", "s"],
+        &["RPOP", "s"],
+        &["LLEN", "s"],
+        &["LRANGE", "s", "0", "-1"],
+        &["LINDEX", "s", "0"],
+        &["LSET", "s", "0", "a"],
+        &["LREM", "s", "0", "a"],
+    ] {
+        assert_eq!(c.cmd(args), Reply::error(WRONGTYPE), "{:?}", args);
+    }
+    assert_eq!(c.cmd(&["GET", "s"]), Reply::bulk("v"));
+}
+
+/// A session run as a script, with the reply Redis 7 gives to each
+/// command according to its list implementation (t_list.c). Each step
+/// builds on the state the earlier ones left.
+#[test]
+fn session_matches_redis_replies() {
+    let nil = Reply::Bulk(None);
+    let nil_array = Reply::Array(None);
+    let none = Reply::Status("none".to_string());
+    let steps: Vec<(&[&str], Reply)> = vec![
+        // LPUSH inserts each value at the head in turn.
+        (&["LPUSH", "l", "1", "2", "3"], int(3)),
+        (&["LRANGE", "l", "0", "-1"], Reply::bulks(&["3", "2", "1"])),
+        (&["lpush", "l", ""], int(4)),
+        (&["LINDEX", "l", "0"], Reply::bulk("")),
+        (&["RPUSH", "l", "4", "5"], int(6)),
+        (
+            &["LRANGE", "l", "0", "-1"],
+            Reply::bulks(&["", "3", "2", "1", "4", "5"]),
+        ),
+        // Counted pops.
+        (&["LPOP", "l", "0"], Reply::bulks(&[])),
+        (&["RPOP", "l", "0"], Reply::bulks(&[])),
+        (&["LLEN", "l"], int(6)),
+        (&["LPOP", "l", "1"], Reply::bulks(&[""])),
+        (&["RPOP", "l", "2"], Reply::bulks(&["5", "4"])),
+        (&["LPOP", "l"], Reply::bulk("3")),
+        (&["LPOP", "l", "x"], Reply::error(NOT_POSITIVE)),
+        (&["RPOP", "l", "1.5"], Reply::error(NOT_POSITIVE)),
+        (&["RPOP", "l", "-2"], Reply::error(NOT_POSITIVE)),
+        (&["LPOP", "l", "10"], Reply::bulks(&["2", "1"])),
+        (&["EXISTS", "l"], int(0)),
+        (&["TYPE", "l"], none.clone()),
+        // Missing keys.
+        (&["LPOP", "missing"], nil.clone()),
+        (&["RPOP", "missing"], nil.clone()),
+        (&["LPOP", "missing", "0"], nil_array.clone()),
+        (&["RPOP", "missing", "2"], nil_array),
+        (&["LLEN", "missing"], int(0)),
+        (&["LRANGE", "missing", "0", "-1"], Reply::bulks(&[])),
+        (&["LINDEX", "missing", "0"], nil.clone()),
+        (&["LREM", "missing", "0", "a"], int(0)),
+        (
+            &["LSET", "missing", "0", "a"],
+            Reply::error("ERR no such key"),
+        ),
+        (&["LPUSHX", "missing", "a"], int(0)),
+        (&["RPUSHX", "missing", "a", "b"], int(0)),
+        (&["EXISTS", "missing"], int(0)),
+        // PUSHX on a list that exists.
+        (&["RPUSH", "x", "m"], int(1)),
+        (&["LPUSHX", "x", "b", "a"], int(3)),
+        (&["RPUSHX", "x", "y", "z"], int(5)),
+        (
+            &["LRANGE", "x", "0", "-1"],
+            Reply::bulks(&["a", "b", "m", "y", "z"]),
+        ),
+        // Indexes.
+        (&["LRANGE", "x", "-2", "100"], Reply::bulks(&["y", "z"])),
+        (&["LRANGE", "x", "3", "1"], Reply::bulks(&[])),
+        (&["LRANGE", "x", "-100", "0"], Reply::bulks(&["a"])),
+        (&["LRANGE", "x", "0", "1.5"], Reply::error(NOT_INTEGER)),
+        (&["LINDEX", "x", "-5"], Reply::bulk("a")),
+        (&["LINDEX", "x", "-6"], nil.clone()),
+        (&["LINDEX", "x", "5"], nil),
+        (&["LSET", "x", "-1", "Z"], Reply::ok()),
+        (
+            &["LSET", "x", "5", "Z"],
+            Reply::error("ERR index out of range"),
+        ),
+        (&["LSET", "x", "x", "Z"], Reply::error(NOT_INTEGER)),
+        (&["LREM", "x", "-1", "Z"], int(1)),
+        (&["LREM", "x", "1", "Z"], int(0)),
+        (
+            &["LRANGE", "x", "0", "-1"],
+            Reply::bulks(&["a", "b", "m", "y"]),
+        ),
+        (&["DEL", "x"], int(1)),
+        // Wrong types, checked after the arguments parse.
+        (&["SET", "s", "v"], Reply::ok()),
+        (&["LPUSH", "s", "a"], Reply::error(WRONGTYPE)),
+        (&["RPUSHX", "s", "a"], Reply::error(WRONGTYPE)),
+        (&["LPUSHX", "s", "a"], Reply::error(WRONGTYPE)),
+        (&["LPOP", "s"], Reply::error(WRONGTYPE)),
+        (&["LPOP", "s", "0"], Reply::error(WRONGTYPE)),
+        (&["RPOP", "s", "-1"], Reply::error(NOT_POSITIVE)),
+        (&["LLEN", "s"], Reply::error(WRONGTYPE)),
+        (&["LRANGE", "s", "0", "x"], Reply::error(NOT_INTEGER)),
+        (&["LREM", "s", "x", "v"], Reply::error(NOT_INTEGER)),
+        (&["LREM", "s", "0", "v"], Reply::error(WRONGTYPE)),
+        (&["TYPE", "s"], Reply::Status("string".to_string())),
+        (&["GET", "s"], Reply::bulk("v")),
+    ];
+    let server = Server::start();
+    let mut c = server.connect();
+    for (args, expected) in steps {
+        assert_eq!(c.cmd(args), expected, "{}", args.join(" "));
+    }
+}
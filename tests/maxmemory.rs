@@ -0,0 +1,166 @@
+//! maxmemory and the eviction policies. Victims are picked from samples,
+//! so the policies are checked by what most of the evictions hit.
+
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{Connection, Reply, Server};
+
+/// Room for roughly a hundred of the values below.
+const MAXMEMORY: &str = "100000";
+const OOM: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+fn value() -> String {
+    "x".repeat(1000)
+}
+
+fn start(policy: &str) -> Server {
+    Server::start_with(&["--maxmemory", MAXMEMORY, "--maxmemory-policy", policy])
+}
+
+fn info_field(c: &mut Connection, field: &str) -> u64 {
+    let info = match c.cmd(&["INFO"]) {
+        Reply::Bulk(Some(info)) => String::from_utf8(info).unwrap(),
+        other => panic!("INFO replied {}", other),
+    };
+    info.lines()
+        .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+        .unwrap_or_else(|| panic!("no {} in INFO", field))
+        .parse()
+        .unwrap()
+}
+
+/// How many of the keys `fill` wrote under `prefix` are left.
+fn survivors(c: &mut Connection, prefix: &str, count: usize) -> usize {
+    (0..count)
+        .filter(|i| c.cmd(&["EXISTS", &format!("{}:{}", prefix, i)]) == Reply::Integer(1))
+        .count()
+}
+
+/// Writes `count` keys named `prefix:<i>`, each of which must succeed.
+fn fill(c: &mut Connection, prefix: &str, count: usize, ttl: Option<&str>) {
+    for i in 0..count {
+        let key = format!("{}:{}", prefix, i);
+        let reply = match ttl {
+            Some(ttl) => c.cmd(&["SET", &key, &value(), "EX", ttl]),
+            None => c.cmd(&["SET", &key, &value()]),
+        };
+        assert_eq!(reply, Reply::ok(), "SET {}", key);
+    }
+}
+
+/// Evictions kept memory near the limit and removed keys.
+fn assert_evicted(c: &mut Connection, written: u64) {
+    let evicted = info_field(c, "evicted_keys");
+    assert!(evicted > 0, "nothing was evicted");
+    assert_eq!(dbsize(c) + evicted, written);
+    // One write may go over before the next command evicts.
+    assert!(info_field(c, "used_memory") < 100_000 + 2 * 1100);
+}
+
+fn dbsize(c: &mut Connection) -> u64 {
+    match c.cmd(&["DBSIZE"]) {
+        Reply::Integer(n) => n as u64,
+        other => panic!("DBSIZE replied {}", other),
+    }
+}
+
+#[test]
+fn noeviction_refuses_writes_once_full() {
+    let server = start("noeviction");
+    let mut c = server.connect();
+    let mut written = 0;
+    loop {
+        match c.cmd(&["SET", &format!("k:{}", written), &value()]) {
+            Reply::Status(_) => written += 1,
+            reply => {
+                assert_eq!(reply, Reply::error(OOM));
+                break;
+            }
+        }
+        assert!(written < 1000, "maxmemory was never enforced");
+    }
+    assert!(written > 50, "only {} keys fit", written);
+    assert_eq!(dbsize(&mut c), written);
+    assert_eq!(info_field(&mut c, "evicted_keys"), 0);
+    assert_eq!(c.cmd(&["RPUSH", "l", "a"]), Reply::error(OOM));
+    // Reads and deletes still work, and free room for new writes.
+    assert_eq!(c.cmd(&["GET", "k:0"]), Reply::bulk(&value()));
+    assert_eq!(c.cmd(&["DEL", "k:0", "k:1", "k:2"]), Reply::Integer(3));
+    assert_eq!(c.cmd(&["SET", "k:0", "v"]), Reply::ok());
+}
+
+#[test]
+fn allkeys_random_evicts() {
+    let server = start("allkeys-random");
+    let mut c = server.connect();
+    fill(&mut c, "k", 300, None);
+    assert_evicted(&mut c, 300);
+}
+
+#[test]
+fn allkeys_lru_evicts_the_least_recently_used() {
+    let server = start("allkeys-lru");
+    let mut c = server.connect();
+    fill(&mut c, "old", 80, None);
+    thread::sleep(Duration::from_millis(100));
+    fill(&mut c, "new", 40, None);
+    assert_evicted(&mut c, 120);
+    let new = survivors(&mut c, "new", 40);
+    assert!(new >= 36, "only {} of 40 new keys were kept", new);
+}
+
+#[test]
+fn allkeys_lfu_keeps_frequently_used_keys() {
+    let server = start("allkeys-lfu");
+    let mut c = server.connect();
+    fill(&mut c, "hot", 10, None);
+    for _ in 0..20 {
+        for i in 0..10 {
+            c.cmd(&["GET", &format!("hot:{}", i)]);
+        }
+    }
+    fill(&mut c, "cold", 200, None);
+    assert_evicted(&mut c, 210);
+    let hot = survivors(&mut c, "hot", 10);
+    assert!(hot >= 9, "only {} of 10 hot keys were kept", hot);
+}
+
+#[test]
+fn volatile_lru_only_evicts_keys_with_a_ttl() {
+    let server = start("volatile-lru");
+    let mut c = server.connect();
+    fill(&mut c, "kept", 40, None);
+    fill(&mut c, "volatile", 200, Some("1000"));
+    assert_evicted(&mut c, 240);
+    assert_eq!(survivors(&mut c, "kept", 40), 40);
+}
+
+#[test]
+fn volatile_ttl_evicts_the_soonest_to_expire() {
+    let server = start("volatile-ttl");
+    let mut c = server.connect();
+    fill(&mut c, "kept", 20, None);
+    fill(&mut c, "late", 20, Some("100000"));
+    fill(&mut c, "soon", 200, Some("1000"));
+    assert_evicted(&mut c, 240);
+    assert_eq!(survivors(&mut c, "kept", 20), 20);
+    let late = survivors(&mut c, "late", 20);
+    assert!(late >= 17, "only {} of 20 late keys were kept", late);
+}
+
+#[test]
+fn volatile_policies_refuse_writes_without_volatile_keys() {
+    for policy in ["volatile-lru", "volatile-ttl"] {
+        let server = start(policy);
+        let mut c = server.connect();
+        let mut written = 0;
+        while c.cmd(&["SET", &format!("k:{}", written), &value()]) == Reply::ok() {
+            written += 1;
+            assert!(written < 1000, "{}: maxmemory was never enforced", policy);
+        }
+        assert_eq!(c.cmd(&["SET", "k", "v"]), Reply::error(OOM), "{}", policy);
+        assert_eq!(dbsize(&mut c), written, "{}", policy);
+    }
+}
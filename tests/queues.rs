@@ -0,0 +1,20 @@
+//! Queue commands.
+
+mod common;
+
+use common::{Reply, Server};
+
+#[test]
+fn a_queue_emptied_by_qread_is_deleted() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["QADD", "q", "a", "b"]), Reply::ok());
+    assert_eq!(c.cmd(&["QREAD", "q"]), Reply::bulk("a"));
+    assert_eq!(c.cmd(&["EXISTS", "q"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["QLEN", "q"]), Reply::Integer(1));
+    assert_eq!(c.cmd(&["QREAD", "q"]), Reply::bulk("b"));
+    assert_eq!(c.cmd(&["EXISTS", "q"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["TYPE", "q"]), Reply::Status("none".to_string()));
+    assert_eq!(c.cmd(&["QLEN", "q"]), Reply::Integer(0));
+    assert_eq!(c.cmd(&["QREAD", "q"]), Reply::Bulk(None));
+}
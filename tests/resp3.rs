@@ -0,0 +1,108 @@
+//! Replies after a connection switches protocols with HELLO.
+
+mod common;
+
+use common::{Connection, Reply, Server};
+
+fn protocol(c: &mut Connection, type_name: &str) -> Reply {
+    c.cmd(&["DEBUG", "PROTOCOL", type_name])
+}
+
+#[test]
+fn hello_3_switches_to_resp3_replies() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["GET", "missing"]), Reply::Bulk(None));
+
+    match c.cmd(&["HELLO", "3"]) {
+        Reply::Map(entries) => {
+            assert!(entries.contains(&(Reply::bulk("proto"), Reply::Integer(3))));
+        }
+        other => panic!("HELLO 3 replied {}", other),
+    }
+    assert_eq!(c.cmd(&["HSET", "h", "f", "v", "g", "w"]), Reply::Integer(2));
+    match c.cmd(&["HGETALL", "h"]) {
+        Reply::Map(mut entries) => {
+            entries.sort_by_key(|(k, _)| k.to_string());
+            assert_eq!(
+                entries,
+                vec![
+                    (Reply::bulk("f"), Reply::bulk("v")),
+                    (Reply::bulk("g"), Reply::bulk("w")),
+                ]
+            );
+        }
+        other => panic!("HGETALL replied {}", other),
+    }
+    assert_eq!(c.cmd(&["GET", "missing"]), Reply::Null);
+    c.cmd(&["SET", "s", "x"]);
+    assert_eq!(
+        c.cmd(&["MGET", "s", "missing"]),
+        Reply::Array(Some(vec![Reply::bulk("x"), Reply::Null]))
+    );
+    assert_eq!(
+        c.cmd(&["GET", "h"]),
+        Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+    assert_eq!(c.cmd(&["KEYS", "s"]), Reply::Set(vec![Reply::bulk("s")]));
+
+    match c.cmd(&["HELLO", "2"]) {
+        Reply::Array(Some(_)) => {}
+        other => panic!("HELLO 2 replied {}", other),
+    }
+    assert_eq!(c.cmd(&["GET", "missing"]), Reply::Bulk(None));
+}
+
+#[test]
+fn debug_protocol_replies_with_each_type() {
+    let server = Server::start();
+    let mut c = server.connect();
+
+    assert_eq!(protocol(&mut c, "double"), Reply::bulk("3.141"));
+    assert_eq!(protocol(&mut c, "true"), Reply::Integer(1));
+    assert_eq!(
+        protocol(&mut c, "push"),
+        Reply::error("ERR RESP2 is not supported by this command")
+    );
+
+    c.cmd(&["HELLO", "3"]);
+    assert_eq!(
+        protocol(&mut c, "double"),
+        Reply::Double("3.141".to_string())
+    );
+    assert_eq!(
+        protocol(&mut c, "bignum"),
+        Reply::BigNumber("1234567999999999999999999999999999999".to_string())
+    );
+    assert_eq!(protocol(&mut c, "true"), Reply::Boolean(true));
+    assert_eq!(protocol(&mut c, "false"), Reply::Boolean(false));
+    assert_eq!(protocol(&mut c, "null"), Reply::Null);
+    assert_eq!(
+        protocol(&mut c, "set"),
+        Reply::Set(vec![
+            Reply::Integer(0),
+            Reply::Integer(1),
+            Reply::Integer(2)
+        ])
+    );
+    assert_eq!(
+        protocol(&mut c, "map"),
+        Reply::Map(vec![
+            (Reply::Integer(0), Reply::Boolean(false)),
+            (Reply::Integer(1), Reply::Boolean(true)),
+            (Reply::Integer(2), Reply::Boolean(false)),
+        ])
+    );
+    assert_eq!(
+        protocol(&mut c, "verbatim"),
+        Reply::Verbatim("txt".to_string(), b"This is a verbatim\nstring".to_vec())
+    );
+    assert_eq!(
+        protocol(&mut c, "push"),
+        Reply::Push(vec![Reply::bulk("server-cpu-usage"), Reply::Integer(42)])
+    );
+    assert_eq!(
+        c.read_reply(),
+        Reply::bulk("Some real reply following the push reply")
+    );
+}
@@ -0,0 +1,144 @@
+//! SCAN cursors.
+
+mod common;
+
+use std::collections::HashSet;
+
+use common::{Connection, Reply, Server};
+
+/// One SCAN call: the next cursor and the keys returned.
+fn scan(c: &mut Connection, cursor: &str, options: &[&str]) -> (String, Vec<String>) {
+    let mut args = vec!["SCAN", cursor];
+    args.extend(options);
+    match c.cmd(&args) {
+        Reply::Array(Some(reply)) => match &reply[..] {
+            [Reply::Bulk(Some(next)), Reply::Array(Some(keys))] => (
+                String::from_utf8(next.clone()).unwrap(),
+                keys.iter()
+                    .map(|k| match k {
+                        Reply::Bulk(Some(k)) => String::from_utf8(k.clone()).unwrap(),
+                        other => panic!("SCAN returned key {}", other),
+                    })
+                    .collect(),
+            ),
+            _ => panic!("SCAN replied {:?}", reply),
+        },
+        other => panic!("SCAN replied {}", other),
+    }
+}
+
+/// Scans to the end, calling `between` after every call.
+fn scan_all(
+    c: &mut Connection,
+    options: &[&str],
+    mut between: impl FnMut(usize),
+) -> HashSet<String> {
+    let mut cursor = "0".to_string();
+    let mut seen = HashSet::new();
+    for call in 0.. {
+        let (next, keys) = scan(c, &cursor, options);
+        seen.extend(keys);
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+        between(call);
+        assert!(call < 100_000, "SCAN never finished");
+    }
+    seen
+}
+
+fn names(prefix: &str, count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("{}:{}", prefix, i)).collect()
+}
+
+#[test]
+fn scan_returns_every_key() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(scan(&mut c, "0", &[]), ("0".to_string(), vec![]));
+    let keys = names("key", 1000);
+    for key in &keys {
+        c.cmd(&["SET", key, "v"]);
+    }
+    for count in ["1", "10", "100", "5000"] {
+        let seen = scan_all(&mut c, &["COUNT", count], |_| {});
+        assert_eq!(seen, keys.iter().cloned().collect(), "COUNT {}", count);
+    }
+}
+
+#[test]
+fn keys_present_for_the_whole_scan_are_returned_despite_inserts() {
+    let server = Server::start();
+    let mut c = server.connect();
+    let mut writer = server.connect();
+    let stable = names("stable", 500);
+    for key in &stable {
+        c.cmd(&["SET", key, "v"]);
+    }
+    let doomed = names("doomed", 200);
+    for key in &doomed {
+        c.cmd(&["SET", key, "v"]);
+    }
+
+    // Between calls, grow the keyspace many times over, so the shards'
+    // tables resize mid-scan, and delete keys that were there at the start.
+    let mut added = 0;
+    let seen = scan_all(&mut c, &["COUNT", "20"], |call| {
+        for key in names(&format!("added:{}", call), 100) {
+            writer.cmd(&["SET", &key, "v"]);
+            added += 1;
+        }
+        if let Some(key) = doomed.get(call) {
+            writer.cmd(&["DEL", key]);
+        }
+    });
+    assert!(added >= 5000, "the scan finished before the keyspace grew");
+    let missing: Vec<_> = stable.iter().filter(|k| !seen.contains(*k)).collect();
+    assert!(missing.is_empty(), "SCAN missed {:?}", missing);
+}
+
+#[test]
+fn match_and_type_filter_the_keys() {
+    let server = Server::start();
+    let mut c = server.connect();
+    for key in names("str", 50) {
+        c.cmd(&["SET", &key, "v"]);
+    }
+    for key in names("list", 50) {
+        c.cmd(&["RPUSH", &key, "v"]);
+    }
+    c.cmd(&["HSET", "hash", "f", "v"]);
+
+    let seen = scan_all(&mut c, &["MATCH", "str:1*"], |_| {});
+    let mut expected: HashSet<String> = (0..50)
+        .map(|i| format!("str:{}", i))
+        .filter(|k| k.starts_with("str:1"))
+        .collect();
+    assert_eq!(seen, expected);
+
+    let seen = scan_all(&mut c, &["TYPE", "list", "COUNT", "7"], |_| {});
+    assert_eq!(seen, names("list", 50).into_iter().collect());
+    let seen = scan_all(&mut c, &["TYPE", "HASH", "MATCH", "h*"], |_| {});
+    expected = ["hash".to_string()].into_iter().collect();
+    assert_eq!(seen, expected);
+}
+
+#[test]
+fn bad_arguments() {
+    let server = Server::start();
+    let mut c = server.connect();
+    assert_eq!(c.cmd(&["SCAN", "x"]), Reply::error("ERR invalid cursor"));
+    assert_eq!(
+        c.cmd(&["SCAN", "0", "COUNT", "0"]),
+        Reply::error("ERR syntax error")
+    );
+    assert_eq!(
+        c.cmd(&["SCAN", "0", "COUNT", "many"]),
+        Reply::error("ERR value is not an integer or out of range")
+    );
+    assert_eq!(
+        c.cmd(&["SCAN", "0", "MATCH"]),
+        Reply::error("ERR syntax error")
+    );
+}